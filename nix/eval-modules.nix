@@ -0,0 +1,67 @@
+{
+  flake,
+  system ? builtins.currentSystem,
+  # systems to evaluate packages for
+  systems ? [system],
+}: let
+  source = builtins.getFlake flake;
+
+  # evaluate against the nixpkgs the flake is pinned to,
+  # an unpinned fallback is not possible in pure evaluation mode
+  nixpkgs =
+    source.inputs.nixpkgs
+    or (throw "the flake has no nixpkgs input to evaluate its modules with");
+  pkgs = import nixpkgs {inherit system;};
+  inherit (pkgs) lib;
+  fclib = import ./lib.nix {inherit lib;};
+
+  extra_packages =
+    builtins.toFile "fc-search-packages.json"
+    (builtins.toJSON (fclib.mergeSystems
+      (map (s: fclib.readPackages s (source.packages.${s} or {})) systems)));
+
+  # stub configuration that lets a module be evaluated without a real machine
+  stub = {
+    networking.hostName = "options";
+    fileSystems."/".device = "/dev/null";
+    boot.loader.grub.enable = false;
+    system.stateVersion = lib.trivial.release;
+  };
+
+  # flakes predating `nixosModules` only export a single `nixosModule`
+  modules =
+    source.nixosModules
+    or (lib.optionalAttrs (source ? nixosModule) {default = source.nixosModule;});
+
+  # every module is evaluated on its own, so options of one module
+  # don't depend on whatever the other modules of the flake configure
+  evalModule = _: module: let
+    module_eval = import "${nixpkgs}/nixos/lib/eval-config.nix" {
+      inherit system;
+      modules = [module stub];
+    };
+    doc = pkgs.nixosOptionsDoc {
+      inherit (module_eval) options;
+      warningsAreErrors = false;
+      transformOptions = fclib.exportOption module_eval.options;
+    };
+  in "${doc.optionsJSON}/share/doc/nixos/options.json";
+
+  extra_options = lib.mapAttrsToList evalModule modules;
+  no_options = builtins.toFile "no-options.json" "{}";
+in
+  pkgs.runCommand "fc-search-options" {
+    buildInputs = [pkgs.jq];
+  } ''
+    mkdir -p $out
+    # merge the options of all modules and only keep the ones declared by the flake itself
+    jq --slurp --arg source ${source} \
+      'add | with_entries(select(.value.declarations | any(startswith($source))))' \
+      ${no_options} ${lib.concatStringsSep " " extra_options} \
+      > $out/options.json
+    cat ${extra_packages} | jq > $out/packages.json
+    echo ${nixpkgs} >> $out/nixpkgs
+    echo ${nixpkgs.rev} >> $out/nixpkgs-rev
+    echo ${nixpkgs.narHash} >> $out/nixpkgs-nar-hash
+    echo ${source} >> $out/source
+  ''
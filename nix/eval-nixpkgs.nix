@@ -0,0 +1,45 @@
+{
+  flake,
+  system ? builtins.currentSystem,
+  # systems to evaluate packages for
+  systems ? [system],
+}: let
+  nixpkgs = builtins.getFlake flake;
+  pkgsFor = system: import nixpkgs {inherit system;};
+  pkgs = pkgsFor system;
+  inherit (pkgs) lib;
+  fclib = import ./lib.nix {inherit lib;};
+
+  nixpkgs_packages =
+    builtins.toFile "fc-search-packages.json"
+    (builtins.toJSON (fclib.mergeSystems (map (s: fclib.readPackages s (pkgsFor s)) systems)));
+
+  nixpkgs_options = let
+    nixos_eval = import "${nixpkgs}/nixos/lib/eval-config.nix" {
+      inherit system;
+      modules = [
+        {
+          networking.hostName = "options";
+        }
+      ];
+    };
+  in
+    (pkgs.nixosOptionsDoc {
+      inherit (nixos_eval) options;
+      warningsAreErrors = false;
+      transformOptions = fclib.exportOption nixos_eval.options;
+    })
+    .optionsJSON;
+in
+  pkgs.runCommand "fc-search-options" {
+    buildInputs = [pkgs.jq];
+    options = nixpkgs_options;
+  } ''
+    mkdir -p $out
+    cat $options/share/doc/nixos/options.json | jq > $out/options.json
+    cat ${nixpkgs_packages} | jq > $out/packages.json
+    echo ${nixpkgs} >> $out/nixpkgs
+    echo ${nixpkgs.rev} >> $out/nixpkgs-rev
+    echo ${nixpkgs.narHash} >> $out/nixpkgs-nar-hash
+    echo ${nixpkgs} >> $out/source
+  ''
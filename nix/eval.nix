@@ -1,5 +1,12 @@
-{flake}: let
-  system = builtins.currentSystem;
+{
+  flake,
+  system ? builtins.currentSystem,
+  # systems to evaluate packages for
+  systems ? [system],
+  # locked reference to the nixpkgs pinned in versions.json, required for pure evaluation
+  nixpkgsRef ? null,
+  homeManager ? false,
+}: let
   fc-nixos = builtins.getFlake flake;
 
   versions_json =
@@ -8,53 +15,46 @@
     else "${fc-nixos}/versions.json";
   versions = builtins.fromJSON (builtins.readFile versions_json);
   nixpkgs =
-    builtins.getFlake "github:nixos/nixpkgs?rev=${versions.nixpkgs.rev}";
+    if nixpkgsRef != null
+    then builtins.getFlake nixpkgsRef
+    else builtins.getFlake "github:nixos/nixpkgs?rev=${versions.nixpkgs.rev}";
 
   nixpkgsConfig = import "${fc-nixos}/nixpkgs-config.nix";
-  pkgs = import nixpkgs {
-    inherit system;
-    overlays = [(import "${fc-nixos}/pkgs/overlay.nix")];
-    config = {inherit (nixpkgsConfig) permittedInsecurePackages;};
-  };
+  pkgsFor = system:
+    import nixpkgs {
+      inherit system;
+      overlays = [(import "${fc-nixos}/pkgs/overlay.nix")];
+      config = {inherit (nixpkgsConfig) permittedInsecurePackages;};
+    };
+  pkgs = pkgsFor system;
+  fclib = import ./lib.nix {inherit (pkgs) lib;};
 
-  fc_packages = let
-    inherit (pkgs) lib;
-    isValid = d: let
-      r = builtins.tryEval (lib.isDerivation d
-        && !(lib.attrByPath ["meta" "broken"] false d)
-        && builtins.seq d.name true
-        && d ? outputs);
-    in
-      r.success && r.value;
-    validPkgs = lib.filterAttrs (_: v: isValid v);
+  fc_packages =
+    builtins.toFile "fc-search-packages.json"
+    (builtins.toJSON (fclib.mergeSystems (map (s: fclib.readPackages s (pkgsFor s)) systems)));
 
-    readPackages = system: drvs:
-      lib.mapAttrs (attribute_name: drv: ({
-          entry_type = "package";
-          attribute_name = attribute_name;
-          system = system;
-          name = drv.name;
-          # TODO consider using `builtins.parseDrvName`
-          version = drv.version or "";
-          outputs = drv.outputs;
-          # paths = builtins.listToAttrs ( map (output: {name = output; value = drv.${output};}) drv.outputs );
-          default_output = drv.outputName;
-        }
-        // lib.optionalAttrs (drv ? meta.homepage) {
-          inherit (drv.meta) homepage;
-        }
-        // lib.optionalAttrs (drv ? meta.description) {
-          inherit (drv.meta) description;
-        }
-        // lib.optionalAttrs (drv ? meta.longDescription) {
-          inherit (drv.meta) longDescription;
-        }
-        // lib.optionalAttrs (drv ? meta.license) {
-          inherit (drv.meta) license;
-        })) (validPkgs drvs);
+  # options additionally record the roles whose modules set them
+  exportOption = options: opt: let
+    option = pkgs.lib.attrByPath opt.loc {} options;
+    # files of the definitions, options that fail to evaluate them have none
+    files = let
+      r = builtins.tryEval (builtins.deepSeq (option.files or []) (option.files or []));
+    in
+      if r.success
+      then map toString r.value
+      else [];
+    # `nixos/roles/lamp.nix` and `nixos/roles/webgateway/default.nix` belong to the roles lamp and webgateway
+    roleName = file:
+      pkgs.lib.removeSuffix ".nix"
+      (builtins.head (pkgs.lib.splitString "/" (pkgs.lib.last (pkgs.lib.splitString "/nixos/roles/" file))));
+    roles =
+      builtins.filter (r: r != "default")
+      (map roleName (builtins.filter (pkgs.lib.hasInfix "/nixos/roles/") files));
   in
-    builtins.toFile "fc-search-packages.json"
-    (builtins.toJSON (readPackages system pkgs));
+    fclib.exportOption options opt
+    // {
+      setByRoles = pkgs.lib.unique roles;
+    };
 
   fc_options = let
     testlib = import "${fc-nixos}/tests/testlib.nix" {inherit (pkgs) lib;};
@@ -87,8 +87,35 @@
     (pkgs.nixosOptionsDoc {
       inherit (fc_eval) options;
       warningsAreErrors = false;
+      transformOptions = exportOption fc_eval.options;
     })
     .optionsJSON;
+
+  hm_options = let
+    # home-manager release matching the platform's nixpkgs
+    home-manager =
+      builtins.getFlake
+      "github:nix-community/home-manager/release-${pkgs.lib.trivial.release}";
+    hm_eval = home-manager.lib.homeManagerConfiguration {
+      inherit pkgs;
+      modules = [
+        {
+          home.username = "options";
+          home.homeDirectory = "/home/options";
+          home.stateVersion = pkgs.lib.trivial.release;
+        }
+      ];
+    };
+  in {
+    source = home-manager;
+    options =
+      (pkgs.nixosOptionsDoc {
+        inherit (hm_eval) options;
+        warningsAreErrors = false;
+        transformOptions = exportOption hm_eval.options;
+      })
+      .optionsJSON;
+  };
 in
   pkgs.runCommand "fc-search-options" {
     buildInputs = [pkgs.jq];
@@ -98,5 +125,15 @@ in
     cat $options/share/doc/nixos/options.json | jq > $out/options.json
     cat ${fc_packages} | jq > $out/packages.json
     echo ${nixpkgs} >> $out/nixpkgs
-    echo ${fc-nixos} >> $out/fc-nixos
+    echo ${nixpkgs.rev} >> $out/nixpkgs-rev
+    echo ${nixpkgs.narHash} >> $out/nixpkgs-nar-hash
+    echo ${fc-nixos} >> $out/source
+  ${pkgs.lib.optionalString homeManager ''
+      # only keep the options declared by home-manager itself
+      cat ${hm_options.options}/share/doc/nixos/options.json \
+        | jq --arg source ${hm_options.source} 'with_entries(select(.value.declarations | any(startswith($source))))' \
+        > $out/home-manager-options.json
+      echo ${hm_options.source} >> $out/home-manager
+      echo ${hm_options.source.rev} >> $out/home-manager-rev
+    ''}
   ''
@@ -0,0 +1,77 @@
+# helpers shared by the eval files
+{lib}: rec {
+  isValid = d: let
+    r = builtins.tryEval (lib.isDerivation d
+      && !(lib.attrByPath ["meta" "broken"] false d)
+      && builtins.seq d.name true
+      && d ? outputs);
+  in
+    r.success && r.value;
+  validPkgs = lib.filterAttrs (_: v: isValid v);
+
+  readPackages = system: drvs:
+    lib.mapAttrs (attribute_name: drv: ({
+        entry_type = "package";
+        attribute_name = attribute_name;
+        system = system;
+        name = drv.name;
+        # TODO consider using `builtins.parseDrvName`
+        version = drv.version or "";
+        outputs = drv.outputs;
+        default_output = drv.outputName;
+      }
+      // lib.optionalAttrs (drv ? meta.homepage) {
+        inherit (drv.meta) homepage;
+      }
+      // lib.optionalAttrs (drv ? meta.description) {
+        inherit (drv.meta) description;
+      }
+      // lib.optionalAttrs (drv ? meta.longDescription) {
+        inherit (drv.meta) longDescription;
+      }
+      // lib.optionalAttrs (drv ? meta.license) {
+        inherit (drv.meta) license;
+      }
+      // lib.optionalAttrs (drv ? meta.maintainers) {
+        maintainers =
+          map (m: {
+            name = m.name or null;
+            github = m.github or null;
+          })
+          (builtins.filter builtins.isAttrs (lib.toList drv.meta.maintainers));
+      }
+      // lib.optionalAttrs (drv ? meta.knownVulnerabilities) {
+        inherit (drv.meta) knownVulnerabilities;
+      })) (validPkgs drvs);
+
+  # packages of every system, the metadata of the first system that has a package wins
+  mergeSystems = perSystem:
+    lib.zipAttrsWith (_: packages:
+      builtins.head packages // {systems = map (p: p.system) packages;})
+    perSystem;
+
+  # related packages are attribute paths as a string or list, or an attrset with
+  # a `path` or only a `name`
+  relatedPackageName = p:
+    if builtins.isString p
+    then p
+    else if builtins.isList p
+    then lib.concatStringsSep "." p
+    else lib.concatStringsSep "." (lib.toList (p.path or [p.name]));
+
+  # the docs only contain the files of declarations and related packages rendered as markdown,
+  # so add the line numbers and keep the attribute paths of related packages
+  exportOption = options: opt: let
+    positions =
+      builtins.filter (p: lib.hasPrefix "/" p.file)
+      ((lib.attrByPath opt.loc {} options).declarationPositions or []);
+  in
+    opt
+    // {
+      declarations =
+        if positions != []
+        then map (p: "${p.file}:${toString p.line}") positions
+        else opt.declarations;
+      relatedPackageNames = map relatedPackageName (opt.relatedPackages or []);
+    };
+}
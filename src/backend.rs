@@ -1,275 +1,2197 @@
 use anyhow::Context;
+use arc_swap::ArcSwap;
 use askama::Template;
 use axum::{
-    extract::State,
-    http::{header, HeaderMap, StatusCode, Uri},
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode, Uri},
+    middleware::{self, Next},
     response::{IntoResponse, Redirect, Response},
     routing::get,
     Router,
 };
 use fc_search::{
-    get_fcio_flake_uris, nix::NixPackage, search::ChannelSearcher, Flake, NaiveNixosOption, NixHtml,
+    changelog::ChangelogEntry,
+    changes::{option_diff, ChannelUpdate, DiffTag, FieldDiff, UpgradeReport},
+    channel::{Channel, Environment, Release},
+    config::{Config, RetireConfig, StorageBackend, WebhookEvent},
+    embeddings,
+    error::ErrorClass,
+    get_extra_flakes, get_upstream_flakes,
+    history::{HistoryEntry, OptionEvent},
+    hydra::{self, get_fcio_flake_uris, BuildStatus},
+    nix::{NixPackage, NixpkgsInput},
+    roles::Role,
+    search::{self, ChannelSearcher, GENERATION_FILE},
+    state::{write_generations, Generations, StateLock, GENERATIONS_FILE},
+    status::{format_ago, ChannelInfo, ChannelStatus, StatusEntry},
+    storage::{self, ObjectStorage},
+    tree::option_tree,
+    upstream::{fetch_upstream_names, UpstreamNames},
+    Flake, FlakeKind, FlakeRev, NaiveNixosOption, NixHtml, OptionSource, PREVIEW_LENGTH,
 };
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server;
 use itertools::Itertools;
+use notify::{RecursiveMode, Watcher};
 use rust_embed::RustEmbed;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    os::unix::fs::FileTypeExt,
     path::{Path, PathBuf},
-    sync::{Arc, RwLock},
-    time::Duration,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock, RwLock,
+    },
+    time::{Duration, SystemTime},
 };
-use tokio::time::interval;
-use tracing::{debug, error, info};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Semaphore;
+use tokio::task::{AbortHandle, JoinSet};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tower::Service;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::commands::export::{Corpus, EntryEncoder, Format};
+use crate::forwarded::ClientIp;
+use crate::webhooks::{self, WebhookPayload};
+use tracing::{debug, error, info, warn};
 
 #[derive(Clone)]
 struct AppState {
-    // Arc to prevent clones for every request, just need read access in the search handler
-    channels: Arc<RwLock<HashMap<String, ChannelSearcher>>>,
+    // immutable map of channels that is swapped out as a whole on updates,
+    // so the search handlers never wait for a lock
+    channels: Arc<ArcSwap<HashMap<Channel, Arc<ChannelSearcher>>>>,
+    status: Arc<RwLock<HashMap<Channel, ChannelStatus>>>,
     state_dir: PathBuf,
+    // replaced as a whole when the config file is reloaded
+    config: Arc<ArcSwap<Config>>,
+    log: Option<LogReloadHandle>,
+    /// set while the channels on disk are opened after the start
+    loading: Arc<AtomicBool>,
+    /// held while a channel is updated, see [`AppState::update_lock`]
+    updating: Arc<Mutex<HashMap<Channel, Arc<tokio::sync::Mutex<()>>>>>,
+    /// upstream names of the nixos releases of the channels, see [`refresh_upstream_names`]
+    upstream: Arc<ArcSwap<HashMap<Release, Arc<UpstreamNames>>>>,
+}
+
+/// results per page unless chosen otherwise
+const DEFAULT_N_ITEMS: u8 = 15;
+
+/// results per page that can be chosen on the search pages
+const N_ITEMS_CHOICES: &[u8] = &[15, 30, 50, 100];
+
+/// larger requests are limited to this many results per page
+const MAX_N_ITEMS: u8 = 100;
+
+/// cookie remembering the chosen results per page
+const N_ITEMS_COOKIE: &str = "n_items";
+
+const fn default_page() -> u8 {
+    1
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchForm {
+    #[serde(default)]
+    q: String,
+    channel: Option<Channel>,
+    n_items: Option<u8>,
+    #[serde(default = "default_page")]
+    page: u8,
+    /// only search options declared there, all options if empty
+    #[serde(default, deserialize_with = "empty_as_none")]
+    source: Option<OptionSource>,
+    /// append the next pages to the results instead of paginating
+    #[serde(default)]
+    scroll: bool,
+    /// the [`ChannelSearcher::generation`] the previous page was searched in
+    #[serde(default, deserialize_with = "empty_as_none")]
+    generation: Option<String>,
+}
+
+/// the empty choice of a select is sent as an empty string
+fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::de::DeserializeOwned,
+{
+    let value = String::deserialize(deserializer)?;
+    if value.is_empty() {
+        return Ok(None);
+    }
+    T::deserialize(serde::de::value::StrDeserializer::new(&value)).map(Some)
+}
+
+/// longest search query that is accepted
+const MAX_QUERY_LENGTH: usize = 200;
+
+impl SearchForm {
+    /// the reason the form can not be searched, if any
+    fn validate(&self) -> Result<(), String> {
+        if self.q.chars().count() > MAX_QUERY_LENGTH {
+            return Err(format!(
+                "the query is longer than {MAX_QUERY_LENGTH} characters"
+            ));
+        }
+        if self.page == 0 {
+            return Err("pages are counted from 1".to_string());
+        }
+        if let Some(n_items) = self.n_items {
+            if !(1..=MAX_N_ITEMS).contains(&n_items) {
+                return Err(format!(
+                    "the number of results per page must be between 1 and {MAX_N_ITEMS}"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// the requested channel, if it is not indexed (anymore)
+    fn unknown_channel(&self, state: &AppState) -> Option<&Channel> {
+        // channels that are not opened yet are not known while loading
+        if state.loading.load(Ordering::Relaxed) {
+            return None;
+        }
+        self.channel
+            .as_ref()
+            .filter(|channel| state.channel(channel).is_none())
+    }
+
+    /// the page to search in the channel, the first one if the index was swapped
+    /// since the previous page was searched, as page N would not continue the previous one
+    fn page_in(&self, searcher: &ChannelSearcher) -> (u8, IndexGeneration) {
+        let id = searcher.generation();
+        let reset = self.page > 1 && self.generation.as_ref().is_some_and(|g| *g != id);
+        let page = if reset { 1 } else { self.page };
+        (page, IndexGeneration { id, reset })
+    }
+
+    /// the results per page from the form, or else the ones chosen before
+    fn n_items(&self, headers: &HeaderMap) -> u8 {
+        self.n_items
+            .or_else(|| cookie(headers, N_ITEMS_COOKIE)?.parse().ok())
+            .unwrap_or(DEFAULT_N_ITEMS)
+            .clamp(1, MAX_N_ITEMS)
+    }
+}
+
+/// a [`SearchForm`] that passed [`SearchForm::validate`],
+/// invalid forms are rejected with a 400 response, unknown channels with a 404
+struct ValidSearchForm(SearchForm);
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for ValidSearchForm {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let form = axum::extract::Query::<SearchForm>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| bad_request(&parts.headers, e.body_text()))?;
+        form.validate()
+            .map_err(|message| bad_request(&parts.headers, message))?;
+        if let Some(channel) = form.unknown_channel(state) {
+            return Err(unknown_channel(
+                &parts.headers,
+                // nested routers only see the path below the base path
+                &prefixed(parts.uri.path()),
+                &form,
+                channel,
+                state,
+            ));
+        }
+        Ok(Self(form.0))
+    }
+}
+
+/// a 404 response listing the channels that can be searched instead of `channel`,
+/// e.g. for bookmarks of retired releases
+fn unknown_channel(
+    headers: &HeaderMap,
+    path: &str,
+    form: &SearchForm,
+    channel: &Channel,
+    state: &AppState,
+) -> Response {
+    let branches = state.active_branches();
+    let fallback = closest_production_channel(channel, &branches);
+    if wants_json(headers) {
+        let body = axum::Json(serde_json::json!({
+            "error": format!("unknown channel {channel}"),
+            "channels": branches.iter().map(|b| &b.name).collect_vec(),
+            "fallback": fallback,
+        }));
+        return (StatusCode::NOT_FOUND, body).into_response();
+    }
+
+    let search_url = |name: &Channel| {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("q", &form.q)
+            .append_pair("channel", name)
+            .finish();
+        format!("{path}?{query}")
+    };
+    (
+        StatusCode::NOT_FOUND,
+        HtmlTemplate(UnknownChannelTemplate {
+            channel: channel.to_string(),
+            fallback: fallback.map(|name| ChannelLink {
+                url: search_url(&name),
+                label: name.to_string(),
+            }),
+            channels: branches
+                .into_iter()
+                .map(|branch| ChannelLink {
+                    url: search_url(&branch.name),
+                    label: branch.label,
+                })
+                .collect(),
+        }),
+    )
+        .into_response()
+}
+
+/// the production channel sharing the longest prefix with `channel`, the newest one on ties,
+/// so `fc-23.11-dev` falls back to `fc-23.11-production`
+fn closest_production_channel(channel: &Channel, branches: &[ChannelChoice]) -> Option<Channel> {
+    let common_prefix = |name: &str| {
+        name.chars()
+            .zip(channel.chars())
+            .take_while(|(a, b)| a == b)
+            .count()
+    };
+    branches
+        .iter()
+        .filter(|branch| branch.name.is_production())
+        .max_by(|a, b| {
+            common_prefix(&a.name)
+                .cmp(&common_prefix(&b.name))
+                .then_with(|| a.name.cmp(&b.name))
+        })
+        .map(|branch| branch.name.clone())
+}
+
+/// a 400 response explaining what is wrong with the request, as json if requested
+fn bad_request(headers: &HeaderMap, message: String) -> Response {
+    if wants_json(headers) {
+        let body = axum::Json(serde_json::json!({ "error": message }));
+        return (StatusCode::BAD_REQUEST, body).into_response();
+    }
+    (
+        StatusCode::BAD_REQUEST,
+        HtmlTemplate(ErrorTemplate { message }),
+    )
+        .into_response()
+}
+
+/// whether the client asked for json instead of html
+fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("application/json"))
+}
+
+/// value of the cookie `name` sent with the request
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// entry of the channel selection of the search pages
+struct ChannelChoice {
+    name: Channel,
+    label: String,
+}
+
+impl AppState {
+    // TODO cache this between requests, only changes on rebuilds
+    fn active_branches(&self) -> Vec<ChannelChoice> {
+        self.channel_choices(|searcher| searcher.active())
+    }
+
+    /// the lock serializing the updates of a channel, so two updates never write
+    /// the files of the same branch at the same time
+    fn update_lock(&self, channel: &Channel) -> Arc<tokio::sync::Mutex<()>> {
+        self.updating
+            .lock()
+            .unwrap()
+            .entry(channel.clone())
+            .or_default()
+            .clone()
+    }
+
+    fn home_manager_branches(&self) -> Vec<ChannelChoice> {
+        self.channel_choices(|searcher| searcher.has_home_manager())
+    }
+
+    /// retired channels are still served during their grace period, they are marked as deprecated
+    fn channel_choices(&self, filter: impl Fn(&ChannelSearcher) -> bool) -> Vec<ChannelChoice> {
+        let statuses = self.status.read().unwrap();
+        self.channels
+            .load()
+            .iter()
+            .filter(|(_, searcher)| filter(searcher))
+            .sorted_by(|a, b| b.0.cmp(a.0))
+            .map(|(name, searcher)| {
+                let retired = statuses
+                    .get(name)
+                    .is_some_and(|s| s.retired_since.is_some());
+                ChannelChoice {
+                    name: name.clone(),
+                    label: if retired {
+                        format!("{} (deprecated)", searcher.flake.label())
+                    } else {
+                        searcher.flake.label()
+                    },
+                }
+            })
+            .collect_vec()
+    }
+
+    fn status_entries(&self) -> Vec<StatusEntry> {
+        let statuses = self.status.read().unwrap();
+        let channels = self.channels.load();
+        channels
+            .iter()
+            .map(|(branch, searcher)| StatusEntry {
+                flake: searcher.flake.clone(),
+                active: searcher.active(),
+                retired: statuses
+                    .get(branch)
+                    .is_some_and(|s| s.retired_since.is_some()),
+                progress: searcher.progress.current(),
+                warnings: searcher.warnings.clone(),
+                shared_with: channels
+                    .iter()
+                    .filter(|(other, s)| *other != branch && s.shares_index_with(searcher))
+                    .map(|(other, _)| other.to_string())
+                    .sorted()
+                    .collect_vec(),
+                status: statuses.get(branch).cloned().unwrap_or_default(),
+            })
+            .sorted_by(|a, b| b.flake.channel().cmp(a.flake.channel()))
+            .collect_vec()
+    }
+
+    /// the upstream names to cross-reference the results of a channel with,
+    /// upstream channels are not cross-referenced with themselves
+    fn upstream_names(
+        &self,
+        channel: &Channel,
+        searcher: &ChannelSearcher,
+    ) -> Option<Arc<UpstreamNames>> {
+        if searcher.flake.kind == FlakeKind::Nixpkgs {
+            return None;
+        }
+        self.upstream.load().get(&channel.release()?).cloned()
+    }
+
+    fn channel(&self, branch: &str) -> Option<Arc<ChannelSearcher>> {
+        self.channels.load().get(branch).cloned()
+    }
+
+    /// add or replace a channel, only the map of pointers is copied
+    fn insert_channel(&self, channel: &Channel, searcher: ChannelSearcher) {
+        let searcher = Arc::new(searcher);
+        self.channels.rcu(|channels| {
+            let mut channels = HashMap::clone(channels);
+            channels.insert(channel.clone(), searcher.clone());
+            channels
+        });
+    }
+
+    fn remove_channel(&self, channel: &Channel) -> Option<Arc<ChannelSearcher>> {
+        let previous = self.channels.rcu(|channels| {
+            let mut channels = HashMap::clone(channels);
+            channels.remove(channel);
+            channels
+        });
+        previous.get(channel).cloned()
+    }
+
+    /// a state without channels, they are loaded in the background so the server
+    /// answers right away, see [`AppState::load_channels`]
+    fn new(state_dir: &Path, config: Arc<ArcSwap<Config>>) -> anyhow::Result<Self> {
+        debug!("initializing app state");
+
+        if !state_dir.exists() {
+            std::fs::create_dir_all(state_dir)?;
+        }
+
+        Ok(Self {
+            channels: Default::default(),
+            status: Default::default(),
+            state_dir: state_dir.to_path_buf(),
+            config,
+            log: None,
+            loading: Arc::new(AtomicBool::new(true)),
+            updating: Arc::default(),
+            upstream: Default::default(),
+        })
+    }
+
+    /// open the cached indices of `branches`, each channel is served as soon as it is open
+    fn load_channels(&self, branches: Vec<(Channel, Flake)>) {
+        for (channel, flake) in branches
+            .into_iter()
+            .sorted_by_key(|(channel, _)| update_priority(channel))
+        {
+            let searcher = ChannelSearcher::in_statedir(&self.state_dir, &flake);
+            self.insert_channel(&channel, searcher);
+        }
+        self.loading.store(false, Ordering::Relaxed);
+        info!("loaded all channels");
+    }
+
+    /// open all channels in the state dir that were built by an external indexer
+    fn load_indexed_channels(&self) {
+        let entries = match std::fs::read_dir(&self.state_dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("could not read the state dir: {e}");
+                self.loading.store(false, Ordering::Relaxed);
+                return;
+            }
+        };
+        for path in entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+        {
+            if !path.join(GENERATION_FILE).exists() {
+                continue;
+            }
+            let Some(branch) = path.file_name().and_then(|b| b.to_str()) else {
+                continue;
+            };
+            let channel: Channel = match branch.parse() {
+                Ok(channel) => channel,
+                Err(e) => {
+                    warn!("skipping {}: {e}", path.display());
+                    continue;
+                }
+            };
+
+            match ChannelSearcher::open_from_statedir(&self.state_dir, &channel) {
+                Ok(searcher) => self.insert_channel(&channel, searcher),
+                Err(e) => error!("could not open channel {}: {}", channel, e.report()),
+            }
+        }
+        self.loading.store(false, Ordering::Relaxed);
+        info!("loaded all indexed channels");
+    }
+}
+
+/// the fc-nixos branches built on hydra, the extra flakes and the upstream channels
+async fn discover_channels(config: &Config, test: bool) -> Vec<(Channel, Flake)> {
+    let default_branches = || {
+        vec![Flake {
+            owner: "flyingcircusio".to_string(),
+            name: "fc-nixos".to_string(),
+            branch: "fc-23.11-dev".to_string(),
+            rev: FlakeRev::FallbackToCached,
+            channel: None,
+            kind: FlakeKind::FcNixos,
+            hydra_jobset: None,
+        }]
+    };
+    if test {
+        return with_channels(default_branches());
+    }
+
+    let mut branches = get_fcio_flake_uris()
+        .await
+        .unwrap_or_else(|_| default_branches());
+    branches.extend(get_extra_flakes(&config.flakes).await);
+    branches.extend(get_upstream_flakes(&config.upstream).await);
+    with_channels(branches)
+}
+
+/// the flakes with the channel they are served under, flakes with invalid channel names are skipped
+fn with_channels(flakes: Vec<Flake>) -> Vec<(Channel, Flake)> {
+    flakes
+        .into_iter()
+        .filter_map(|flake| match flake.channel().parse() {
+            Ok(channel) => Some((channel, flake)),
+            Err(e) => {
+                warn!("skipping flake {}: {e}", flake.flake_uri());
+                None
+            }
+        })
+        .collect()
+}
+
+/// how long a shutdown waits for running channel updates to stop
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// where the served channels come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// evaluate and index the channels, `test` only indexes a single branch
+    Index { test: bool },
+    /// hot-reload the indices an external indexer writes to the state dir
+    Watch,
+    /// serve the indexed channels without updating them
+    Offline,
+    /// download the channels published to the configured storage
+    Pull,
+}
+
+pub async fn run(
+    listen: Listen,
+    state_dir: &Path,
+    mode: Mode,
+    config: Config,
+    reload: Reload,
+) -> anyhow::Result<()> {
+    let watch = mode == Mode::Watch;
+    let offline = mode == Mode::Offline;
+    let pull = mode == Mode::Pull;
+    let test = mode == Mode::Index { test: true };
+    anyhow::ensure!(
+        !pull || config.storage.backend.is_some(),
+        "pulling the channels requires a `storage.backend` in the config"
+    );
+
+    // held for the lifetime of the server, a watching or offline server never writes to the state dir
+    let _state_lock = if watch || offline {
+        None
+    } else {
+        Some(StateLock::try_acquire(state_dir)?)
+    };
+
+    // the routes can not change while running, so a changed base path is only applied on restart
+    let base_path = config.base_path.clone();
+    BASE_PATH.get_or_init(|| base_path.clone());
+
+    let config = Arc::new(ArcSwap::from_pointee(config));
+    let mut state = AppState::new(state_dir, config)?;
+    state.log = reload.log.clone();
+
+    let router = Router::new()
+        .route("/", get(index_handler))
+        .route(
+            "/search",
+            get(|| async { Redirect::permanent(&prefixed("/search/options")).into_response() }),
+        )
+        .route("/search/options", get(search_options_handler))
+        .route("/search/packages", get(search_packages_handler))
+        .route("/search/home-manager", get(search_home_manager_handler))
+        .route("/search/changelog", get(search_changelog_handler))
+        .route("/options/:channel/:name", get(option_handler))
+        .route("/options/:channel/:name/:field", get(option_field_handler))
+        .route(
+            "/home-manager/:channel/:name",
+            get(home_manager_option_handler),
+        )
+        .route(
+            "/home-manager/:channel/:name/:field",
+            get(home_manager_option_field_handler),
+        )
+        .route("/channels/:branch/changes", get(channel_changes_handler))
+        .route("/channels/:branch/upgrade/:to", get(upgrade_report_handler))
+        .route("/channel-info", get(channel_info_handler))
+        .route("/api/v1/snippet", get(snippet_handler))
+        .route("/api/v1/tree/:channel", get(tree_handler))
+        .route("/api/v1/export/:channel/:corpus", get(export_handler))
+        .route("/roles", get(roles_handler))
+        .route("/roles/:name", get(role_handler))
+        .route("/health", get(health_handler))
+        .route("/status", get(status_handler))
+        .route(
+            "/admin/loglevel",
+            get(get_log_level_handler).put(set_log_level_handler),
+        )
+        .route("/assets/*file", get(static_handler))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            identify_client,
+        ))
+        .with_state(state.clone());
+    let router = if base_path.is_empty() {
+        router
+    } else {
+        info!("serving the frontend below {base_path}");
+        Router::new().nest(&base_path, router)
+    };
+
+    let listener = listen.bind().await?;
+
+    if reload.config_path.is_some() {
+        tokio::spawn(reload_on_sighup(state.clone(), reload));
+    }
+
+    // load the channels in the background while the server already answers,
+    // then run the update tasks or follow an external indexer
+    let shutdown = CancellationToken::new();
+    let (watcher_handle, updater_handle) = if watch || offline || pull {
+        if offline {
+            info!("offline mode, serving the indexed channels without updating them");
+        }
+        let handle = tokio::spawn(async move {
+            // taken before loading, so generations written while loading are reloaded
+            let generations = Generations::read_or_scan(&state.state_dir).unwrap_or_default();
+            let loader = state.clone();
+            if let Err(e) =
+                tokio::task::spawn_blocking(move || loader.load_indexed_channels()).await
+            {
+                error!("loading the indexed channels failed: {e}");
+            }
+            if watch {
+                watch_state_dir(state, generations).await;
+            } else if pull {
+                pull_channels(state).await;
+            }
+        });
+        (Some(handle), None)
+    } else {
+        let shutdown = shutdown.clone();
+        let handle = tokio::spawn(async move {
+            let branches = discover_channels(&state.config.load_full(), test).await;
+            let loader = state.clone();
+            if let Err(e) =
+                tokio::task::spawn_blocking(move || loader.load_channels(branches)).await
+            {
+                error!("loading the channels failed: {e}");
+            }
+            update_channels(state, shutdown).await;
+        });
+        (None, Some(handle))
+    };
+
+    let result = tokio::select! {
+        result = listener.serve(router) => result,
+        _ = shutdown_signal() => {
+            info!("shutting down");
+            Ok(())
+        }
+    };
+
+    if let Some(handle) = watcher_handle {
+        handle.abort();
+    }
+    shutdown.cancel();
+    if let Some(mut handle) = updater_handle {
+        // the nix build of a running update is killed, indexing it has to finish
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, &mut handle)
+            .await
+            .is_err()
+        {
+            warn!("channel updates did not stop in time, aborting them");
+            handle.abort();
+        }
+    }
+    result
+}
+
+/// resolves once the server is asked to stop with SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            error!("could not listen for SIGTERM: {e}");
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
 }
 
-const fn default_n_items() -> u8 {
-    15
+/// discover new and retired channels and run an update task for every channel
+/// until `shutdown` is cancelled
+async fn update_channels(state: AppState, shutdown: CancellationToken) {
+    // nix builds are heavy, so channels are still built one at a time
+    let builds = Arc::new(Semaphore::new(1));
+    let mut tasks = JoinSet::new();
+    let mut running: HashMap<Channel, (CancellationToken, AbortHandle)> = HashMap::new();
+
+    'discovery: loop {
+        let config = state.config.load_full();
+
+        // initialise possibly missing channels, they are updated right away
+        if let Ok(mut upstream_flakes) = get_fcio_flake_uris().await {
+            upstream_flakes.extend(get_extra_flakes(&config.flakes).await);
+            upstream_flakes.extend(get_upstream_flakes(&config.upstream).await);
+            let upstream_flakes = with_channels(upstream_flakes);
+            retire_channels(&state, &upstream_flakes, &config.retire).await;
+            for (channel, flake) in upstream_flakes {
+                if state.channel(&channel).is_none() {
+                    let searcher = ChannelSearcher::in_statedir(&state.state_dir, &flake);
+                    state.insert_channel(&channel, searcher);
+                }
+            }
+        }
+
+        if config.upstream.cross_reference {
+            refresh_upstream_names(&state).await;
+        }
+
+        // stop the tasks of removed channels and start the ones of new channels
+        let channels = state.channels.load();
+        running.retain(|branch, (cancel, _)| {
+            let keep = channels.contains_key(branch);
+            if !keep {
+                cancel.cancel();
+            }
+            keep
+        });
+        for branch in channels
+            .keys()
+            .sorted_by_key(|branch| update_priority(branch))
+        {
+            if running.contains_key(branch) {
+                continue;
+            }
+            let cancel = shutdown.child_token();
+            let task = tasks.spawn(update_channel_task(
+                branch.clone(),
+                state.clone(),
+                builds.clone(),
+                cancel.clone(),
+            ));
+            running.insert(branch.clone(), (cancel, task));
+        }
+
+        let next_discovery = tokio::time::sleep(config.update.interval);
+        tokio::pin!(next_discovery);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break 'discovery,
+                _ = &mut next_discovery => break,
+                Some(result) = tasks.join_next() => {
+                    if let Err(e) = result {
+                        error!("channel update task failed: {e}");
+                        // the task of the channel is started again by the next discovery
+                        running.retain(|_, (_, task)| !task.is_finished());
+                    }
+                }
+            }
+        }
+    }
+
+    while tasks.join_next().await.is_some() {}
+    info!("stopped all channel updates");
+}
+
+/// fetch the upstream names of the nixos releases of all channels,
+/// the previous names of a release are kept if fetching them fails
+async fn refresh_upstream_names(state: &AppState) {
+    let releases = state
+        .channels
+        .load()
+        .iter()
+        .filter(|(_, searcher)| searcher.flake.kind != FlakeKind::Nixpkgs)
+        .filter_map(|(channel, _)| channel.release())
+        .unique()
+        .collect_vec();
+
+    let mut fetched = HashMap::new();
+    for release in releases {
+        match fetch_upstream_names(release).await {
+            Ok(names) => {
+                fetched.insert(release, Arc::new(names));
+            }
+            Err(e) => warn!(
+                "could not fetch the upstream names of nixos {release}: {}",
+                e.report()
+            ),
+        }
+    }
+    state.upstream.rcu(|upstream| {
+        let mut upstream = HashMap::clone(upstream);
+        upstream.extend(fetched.clone());
+        upstream
+    });
+}
+
+/// update a channel whenever its schedule is due, until `cancel` is cancelled
+async fn update_channel_task(
+    branch: Channel,
+    state: AppState,
+    builds: Arc<Semaphore>,
+    cancel: CancellationToken,
+) {
+    let mut next_update = Instant::now();
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = tokio::time::sleep_until(next_update) => {}
+        }
+
+        let config = state.config.load_full();
+        let retired = state
+            .status
+            .read()
+            .unwrap()
+            .get(&branch)
+            .is_some_and(|status| status.retired_since.is_some());
+        if retired {
+            next_update = Instant::now() + config.update.interval;
+            continue;
+        }
+
+        // a running update is not dropped but stops its nix build once cancelled
+        let failures = tokio::select! {
+            _ = cancel.cancelled() => break,
+            _build = builds.acquire() => update_channel(&branch, &state, &cancel).await,
+        };
+        let delay = if failures == 0 {
+            config.update.interval_for(&branch)
+        } else {
+            config.update.retry_delay(&branch, failures)
+        };
+        debug!("next update for branch {} in {:?}", branch, delay);
+        next_update = Instant::now() + delay;
+    }
+    debug!("stopped updating branch {}", branch);
+}
+
+/// address the server listens on, either `host:port` or `unix:/path/to/socket`
+#[derive(Debug, Clone)]
+pub enum Listen {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for Listen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some(path) if !path.is_empty() => Ok(Self::Unix(PathBuf::from(path))),
+            Some(_) => Err("expected a socket path after `unix:`".to_string()),
+            None => s.parse().map(Self::Tcp).map_err(|e| {
+                format!("expected `host:port` like `[::1]:8000` or `unix:/path`, got `{s}`: {e}")
+            }),
+        }
+    }
+}
+
+enum BoundListener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listen {
+    async fn bind(&self) -> anyhow::Result<BoundListener> {
+        match self {
+            Self::Tcp(addr) => {
+                let listener = TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("could not listen on {addr}"))?;
+                info!(
+                    "router initialized, now listening on http://{}",
+                    listener.local_addr()?
+                );
+                Ok(BoundListener::Tcp(listener))
+            }
+            Self::Unix(path) => {
+                // a socket left behind by a previous run would make the bind fail,
+                // anything else at the path is not ours to remove
+                let stale = std::fs::symlink_metadata(path)
+                    .is_ok_and(|metadata| metadata.file_type().is_socket());
+                if stale {
+                    std::fs::remove_file(path).with_context(|| {
+                        format!("could not remove the stale socket {}", path.display())
+                    })?;
+                }
+                let listener = UnixListener::bind(path)
+                    .with_context(|| format!("could not listen on {}", path.display()))?;
+                info!("router initialized, now listening on {}", path.display());
+                Ok(BoundListener::Unix(listener))
+            }
+        }
+    }
+}
+
+impl BoundListener {
+    async fn serve(self, router: Router) -> anyhow::Result<()> {
+        match self {
+            Self::Tcp(listener) => axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+            .context("error while starting server"),
+            Self::Unix(listener) => loop {
+                // axum can only serve tcp listeners itself, so drive hyper directly
+                let socket = match listener.accept().await {
+                    Ok((socket, _)) => socket,
+                    Err(e) => {
+                        // e.g. running out of file descriptors, keep serving like axum does
+                        error!("error accepting a connection: {e}");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+                let router = router.clone();
+                tokio::spawn(async move {
+                    let service = hyper::service::service_fn(move |request: Request<Incoming>| {
+                        router.clone().call(request)
+                    });
+                    if let Err(e) = server::conn::auto::Builder::new(TokioExecutor::new())
+                        .serve_connection_with_upgrades(TokioIo::new(socket), service)
+                        .await
+                    {
+                        debug!("error serving a unix socket connection: {e}");
+                    }
+                });
+            },
+        }
+    }
+}
+
+/// resolve the client behind the trusted proxies for the handlers, see [`ClientIp`],
+/// and log the request with it
+async fn identify_client(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0);
+    let client = ClientIp::resolve(peer, request.headers(), &state.config.load().proxy);
+    request.extensions_mut().insert(client);
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let timer = Instant::now();
+    let response = next.run(request).await;
+    debug!(
+        %client,
+        %method,
+        path,
+        status = response.status().as_u16(),
+        elapsed = ?timer.elapsed(),
+        "request"
+    );
+    response
+}
+
+/// what to reload when the server receives a SIGHUP
+pub struct Reload {
+    pub config_path: Option<PathBuf>,
+    pub log: Option<LogReloadHandle>,
+    /// log filter used when the config does not set a log level
+    pub default_log_level: String,
+}
+
+pub type LogReloadHandle = reload::Handle<EnvFilter, Registry>;
+
+/// re-read the config file on SIGHUP. changed update intervals, pins, flakes and
+/// nix settings are picked up by the next update, ranking weights and the log level
+/// apply immediately
+async fn reload_on_sighup(state: AppState, reload: Reload) {
+    let Some(config_path) = reload.config_path else {
+        return;
+    };
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            error!("could not listen for SIGHUP: {e}");
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        info!("received SIGHUP, reloading {}", config_path.display());
+        let config = match Config::load(&config_path) {
+            Ok(config) => config,
+            Err(e) => {
+                error!("keeping the previous config, the new one is invalid: {e:?}");
+                continue;
+            }
+        };
+
+        if let Some(ref log) = reload.log {
+            let level = config
+                .log_level
+                .as_deref()
+                .unwrap_or(&reload.default_log_level);
+            match EnvFilter::try_new(level) {
+                Ok(filter) => {
+                    if let Err(e) = log.reload(filter) {
+                        error!("could not change the log level: {e}");
+                    }
+                }
+                Err(e) => error!("invalid log level {level}: {e}"),
+            }
+        }
+
+        if BASE_PATH
+            .get()
+            .is_some_and(|base_path| *base_path != config.base_path)
+        {
+            warn!("the base path is only changed on restart");
+        }
+        search::set_ranking(config.ranking.clone());
+        hydra::configure(&config.hydra);
+        fc_search::set_github_api_url(&config.github.api_url);
+        state.config.store(Arc::new(config));
+        info!("config reloaded");
+    }
+}
+
+/// reload channels whenever the writer of the state dir recorded a new index generation.
+/// the generations file is watched for changes on this host and polled for changes written
+/// by other hosts of a shared filesystem
+async fn watch_state_dir(state: AppState, mut known: Generations) {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<()>();
+
+    let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        let event = match res {
+            Ok(event) => event,
+            Err(e) => {
+                error!("error while watching the state dir: {e}");
+                return;
+            }
+        };
+        if !(event.kind.is_create() || event.kind.is_modify()) {
+            return;
+        }
+        if event
+            .paths
+            .iter()
+            .any(|path| path.file_name().is_some_and(|f| f == GENERATIONS_FILE))
+        {
+            let _ = tx.send(());
+        }
+    });
+
+    // without notifications the generations are still polled
+    let _watcher = match watcher {
+        Ok(mut watcher) => match watcher.watch(&state.state_dir, RecursiveMode::NonRecursive) {
+            Ok(()) => Some(watcher),
+            Err(e) => {
+                error!("could not watch {}: {e}", state.state_dir.display());
+                None
+            }
+        },
+        Err(e) => {
+            error!("could not create the state dir watcher: {e}");
+            None
+        }
+    };
+    info!(
+        "watching {} for new index generations",
+        state.state_dir.display()
+    );
+
+    loop {
+        let poll_interval = state.config.load().storage.poll_interval;
+        tokio::select! {
+            Some(()) = rx.recv() => {
+                // the file may be replaced several times in a row, coalesce those events
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                while rx.try_recv().is_ok() {}
+            }
+            _ = tokio::time::sleep(poll_interval) => {}
+        }
+
+        let generations = match Generations::read_or_scan(&state.state_dir) {
+            Ok(generations) => generations,
+            Err(e) => {
+                error!("could not read the generations of the state dir: {e}");
+                continue;
+            }
+        };
+        if generations == known {
+            continue;
+        }
+        reload_generations(&state, &mut known, generations);
+    }
+}
+
+/// reopen the channels whose generation changed and drop the ones that were deleted.
+/// channels that fail to open keep their previous generation, so they are retried
+fn reload_generations(state: &AppState, known: &mut Generations, generations: Generations) {
+    for (branch, generation) in &generations.channels {
+        match known.channels.get_mut(branch) {
+            Some(known) if known.generation == generation.generation => {
+                // only the kept revisions changed
+                known.revisions.clone_from(&generation.revisions);
+                continue;
+            }
+            _ => {}
+        }
+        let channel: Channel = match branch.parse() {
+            Ok(channel) => channel,
+            Err(e) => {
+                warn!("not reloading {e}");
+                continue;
+            }
+        };
+        match ChannelSearcher::open_from_statedir(&state.state_dir, &channel) {
+            Ok(searcher) => {
+                info!("reloaded channel {}", channel);
+                state.insert_channel(&channel, searcher);
+                known.channels.insert(branch.clone(), generation.clone());
+            }
+            Err(e) => error!("failed to reload channel {}: {}", channel, e.report()),
+        }
+    }
+
+    known.channels.retain(|branch, _| {
+        let kept = generations.channels.contains_key(branch);
+        if !kept {
+            if let Ok(channel) = branch.parse::<Channel>() {
+                info!("channel {} was removed from the state dir", channel);
+                state.remove_channel(&channel);
+            }
+        }
+        kept
+    });
+}
+
+/// poll the configured storage and serve the channels published there once they are downloaded
+async fn pull_channels(state: AppState) {
+    loop {
+        let config = state.config.load_full();
+        let Some(ref backend) = config.storage.backend else {
+            warn!("no storage configured, stopped pulling channels");
+            return;
+        };
+        match pull_published(&state, backend).await {
+            Ok(0) => debug!("no newly published channels"),
+            Ok(pulled) => {
+                info!("pulled {pulled} channels");
+                let state_dir = state.state_dir.clone();
+                let _ =
+                    tokio::task::spawn_blocking(move || search::collect_garbage(&state_dir)).await;
+            }
+            Err(e) => error!("could not pull the published channels: {e:#}"),
+        }
+        tokio::time::sleep(config.storage.poll_interval).await;
+    }
+}
+
+/// download the newly published channels and reopen them, returns their number
+async fn pull_published(state: &AppState, backend: &StorageBackend) -> anyhow::Result<usize> {
+    let storage = ObjectStorage::new(backend)?;
+    let pulled = storage::pull(&storage, &state.state_dir).await?;
+    for branch in &pulled {
+        let branch: Channel = match branch.parse() {
+            Ok(channel) => channel,
+            Err(e) => {
+                warn!("not loading {e}");
+                continue;
+            }
+        };
+        match ChannelSearcher::open_from_statedir(&state.state_dir, &branch) {
+            Ok(searcher) => {
+                info!("reloaded channel {}", branch);
+                state.insert_channel(&branch, searcher);
+            }
+            Err(e) => error!("failed to reload channel {}: {}", branch, e.report()),
+        }
+    }
+    Ok(pulled.len())
+}
+
+/// fails once a channel could not be updated `max_failures` times in a row
+async fn health_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let max_failures = state.config.load().update.max_failures;
+    let failing = state
+        .status
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(_, status)| status.consecutive_failures >= max_failures)
+        .map(|(branch, status)| {
+            format!(
+                "{branch}: {} consecutive failed updates",
+                status.consecutive_failures
+            )
+        })
+        .sorted()
+        .collect_vec();
+
+    if failing.is_empty() {
+        (StatusCode::OK, "ok".to_string()).into_response()
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, failing.join("\n")).into_response()
+    }
+}
+
+/// update status of all channels, as json if requested by the accept header
+async fn status_handler(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let entries = state.status_entries();
+
+    if wants_json(&headers) {
+        return axum::Json(entries).into_response();
+    }
+
+    HtmlTemplate(StatusTemplate { entries }).into_response()
+}
+
+#[derive(Deserialize, Debug)]
+struct RolesQuery {
+    channel: Option<Channel>,
+}
+
+/// the selected channel, or the default channel like on the search pages
+fn selected_channel(
+    state: &AppState,
+    channel: Option<Channel>,
+) -> Option<(Channel, Arc<ChannelSearcher>)> {
+    let channel = channel.or_else(|| Channel::default_of(state.channels.load().keys()).cloned())?;
+    let searcher = state.channel(&channel)?;
+    Some((channel, searcher))
+}
+
+/// the selected channel like [`selected_channel`], if it can already be searched
+fn active_channel(
+    state: &AppState,
+    channel: Option<Channel>,
+) -> Option<(Channel, Arc<ChannelSearcher>)> {
+    selected_channel(state, channel).filter(|(_, searcher)| searcher.active())
+}
+
+/// shown instead of the search while no channel is indexed yet, e.g. right after the start
+fn warming_up(headers: &HeaderMap) -> Response {
+    // htmx only swaps successful responses into the results
+    if headers.contains_key("HX-Request") {
+        return axum::response::Html(
+            "<li class=\"my-4 p-2\">The search index is warming up, please try again in a moment.</li>",
+        )
+        .into_response();
+    }
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, "10")],
+        HtmlTemplate(WarmingUpTemplate {}),
+    )
+        .into_response()
+}
+
+/// all fc-nixos roles of a channel
+async fn roles_handler(
+    State(state): State<AppState>,
+    query: axum::extract::Query<RolesQuery>,
+) -> Response {
+    let Some((channel, searcher)) = selected_channel(&state, query.channel.clone()) else {
+        return (StatusCode::NOT_FOUND, "channel not found").into_response();
+    };
+    HtmlTemplate(RolesTemplate {
+        branches: state.active_branches(),
+        roles: searcher
+            .roles()
+            .map(|roles| roles.values().cloned().collect())
+            .unwrap_or_default(),
+        channel,
+    })
+    .into_response()
+}
+
+/// a fc-nixos role with all of its options
+async fn role_handler(
+    State(state): State<AppState>,
+    axum::extract::Path(name): axum::extract::Path<String>,
+    query: axum::extract::Query<RolesQuery>,
+) -> Response {
+    let Some((channel, searcher)) = selected_channel(&state, query.channel.clone()) else {
+        return (StatusCode::NOT_FOUND, "channel not found").into_response();
+    };
+    let Some(role) = searcher.roles().and_then(|roles| roles.get(&name)) else {
+        return (StatusCode::NOT_FOUND, "role not found").into_response();
+    };
+    let options = searcher
+        .options()
+        .map(|options| {
+            role.options
+                .iter()
+                .filter_map(|name| options.get(name).cloned())
+                .collect()
+        })
+        .unwrap_or_default();
+    HtmlTemplate(RoleTemplate {
+        permalink_base: prefixed(&format!("/options/{}", urlencoding(&channel))),
+        role: role.clone(),
+        options,
+        channel,
+    })
+    .into_response()
+}
+
+/// deeper trees are cut off, the client can expand them with further requests
+const MAX_TREE_DEPTH: usize = 5;
+
+const fn default_tree_depth() -> usize {
+    1
+}
+
+#[derive(Deserialize, Debug)]
+struct TreeQuery {
+    #[serde(default)]
+    prefix: String,
+    #[serde(default = "default_tree_depth")]
+    depth: usize,
+}
+
+/// the option namespace of a channel below a prefix as nested json
+async fn tree_handler(
+    State(state): State<AppState>,
+    axum::extract::Path(channel): axum::extract::Path<String>,
+    query: axum::extract::Query<TreeQuery>,
+) -> Response {
+    let searcher = state.channel(&channel);
+    let Some(options) = searcher.as_ref().and_then(|c| c.options()) else {
+        return (StatusCode::NOT_FOUND, "channel not found").into_response();
+    };
+    let tree = option_tree(
+        options.keys().map(String::as_str),
+        query.prefix.trim_matches('.'),
+        query.depth.min(MAX_TREE_DEPTH),
+    );
+    if tree.count == 0 {
+        return (StatusCode::NOT_FOUND, "no options below the prefix").into_response();
+    }
+    axum::Json(tree).into_response()
+}
+
+#[derive(Deserialize, Debug)]
+struct ExportQuery {
+    #[serde(default)]
+    format: Format,
+}
+
+/// the complete options or packages of a channel, streamed one entry at a time
+async fn export_handler(
+    State(state): State<AppState>,
+    axum::extract::Path((channel, corpus)): axum::extract::Path<(String, Corpus)>,
+    query: axum::extract::Query<ExportQuery>,
+) -> Response {
+    let Some(searcher) = state.channel(&channel) else {
+        return (StatusCode::NOT_FOUND, "channel not found").into_response();
+    };
+    let body = match corpus {
+        Corpus::Options => export_body(searcher, ChannelSearcher::options, corpus, query.format),
+        Corpus::Packages => export_body(searcher, ChannelSearcher::packages, corpus, query.format),
+    };
+    let Some(body) = body else {
+        return (StatusCode::NOT_FOUND, "channel not indexed yet").into_response();
+    };
+
+    let file_name = format!(
+        "{}-{}.{}",
+        channel,
+        format!("{corpus:?}").to_lowercase(),
+        query.format.extension()
+    );
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                query.format.content_type().to_string(),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{file_name}\""),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// encodes the entries while the body is sent, only their sorted keys are collected up front
+fn export_body<T: Serialize + 'static>(
+    searcher: Arc<ChannelSearcher>,
+    entries: fn(&ChannelSearcher) -> Option<&HashMap<String, T>>,
+    corpus: Corpus,
+    format: Format,
+) -> Option<axum::body::Body> {
+    let keys = entries(&searcher)?.keys().cloned().sorted().collect_vec();
+    let mut encoder = EntryEncoder::new(format, corpus);
+    let chunks = keys
+        .into_iter()
+        .map(Some)
+        .chain([None])
+        .map(move |key| match key {
+            // the channel may be updated meanwhile, the searcher is kept until the export is done
+            Some(key) => match entries(&searcher).and_then(|e| e.get(&key)) {
+                Some(entry) => encoder.entry(&key, entry),
+                None => Ok(Vec::new()),
+            },
+            None => Ok(encoder.finish()),
+        });
+    Some(axum::body::Body::from_stream(futures_util::stream::iter(
+        chunks,
+    )))
+}
+
+#[derive(Deserialize, Debug)]
+struct SnippetQuery {
+    channel: String,
+    option: String,
+}
+
+/// a config snippet setting an option,
+/// home-manager options are looked up if there is no such nixos option
+async fn snippet_handler(
+    State(state): State<AppState>,
+    query: axum::extract::Query<SnippetQuery>,
+) -> Response {
+    let option = state.channel(&query.channel).and_then(|c| {
+        c.options()
+            .and_then(|options| options.get(&query.option))
+            .or_else(|| c.home_manager_options()?.get(&query.option))
+            .map(NaiveNixosOption::nix_snippet)
+    });
+    match option {
+        Some(snippet) => snippet.into_response(),
+        None => (StatusCode::NOT_FOUND, "option not found").into_response(),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ChannelInfoQuery {
+    channel: String,
+}
+
+/// revision and age of the selected channel for the footer of the search pages
+async fn channel_info_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    query: axum::extract::Query<ChannelInfoQuery>,
+) -> Response {
+    let Some(searcher) = state.channel(&query.channel) else {
+        return (StatusCode::NOT_FOUND, "channel not found").into_response();
+    };
+    let info = ChannelInfo {
+        channel: query.channel.clone(),
+        flake: searcher.flake.clone(),
+        indexed_at: searcher.indexed_at(),
+        nixpkgs: searcher.nixpkgs.clone(),
+    };
+
+    if wants_json(&headers) {
+        return axum::Json(info).into_response();
+    }
+
+    HtmlTemplate(ChannelInfoTemplate {
+        indexed_ago: format_ago(info.indexed_at),
+        channel: info.channel,
+        flake: info.flake,
+        nixpkgs: info.nixpkgs,
+    })
+    .into_response()
+}
+
+/// what the last update of a channel changed
+async fn channel_changes_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path(branch): axum::extract::Path<String>,
+) -> Response {
+    let Some(searcher) = state.channel(&branch) else {
+        return (StatusCode::NOT_FOUND, "channel not found").into_response();
+    };
+    let update = searcher.last_update();
+
+    if wants_json(&headers) {
+        return axum::Json(update).into_response();
+    }
+
+    let updated_ago = update.as_ref().map(|u| {
+        format_ago(Some(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(u.updated_at),
+        ))
+    });
+    HtmlTemplate(ChannelChangesTemplate {
+        label: searcher.flake.label(),
+        option_base: prefixed(&format!("/options/{}", urlencoding(&branch))),
+        upgrade_base: prefixed(&format!("/channels/{}/upgrade", urlencoding(&branch))),
+        branches: state
+            .active_branches()
+            .into_iter()
+            .filter(|b| b.name.as_str() != branch)
+            .collect(),
+        update,
+        updated_ago: updated_ago.unwrap_or_default(),
+    })
+    .into_response()
+}
+
+/// the options that are removed or deprecated by an upgrade from one channel to another
+async fn upgrade_report_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    axum::extract::Path((branch, to)): axum::extract::Path<(String, String)>,
+) -> Response {
+    let (Some(from_searcher), Some(to_searcher)) = (state.channel(&branch), state.channel(&to))
+    else {
+        return (StatusCode::NOT_FOUND, "channel not found").into_response();
+    };
+    let (Some(from_options), Some(to_options)) = (from_searcher.options(), to_searcher.options())
+    else {
+        return (StatusCode::NOT_FOUND, "channel not indexed yet").into_response();
+    };
+    let report = UpgradeReport::between(&branch, from_options, &to, to_options);
+
+    if wants_json(&headers) {
+        return axum::Json(report).into_response();
+    }
+
+    HtmlTemplate(UpgradeReportTemplate {
+        from_label: from_searcher.flake.label(),
+        to_label: to_searcher.flake.label(),
+        from_option_base: prefixed(&format!("/options/{}", urlencoding(&branch))),
+        to_option_base: prefixed(&format!("/options/{}", urlencoding(&to))),
+        report,
+    })
+    .into_response()
+}
+
+/// whether the request carries the configured admin token, admin endpoints are disabled without one
+fn authorized(state: &AppState, headers: &HeaderMap) -> bool {
+    let config = state.config.load();
+    let Some(ref token) = config.admin.token else {
+        return false;
+    };
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|given| {
+            // compare in constant time to not leak the token through timing
+            given.len() == token.len()
+                && given
+                    .bytes()
+                    .zip(token.bytes())
+                    .fold(0, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+        })
+}
+
+/// the current log filter
+async fn get_log_level_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let Some(ref log) = state.log else {
+        return (StatusCode::NOT_FOUND, "the log level can not be changed").into_response();
+    };
+
+    match log.with_current(|filter| filter.to_string()) {
+        Ok(filter) => filter.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// replace the log filter with the one in the body, e.g. `fc_search::search=debug,info`
+async fn set_log_level_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    if !authorized(&state, &headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let Some(ref log) = state.log else {
+        return (StatusCode::NOT_FOUND, "the log level can not be changed").into_response();
+    };
+
+    let filter = match EnvFilter::try_new(body.trim()) {
+        Ok(filter) => filter,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    match log.reload(filter) {
+        Ok(()) => {
+            info!("log level changed to {}", body.trim());
+            "ok".into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn index_handler() -> impl IntoResponse {
+    Redirect::permanent(&prefixed("/search")).into_response()
+}
+
+async fn search_options_handler<'a>(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ValidSearchForm(form): ValidSearchForm,
+) -> impl IntoResponse {
+    let n_items = form.n_items(&headers);
+
+    let Some((channel, c)) = active_channel(&state, form.channel.clone()) else {
+        return warming_up(&headers);
+    };
+    let (requested_page, generation) = form.page_in(&c);
+    let similar = similar_options(&state, &c, &form.q).await;
+    let (search_results, page, last_page, tabs) = if !form.q.is_empty() {
+        let options = c.count_options(&form.q, form.source, &similar);
+        let (page, last_page) = clamp_page(requested_page, options, n_items);
+        let tabs = CorpusTabs::new(
+            &form,
+            &channel,
+            page,
+            options,
+            c.count_packages(&form.q),
+            "options",
+        );
+        (
+            c.search_options(&form.q, form.source, &similar, n_items, page),
+            page,
+            last_page,
+            tabs,
+        )
+    } else {
+        (Vec::new(), 1, 1, None)
+    };
+    let permalink_base = prefixed(&format!("/options/{}", urlencoding(&channel)));
+    let recent = if form.q.is_empty() {
+        recent_changes(&state, &channel)
+    } else {
+        None
+    };
+    let upstream = state.upstream_names(&channel, &c);
+
+    if headers.contains_key("HX-Request") {
+        let template = OptionItemTemplate {
+            results: search_results,
+            page,
+            last_page,
+            scroll: form.scroll,
+            generation: generation.clone(),
+            permalink_base,
+            preview_length: PREVIEW_LENGTH,
+            recent,
+            tabs,
+            upstream,
+        };
+        return search_response(&headers, &form, &generation, HtmlTemplate(template));
+    }
+
+    search_response(
+        &headers,
+        &form,
+        &generation,
+        HtmlTemplate(OptionsIndexTemplate {
+            branches: state.active_branches(),
+            results: search_results,
+            search_value: &form.q,
+            page,
+            last_page,
+            scroll: form.scroll,
+            generation: generation.clone(),
+            n_items,
+            n_items_choices: N_ITEMS_CHOICES,
+            source: form.source.map(|s| s.as_str()).unwrap_or_default(),
+            permalink_base,
+            preview_length: PREVIEW_LENGTH,
+            recent,
+            tabs,
+            upstream,
+        }),
+    )
+}
+
+/// the options nearest in meaning to the query, added to the keyword matches.
+/// empty without embeddings, the keyword search is used alone if the query can not be embedded
+async fn similar_options(
+    state: &AppState,
+    searcher: &ChannelSearcher,
+    q: &str,
+) -> Vec<(String, f32)> {
+    if q.is_empty() || searcher.embeddings().is_none() {
+        return Vec::new();
+    }
+    let config = state.config.load_full();
+    match embeddings::embed_query(&config.embeddings, q).await {
+        Ok(Some(vector)) => searcher.similar_options(&vector, &config.embeddings),
+        Ok(None) => Vec::new(),
+        Err(e) => {
+            warn!("could not embed the query: {}", e.report());
+            Vec::new()
+        }
+    }
 }
 
-const fn default_page() -> u8 {
-    1
-}
+async fn search_packages_handler<'a>(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ValidSearchForm(form): ValidSearchForm,
+) -> impl IntoResponse {
+    let n_items = form.n_items(&headers);
 
-#[derive(Deserialize, Debug)]
-struct SearchForm {
-    #[serde(default)]
-    q: String,
-    channel: Option<String>,
-    #[serde(default = "default_n_items")]
-    n_items: u8,
-    #[serde(default = "default_page")]
-    page: u8,
-}
+    let Some((channel, c)) = active_channel(&state, form.channel.clone()) else {
+        return warming_up(&headers);
+    };
+    let (requested_page, generation) = form.page_in(&c);
+    let (search_results, page, last_page, tabs) = if !form.q.is_empty() {
+        let packages = c.count_packages(&form.q);
+        let (page, last_page) = clamp_page(requested_page, packages, n_items);
+        let tabs = CorpusTabs::new(
+            &form,
+            &channel,
+            page,
+            // without the options nearest in meaning, embedding the query is left to the options page
+            c.count_options(&form.q, None, &[]),
+            packages,
+            "packages",
+        );
+        (
+            c.search_packages(&form.q, n_items, page),
+            page,
+            last_page,
+            tabs,
+        )
+    } else {
+        (Vec::new(), 1, 1, None)
+    };
+    let upstream = state.upstream_names(&channel, &c);
 
-impl AppState {
-    // TODO cache this between requests, only changes on rebuilds
-    fn active_branches(&self) -> Vec<String> {
-        self.channels
-            .read()
-            .unwrap()
-            .iter()
-            .filter_map(|channel| channel.1.active().then_some(channel.0))
-            .sorted()
-            .rev()
-            .cloned()
-            .collect_vec()
+    if headers.contains_key("HX-Request") {
+        let template = PackageItemTemplate {
+            page,
+            last_page,
+            scroll: form.scroll,
+            generation: generation.clone(),
+            results: search_results,
+            tabs,
+            upstream,
+        };
+        return search_response(&headers, &form, &generation, HtmlTemplate(template));
     }
 
-    fn in_dir(state_dir: &Path, branches: Vec<Flake>) -> anyhow::Result<Self> {
-        debug!("initializing app state");
+    search_response(
+        &headers,
+        &form,
+        &generation,
+        HtmlTemplate(PackagesIndexTemplate {
+            branches: state.active_branches(),
+            results: search_results,
+            search_value: &form.q,
+            page,
+            last_page,
+            scroll: form.scroll,
+            generation: generation.clone(),
+            n_items,
+            n_items_choices: N_ITEMS_CHOICES,
+            tabs,
+            upstream,
+        }),
+    )
+}
 
-        if !state_dir.exists() {
-            std::fs::create_dir_all(state_dir)?;
-        }
+async fn search_home_manager_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ValidSearchForm(form): ValidSearchForm,
+) -> impl IntoResponse {
+    let n_items = form.n_items(&headers);
 
-        let mut channels = HashMap::new();
-        for flake in branches {
-            let searcher = ChannelSearcher::in_statedir(state_dir, &flake);
-            channels.insert(flake.branch, searcher.into());
+    let branches = state.home_manager_branches();
+    let channel = form
+        .channel
+        .clone()
+        .or_else(|| Channel::default_of(branches.iter().map(|b| &b.name)).cloned());
+    let searcher = channel.as_ref().and_then(|c| state.channel(c));
+    let (requested_page, generation) = searcher
+        .as_ref()
+        .map(|c| form.page_in(c))
+        .unwrap_or_default();
+    let (search_results, page, last_page) = match searcher {
+        Some(c) if !form.q.is_empty() => {
+            let total = c.count_home_manager(&form.q);
+            let (page, last_page) = clamp_page(requested_page, total, n_items);
+            (
+                c.search_home_manager(&form.q, n_items, page),
+                page,
+                last_page,
+            )
         }
+        _ => (Vec::new(), 1, 1),
+    };
+    let permalink_base = prefixed(&format!(
+        "/home-manager/{}",
+        urlencoding(channel.as_deref().unwrap_or_default())
+    ));
 
-        let ret = Self {
-            channels: Arc::new(RwLock::new(channels)),
-            state_dir: state_dir.to_path_buf(),
+    if headers.contains_key("HX-Request") {
+        let template = OptionItemTemplate {
+            results: search_results,
+            page,
+            last_page,
+            scroll: form.scroll,
+            generation: generation.clone(),
+            permalink_base,
+            preview_length: PREVIEW_LENGTH,
+            recent: None,
+            tabs: None,
+            upstream: None,
         };
-        Ok(ret)
+        return search_response(&headers, &form, &generation, HtmlTemplate(template));
     }
+
+    search_response(
+        &headers,
+        &form,
+        &generation,
+        HtmlTemplate(HomeManagerIndexTemplate {
+            branches,
+            results: search_results,
+            search_value: &form.q,
+            page,
+            last_page,
+            scroll: form.scroll,
+            generation: generation.clone(),
+            n_items,
+            n_items_choices: N_ITEMS_CHOICES,
+            permalink_base,
+            preview_length: PREVIEW_LENGTH,
+            recent: None,
+            tabs: None,
+            upstream: None,
+        }),
+    )
 }
 
-pub async fn run(port: u16, state_dir: &Path, test: bool) -> anyhow::Result<()> {
-    let state = {
-        let default_branches = || {
-            vec![Flake {
-                owner: "flyingcircusio".to_string(),
-                name: "fc-nixos".to_string(),
-                branch: "fc-23.11-dev".to_string(),
-                rev: fc_search::FlakeRev::FallbackToCached,
-            }]
-        };
+#[derive(Deserialize, Debug)]
+struct OptionDetailQuery {
+    /// another channel to compare the option with
+    compare: Option<Channel>,
+}
 
-        let branches = if test {
-            default_branches()
-        } else {
-            get_fcio_flake_uris()
-                .await
-                .unwrap_or_else(|_| default_branches())
-        };
+/// the option in another channel, shown as the diff of an upgrade to it
+struct OptionComparison {
+    /// the channels the option can be compared with
+    channels: Vec<ChannelChoice>,
+    selected: Option<Channel>,
+    /// the fields that differ, `None` if the selected channel does not have the option
+    diffs: Option<Vec<FieldDiff>>,
+}
+
+impl OptionComparison {
+    fn is_selected(&self, channel: &str) -> bool {
+        self.selected.as_deref() == Some(channel)
+    }
+}
+
+/// compare `option` of `channel` with the same option in the channel `compare`,
+/// which is looked up by `lookup`
+fn compare_option(
+    state: &AppState,
+    channel: &str,
+    option: Option<&NaiveNixosOption>,
+    compare: Option<Channel>,
+    lookup: impl Fn(&ChannelSearcher) -> Option<NaiveNixosOption>,
+) -> OptionComparison {
+    let diffs = compare
+        .as_ref()
+        .and_then(|c| state.channel(c))
+        .zip(option)
+        .and_then(|(searcher, option)| Some(option_diff(option, &lookup(&searcher)?)));
+    OptionComparison {
+        channels: state
+            .active_branches()
+            .into_iter()
+            .filter(|b| b.name.as_str() != channel)
+            .collect(),
+        selected: compare,
+        diffs,
+    }
+}
+
+/// a single option with all its details, the target of the option permalinks
+async fn option_handler(
+    State(state): State<AppState>,
+    axum::extract::Path((channel, name)): axum::extract::Path<(String, String)>,
+    query: axum::extract::Query<OptionDetailQuery>,
+) -> Response {
+    let searcher = state.channel(&channel);
+    let option = searcher
+        .as_ref()
+        .and_then(|c| c.options()?.get(&name).cloned());
+    let comparison = compare_option(&state, &channel, option.as_ref(), query.0.compare, |c| {
+        c.options()?.get(&name).cloned()
+    });
+    let sub_options = match (&searcher, &option) {
+        (Some(c), Some(o)) if o.has_sub_options() => c.sub_options(&name),
+        _ => Vec::new(),
+    };
+    let history = match (&searcher, &option) {
+        (Some(c), Some(_)) => c
+            .option_history(&name)
+            .into_iter()
+            .map(|entry| {
+                let url = c.flake.rev_url(&entry.rev);
+                (entry, url)
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    option_detail(
+        option,
+        sub_options,
+        history,
+        comparison,
+        channel,
+        "/search/options",
+        "/options",
+    )
+}
+
+/// the complete html of an option field that is only previewed in the search results
+async fn option_field_handler(
+    State(state): State<AppState>,
+    axum::extract::Path((channel, name, field)): axum::extract::Path<(String, String, String)>,
+) -> Response {
+    let option = state
+        .channel(&channel)
+        .and_then(|c| c.options()?.get(&name).cloned());
+    option_field(option, &field)
+}
 
-        // in release mode try to load the cached index from disk
-        AppState::in_dir(state_dir, branches)?
+async fn home_manager_option_field_handler(
+    State(state): State<AppState>,
+    axum::extract::Path((channel, name, field)): axum::extract::Path<(String, String, String)>,
+) -> Response {
+    let option = state
+        .channel(&channel)
+        .and_then(|c| c.home_manager_options()?.get(&name).cloned());
+    option_field(option, &field)
+}
+
+fn option_field(option: Option<NaiveNixosOption>, field: &str) -> Response {
+    let Some(option) = option else {
+        return (StatusCode::NOT_FOUND, "option not found").into_response();
+    };
+    let html = match field {
+        "description" => format!("<div>{}</div>", option.description),
+        "default" => format!("<code>{}</code>", option.default),
+        "example" => format!("<code>{}</code>", option.example),
+        _ => return (StatusCode::NOT_FOUND, "unknown option field").into_response(),
     };
+    axum::response::Html(html).into_response()
+}
 
-    let addr = std::net::SocketAddr::from(([0, 0, 0, 0], port));
+async fn home_manager_option_handler(
+    State(state): State<AppState>,
+    axum::extract::Path((channel, name)): axum::extract::Path<(String, String)>,
+    query: axum::extract::Query<OptionDetailQuery>,
+) -> Response {
+    let searcher = state.channel(&channel);
+    let option = searcher
+        .as_ref()
+        .and_then(|c| c.home_manager_options()?.get(&name).cloned());
+    let comparison = compare_option(&state, &channel, option.as_ref(), query.0.compare, |c| {
+        c.home_manager_options()?.get(&name).cloned()
+    });
+    let sub_options = match (&searcher, &option) {
+        (Some(c), Some(o)) if o.has_sub_options() => c.home_manager_sub_options(&name),
+        _ => Vec::new(),
+    };
+    option_detail(
+        option,
+        sub_options,
+        Vec::new(),
+        comparison,
+        channel,
+        "/search/home-manager",
+        "/home-manager",
+    )
+}
 
-    let router = Router::new()
-        .route("/", get(index_handler))
-        .route(
-            "/search",
-            get(|| async { Redirect::permanent("/search/options").into_response() }),
-        )
-        .route("/search/options", get(search_options_handler))
-        .route("/search/packages", get(search_packages_handler))
-        .route("/assets/*file", get(static_handler))
-        .with_state(state.clone());
+fn option_detail(
+    option: Option<NaiveNixosOption>,
+    sub_options: Vec<NaiveNixosOption>,
+    history: Vec<(HistoryEntry, Option<String>)>,
+    comparison: OptionComparison,
+    channel: String,
+    search_url: &str,
+    detail_url: &str,
+) -> Response {
+    match option {
+        Some(item) => HtmlTemplate(OptionDetailTemplate {
+            item,
+            sub_options,
+            history,
+            comparison,
+            permalink_base: prefixed(&format!("{detail_url}/{}", urlencoding(&channel))),
+            channel,
+            search_url: prefixed(search_url),
+            // the detail page always shows everything
+            preview_length: usize::MAX,
+        })
+        .into_response(),
+        None => (StatusCode::NOT_FOUND, "option not found").into_response(),
+    }
+}
 
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    info!(
-        "router initialized, now listening on http://{}",
-        listener.local_addr().unwrap()
-    );
+/// number of recently changed options shown before anything was searched
+const RECENT_CHANGES: usize = 10;
 
-    let updater_channels = state.channels.clone();
+/// the options the last update of a channel added or changed
+fn recent_changes(state: &AppState, channel: &str) -> Option<RecentChanges> {
+    let changes = state.channel(channel)?.last_update()?.changes;
+    let options = changes
+        .added_options
+        .into_iter()
+        .chain(changes.changed_options)
+        .take(RECENT_CHANGES)
+        .collect_vec();
+    (!options.is_empty()).then(|| RecentChanges {
+        changes_url: prefixed(&format!("/channels/{}/changes", urlencoding(channel))),
+        options,
+    })
+}
 
-    // run update loop in the background
-    let updater_handle = tokio::spawn(async move {
-        let freq = Duration::from_hours(5);
-        let mut interval = interval(freq);
-        loop {
-            interval.tick().await;
-            if let Ok(upstream_flakes) = get_fcio_flake_uris().await {
-                let channels: HashMap<String, RwLock<ChannelSearcher>> = updater_channels
-                    .read()
-                    .unwrap()
-                    .iter()
-                    .map(|(x, y)| (x.clone(), y.clone().into()))
-                    .collect();
+/// header numbering the searches of a page, see `index.html`
+const SEARCH_SEQ_HEADER: &str = "x-search-seq";
 
-                // update existing channels
-                for (branch, searcher) in &channels {
-                    update_channel(branch, searcher).await;
-                }
+/// the [`IndexGeneration`] the results were searched in
+const GENERATION_HEADER: &str = "x-index-generation";
 
-                // initialise possibly missing channels, they will be updated on the next run
-                for flake in upstream_flakes {
-                    // index new branches
-                    if !channels.contains_key(&flake.branch) {
-                        let searcher = ChannelSearcher::in_statedir(&state.state_dir, &flake);
+/// echo the sequence number of a search, so the page can drop responses
+/// that arrive after the ones to newer searches,
+/// and remember newly chosen results per page
+fn search_response(
+    headers: &HeaderMap,
+    form: &SearchForm,
+    generation: &IndexGeneration,
+    response: impl IntoResponse,
+) -> Response {
+    let mut response = response.into_response();
+    if let Some(seq) = headers.get(SEARCH_SEQ_HEADER) {
+        response
+            .headers_mut()
+            .insert(SEARCH_SEQ_HEADER, seq.clone());
+    }
+    if let Ok(id) = HeaderValue::from_str(&generation.id) {
+        response.headers_mut().insert(GENERATION_HEADER, id);
+    }
+    // the first page replaces all results instead of being appended to the ones of the old index
+    if generation.reset && form.scroll {
+        let response_headers = response.headers_mut();
+        response_headers.insert("HX-Retarget", HeaderValue::from_static("#searchresults"));
+        response_headers.insert("HX-Reswap", HeaderValue::from_static("innerHTML"));
+    }
 
-                        updater_channels
-                            .write()
-                            .unwrap()
-                            .insert(flake.branch, searcher.into());
-                    }
-                }
-            }
+    let n_items = form.n_items(headers);
+    let remembered = cookie(headers, N_ITEMS_COOKIE).and_then(|c| c.parse().ok());
+    if form.n_items.is_some() && remembered != Some(n_items) {
+        let cookie = format!(
+            "{N_ITEMS_COOKIE}={n_items}; Path={}; Max-Age=31536000; SameSite=Lax",
+            prefixed("/")
+        );
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().append(header::SET_COOKIE, cookie);
         }
-    });
-
-    if let Err(e) = axum::serve(listener, router.into_make_service())
-        .await
-        .context("error while starting server")
-    {
-        let _ = updater_handle.abort();
-        Err(e)
-    } else {
-        Ok(())
     }
+    response
 }
 
-async fn index_handler() -> impl IntoResponse {
-    Redirect::permanent("/search").into_response()
+/// the requested page limited to the last page with results, and the last page
+fn clamp_page(page: u8, total: usize, n_items: u8) -> (u8, u8) {
+    let last_page = total
+        .div_ceil(n_items.max(1).into())
+        .clamp(1, u8::MAX.into()) as u8;
+    (page.min(last_page), last_page)
 }
 
-async fn search_options_handler<'a>(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    form: axum::extract::Form<SearchForm>,
-) -> impl IntoResponse {
-    if form.page == 0 {
-        return axum::http::StatusCode::IM_A_TEAPOT.into_response();
-    }
-
-    let search_results = if !form.q.is_empty() {
-        let channel = form.channel.clone().unwrap_or_else(|| {
-            state
-                .channels
-                .read()
-                .unwrap()
-                .keys()
-                .sorted()
-                .find(|x| x.contains("prod"))
-                .cloned()
-                .context("no channels active")
-                .unwrap()
-        });
+/// path prefix the frontend is served under, empty if it is served at the root.
+/// set once on startup, see [`Config::base_path`]
+static BASE_PATH: OnceLock<String> = OnceLock::new();
 
-        match state.channels.read().unwrap().get(&channel) {
-            Some(c) => c.search_options(&form.q, form.n_items, form.page),
-            None => Vec::new(),
-        }
-    } else {
-        Vec::new()
-    };
+/// `path` below the base path, for all links and redirects to the frontend
+fn prefixed(path: &str) -> String {
+    let base = BASE_PATH.get().map(String::as_str).unwrap_or_default();
+    format!("{base}{path}")
+}
 
-    if headers.contains_key("HX-Request") {
-        let template = OptionItemTemplate {
-            results: search_results,
-            page: form.page,
-        };
-        return HtmlTemplate(template).into_response();
+/// custom filters of the templates
+mod filters {
+    /// the absolute `path` below the base path, like `{{ "/status"|prefixed }}`
+    pub fn prefixed(path: &str) -> askama::Result<String> {
+        Ok(super::prefixed(path))
     }
+}
 
-    HtmlTemplate(OptionsIndexTemplate {
-        branches: state.active_branches(),
-        results: search_results,
-        search_value: &form.q,
-        page: form.page,
-    })
-    .into_response()
+/// percent-encode a path segment
+fn urlencoding(segment: &str) -> String {
+    url::form_urlencoded::byte_serialize(segment.as_bytes()).collect()
 }
 
-async fn search_packages_handler<'a>(
+/// search the release notes of a channel, or browse them if there is no query
+async fn search_changelog_handler(
     State(state): State<AppState>,
     headers: HeaderMap,
-    form: axum::extract::Form<SearchForm>,
+    ValidSearchForm(form): ValidSearchForm,
 ) -> impl IntoResponse {
-    if form.page == 0 {
-        return axum::http::StatusCode::IM_A_TEAPOT.into_response();
-    }
-
-    let search_results = if !form.q.is_empty() {
-        let channel = form.channel.clone().unwrap_or_else(|| {
-            state
-                .channels
-                .read()
-                .unwrap()
-                .keys()
-                .sorted()
-                .find(|x| x.contains("prod"))
-                .cloned()
-                .context("no prod channels active")
-                .unwrap()
-        });
-        match state.channels.read().unwrap().get(&channel) {
-            Some(c) => c.search_packages(&form.q, form.n_items, form.page),
-            None => Vec::new(),
+    let n_items = form.n_items(&headers);
+
+    let branches = state.active_branches();
+    let channel = form
+        .channel
+        .clone()
+        .or_else(|| Channel::default_of(branches.iter().map(|b| &b.name)).cloned());
+    let searcher = channel.and_then(|c| state.channel(&c));
+    let (requested_page, generation) = searcher
+        .as_ref()
+        .map(|c| form.page_in(c))
+        .unwrap_or_default();
+    let (results, page, last_page) = match searcher {
+        Some(c) => {
+            let (page, last_page) = clamp_page(requested_page, c.count_changelog(&form.q), n_items);
+            let results = match form.q.as_str() {
+                "" => c.browse_changelog(n_items, page),
+                q => c.search_changelog(q, n_items, page),
+            };
+            (results, page, last_page)
         }
-    } else {
-        Vec::new()
+        None => (Vec::new(), 1, 1),
     };
 
     if headers.contains_key("HX-Request") {
-        let template = PackageItemTemplate {
-            page: form.page,
-            results: search_results,
+        let template = ChangelogItemTemplate {
+            results,
+            page,
+            last_page,
+            scroll: form.scroll,
+            generation: generation.clone(),
         };
-        return HtmlTemplate(template).into_response();
+        return search_response(&headers, &form, &generation, HtmlTemplate(template));
     }
 
-    HtmlTemplate(PackagesIndexTemplate {
-        branches: state.active_branches(),
-        results: search_results,
-        search_value: &form.q,
-        page: form.page,
-    })
-    .into_response()
+    search_response(
+        &headers,
+        &form,
+        &generation,
+        HtmlTemplate(ChangelogIndexTemplate {
+            branches,
+            results,
+            search_value: &form.q,
+            page,
+            last_page,
+            scroll: form.scroll,
+            generation: generation.clone(),
+            n_items,
+            n_items_choices: N_ITEMS_CHOICES,
+        }),
+    )
 }
 
 async fn static_handler(uri: Uri) -> impl IntoResponse {
@@ -284,7 +2206,7 @@ async fn static_handler(uri: Uri) -> impl IntoResponse {
 
 #[derive(RustEmbed)]
 #[folder = "assets/"]
-struct Asset;
+pub(crate) struct Asset;
 
 pub struct StaticFile<T>(pub T);
 
@@ -308,19 +2230,88 @@ where
 #[derive(Template)]
 #[template(path = "options_index.html")]
 struct OptionsIndexTemplate<'a> {
-    branches: Vec<String>,
+    branches: Vec<ChannelChoice>,
     results: Vec<NaiveNixosOption>,
     search_value: &'a str,
     page: u8,
+    last_page: u8,
+    /// load further pages when scrolling down instead of paginating
+    scroll: bool,
+    generation: IndexGeneration,
+    n_items: u8,
+    n_items_choices: &'static [u8],
+    /// the selected [`OptionSource`], empty for all options
+    source: &'static str,
+    permalink_base: String,
+    preview_length: usize,
+    recent: Option<RecentChanges>,
+    tabs: Option<CorpusTabs>,
+    /// see [`AppState::upstream_names`]
+    upstream: Option<Arc<UpstreamNames>>,
 }
 
 #[derive(Template)]
 #[template(path = "packages_index.html")]
 struct PackagesIndexTemplate<'a> {
-    branches: Vec<String>,
+    branches: Vec<ChannelChoice>,
     results: Vec<NixPackage>,
     search_value: &'a str,
     page: u8,
+    last_page: u8,
+    /// load further pages when scrolling down instead of paginating
+    scroll: bool,
+    generation: IndexGeneration,
+    n_items: u8,
+    n_items_choices: &'static [u8],
+    tabs: Option<CorpusTabs>,
+    upstream: Option<Arc<UpstreamNames>>,
+}
+
+#[derive(Template)]
+#[template(path = "home_manager_index.html")]
+struct HomeManagerIndexTemplate<'a> {
+    branches: Vec<ChannelChoice>,
+    results: Vec<NaiveNixosOption>,
+    search_value: &'a str,
+    page: u8,
+    last_page: u8,
+    /// load further pages when scrolling down instead of paginating
+    scroll: bool,
+    generation: IndexGeneration,
+    n_items: u8,
+    n_items_choices: &'static [u8],
+    permalink_base: String,
+    preview_length: usize,
+    recent: Option<RecentChanges>,
+    tabs: Option<CorpusTabs>,
+    /// home-manager options are not on search.nixos.org, always `None`
+    upstream: Option<Arc<UpstreamNames>>,
+}
+
+#[derive(Template)]
+#[template(path = "changelog_index.html")]
+struct ChangelogIndexTemplate<'a> {
+    branches: Vec<ChannelChoice>,
+    results: Vec<ChangelogEntry>,
+    search_value: &'a str,
+    page: u8,
+    last_page: u8,
+    /// load further pages when scrolling down instead of paginating
+    scroll: bool,
+    generation: IndexGeneration,
+    n_items: u8,
+    n_items_choices: &'static [u8],
+}
+
+#[derive(Template)]
+#[template(path = "changelog_item.html")]
+struct ChangelogItemTemplate {
+    results: Vec<ChangelogEntry>,
+    page: u8,
+    last_page: u8,
+    /// load further pages when scrolling down instead of paginating
+    scroll: bool,
+    generation: IndexGeneration,
 }
 
 #[derive(Template)]
@@ -328,6 +2319,138 @@ struct PackagesIndexTemplate<'a> {
 struct OptionItemTemplate {
     results: Vec<NaiveNixosOption>,
     page: u8,
+    last_page: u8,
+    /// load further pages when scrolling down instead of paginating
+    scroll: bool,
+    generation: IndexGeneration,
+    /// path the option names are appended to for their detail page
+    permalink_base: String,
+    /// longer fields are only previewed, see [`fc_search::Html::preview`]
+    preview_length: usize,
+    recent: Option<RecentChanges>,
+    tabs: Option<CorpusTabs>,
+    /// see [`AppState::upstream_names`]
+    upstream: Option<Arc<UpstreamNames>>,
+}
+
+/// the index a page of results was searched in, sent along with the next page
+#[derive(Clone, Default)]
+struct IndexGeneration {
+    /// see [`ChannelSearcher::generation`]
+    id: String,
+    /// the index was swapped while browsing, so the first page is shown again
+    reset: bool,
+}
+
+/// shown instead of the results as long as nothing was searched
+struct RecentChanges {
+    /// page with all changes of the last update
+    changes_url: String,
+    options: Vec<String>,
+}
+
+/// hit counts of a query in the options and packages of a channel
+struct CorpusTabs {
+    /// query string searching the same query and channel on either search page
+    query: String,
+    options: usize,
+    packages: usize,
+    /// the corpus that is shown, `options` or `packages`
+    active: &'static str,
+}
+
+impl CorpusTabs {
+    /// only shown above the first page when the next pages are appended while scrolling
+    fn new(
+        form: &SearchForm,
+        channel: &str,
+        page: u8,
+        options: usize,
+        packages: usize,
+        active: &'static str,
+    ) -> Option<Self> {
+        if form.scroll && page > 1 {
+            return None;
+        }
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("q", &form.q)
+            .append_pair("channel", channel)
+            .finish();
+        Some(Self {
+            query,
+            options,
+            packages,
+            active,
+        })
+    }
+}
+
+#[derive(Template)]
+#[template(path = "roles.html")]
+struct RolesTemplate {
+    branches: Vec<ChannelChoice>,
+    channel: Channel,
+    roles: Vec<Role>,
+}
+
+#[derive(Template)]
+#[template(path = "role.html")]
+struct RoleTemplate {
+    channel: Channel,
+    role: Role,
+    options: Vec<NaiveNixosOption>,
+    /// path the option names are appended to for their detail page
+    permalink_base: String,
+}
+
+#[derive(Template)]
+#[template(path = "channel_info.html")]
+struct ChannelInfoTemplate {
+    channel: String,
+    flake: Flake,
+    indexed_ago: String,
+    nixpkgs: Option<NixpkgsInput>,
+}
+
+#[derive(Template)]
+#[template(path = "channel_changes.html")]
+struct ChannelChangesTemplate {
+    label: String,
+    /// path the option names are appended to for their detail page
+    option_base: String,
+    update: Option<ChannelUpdate>,
+    updated_ago: String,
+    /// path the channels are appended to for their upgrade report
+    upgrade_base: String,
+    /// channels an upgrade can be reported for
+    branches: Vec<ChannelChoice>,
+}
+
+#[derive(Template)]
+#[template(path = "upgrade_report.html")]
+struct UpgradeReportTemplate {
+    from_label: String,
+    to_label: String,
+    /// paths the option names are appended to for their detail page in either channel
+    from_option_base: String,
+    to_option_base: String,
+    report: UpgradeReport,
+}
+
+#[derive(Template)]
+#[template(path = "option_detail.html")]
+struct OptionDetailTemplate {
+    item: NaiveNixosOption,
+    /// options of a submodule type option
+    sub_options: Vec<NaiveNixosOption>,
+    /// changes of the option with links to their revisions, oldest first
+    history: Vec<(HistoryEntry, Option<String>)>,
+    comparison: OptionComparison,
+    channel: String,
+    /// search page the option was found on
+    search_url: String,
+    permalink_base: String,
+    preview_length: usize,
 }
 
 #[derive(Template)]
@@ -335,6 +2458,42 @@ struct OptionItemTemplate {
 struct PackageItemTemplate {
     results: Vec<NixPackage>,
     page: u8,
+    last_page: u8,
+    /// load further pages when scrolling down instead of paginating
+    scroll: bool,
+    generation: IndexGeneration,
+    tabs: Option<CorpusTabs>,
+    upstream: Option<Arc<UpstreamNames>>,
+}
+
+#[derive(Template)]
+#[template(path = "warming_up.html")]
+struct WarmingUpTemplate {}
+
+#[derive(Template)]
+#[template(path = "unknown_channel.html")]
+struct UnknownChannelTemplate {
+    channel: String,
+    fallback: Option<ChannelLink>,
+    channels: Vec<ChannelLink>,
+}
+
+/// search in another channel, see [`UnknownChannelTemplate`]
+struct ChannelLink {
+    url: String,
+    label: String,
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorTemplate {
+    message: String,
+}
+
+#[derive(Template)]
+#[template(path = "status.html")]
+struct StatusTemplate {
+    entries: Vec<StatusEntry>,
 }
 
 struct HtmlTemplate<T>(T);
@@ -355,20 +2514,284 @@ where
     }
 }
 
-async fn update_channel(branch: &str, channel: &RwLock<ChannelSearcher>) {
+/// mark channels that are no longer built on hydra as retired
+/// and drop them once their grace period is over
+async fn retire_channels(
+    state: &AppState,
+    upstream_flakes: &[(Channel, Flake)],
+    config: &RetireConfig,
+) {
+    let upstream: HashSet<&Channel> = upstream_flakes.iter().map(|(channel, _)| channel).collect();
+
+    let mut expired = Vec::new();
+    {
+        let mut statuses = state.status.write().unwrap();
+        for branch in state.channels.load().keys() {
+            let status = statuses.entry(branch.clone()).or_default();
+            if upstream.contains(branch) {
+                if status.retired_since.take().is_some() {
+                    info!("retired branch {} is built on hydra again", branch);
+                }
+                continue;
+            }
+
+            match status.retired_since {
+                None => {
+                    warn!("branch {} disappeared from hydra, retiring it", branch);
+                    status.retired_since = Some(SystemTime::now());
+                }
+                Some(since) if since.elapsed().unwrap_or_default() >= config.grace_period => {
+                    expired.push(branch.clone());
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    let mut deleted = Vec::new();
+    for branch in expired {
+        let Some(searcher) = state.remove_channel(&branch) else {
+            continue;
+        };
+        state.status.write().unwrap().remove(&branch);
+        info!("removing retired branch {}", branch);
+        if config.delete_state {
+            deleted.push((branch, searcher.branch_path().to_path_buf()));
+        }
+    }
+    if deleted.is_empty() {
+        return;
+    }
+
+    // removing the indices of whole channels takes a while, keep it off the runtime
+    let state_dir = state.state_dir.clone();
+    let _ = tokio::task::spawn_blocking(move || {
+        for (branch, branch_path) in deleted {
+            if let Err(e) = std::fs::remove_dir_all(branch_path) {
+                error!("failed to delete the state of branch {}: {e}", branch);
+            }
+        }
+        write_generations(&state_dir);
+        search::collect_garbage(&state_dir);
+    })
+    .await;
+}
+
+/// sort key for due channels: production branches first, newest releases first
+/// so the channels most users browse become searchable first
+fn update_priority(channel: &Channel) -> (u8, std::cmp::Reverse<Channel>) {
+    let environment = match channel.environment() {
+        Some(Environment::Production) => 0,
+        Some(Environment::Staging) => 1,
+        _ => 2,
+    };
+    (environment, std::cmp::Reverse(channel.clone()))
+}
+
+/// update a single channel and record the outcome in its status.
+/// returns the number of consecutive failed updates, 0 on success or when `cancel` stopped it
+async fn update_channel(branch: &Channel, state: &AppState, cancel: &CancellationToken) -> u32 {
+    // wait for a running update of the channel, then continue from its result
+    let lock = state.update_lock(branch);
+    let _updating = lock.lock().await;
+
     // obtain the current searcher
-    let mut cs: ChannelSearcher = channel.read().unwrap().clone();
+    let Some(current) = state.channel(branch) else {
+        return 0;
+    };
+    let mut cs = ChannelSearcher::clone(&current);
+    let previous_rev = cs.active().then(|| cs.flake.rev.clone());
 
     // no lock on the channel searcher here, so we can update it
     // and replace the value on success while search is still running
     // in an error case the old status is retained and the error logged
     info!("starting update for branch {}", branch);
-    match cs.update().await {
-        Err(e) => error!("error updating branch {}: {e:?}", branch),
+    let started = SystemTime::now();
+    let timer = Instant::now();
+    let config = state.config.load_full();
+    let rev = match config.pins.get(branch.as_str()) {
+        Some(rev) => {
+            info!("branch {} is pinned to {}", branch, rev);
+            Ok(FlakeRev::Specific(rev.clone()))
+        }
+        None => cs.latest_rev().await,
+    };
+    // evaluating and indexing block, so they must not hold up a worker of the runtime
+    let (cs, result) = match rev {
+        Ok(rev) => {
+            let nix_config = config.nix.clone();
+            let cancel = cancel.clone();
+            let updated = tokio::task::spawn_blocking(move || {
+                let result = cs.update_to(rev, &nix_config, &cancel);
+                (cs, result)
+            })
+            .await
+            .query("updating the channel panicked");
+            match updated {
+                Ok(updated) => updated,
+                // the channel keeps its current searcher and is retried like after any failure
+                Err(e) => (ChannelSearcher::clone(&current), Err(e)),
+            }
+        }
+        Err(e) => (cs, Err(e)),
+    };
+    let duration = timer.elapsed();
+    if result.is_err() && cancel.is_cancelled() {
+        info!("cancelled the update of branch {}", branch);
+        return 0;
+    }
+
+    // missing embeddings only disable the semantic part of the search, the update succeeded
+    if result.is_ok() {
+        if let Err(e) = cs.update_embeddings(&config.embeddings).await {
+            warn!(
+                "could not compute the embeddings of {}: {}",
+                branch,
+                e.report()
+            );
+        }
+    }
+    if let (Ok(()), Some(backend)) = (&result, &config.storage.backend) {
+        let published = match ObjectStorage::new(backend) {
+            Ok(storage) => storage::publish(&storage, &state.state_dir, branch.as_str()).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = published {
+            warn!("could not publish {branch}: {e:#}");
+        }
+    }
+    let hydra_status = match (&result, &cs.flake.hydra_jobset, &cs.flake.rev) {
+        (Ok(()), Some(jobset), FlakeRev::Specific(rev)) => {
+            match hydra::fetch_status(jobset, rev, config.hydra.build_job.as_deref()).await {
+                Ok(status) => status,
+                Err(e) => {
+                    warn!("could not fetch the hydra status of {branch}: {e}");
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let mut statuses = state.status.write().unwrap();
+    let status = statuses.entry(branch.clone()).or_default();
+    match result {
+        Err(e) => {
+            // github or hydra being unreachable resolves itself, broken channels do not
+            if e.is_transient() {
+                warn!("could not update branch {}: {}", branch, e.report());
+            } else {
+                error!("error updating branch {}: {}", branch, e.report());
+            }
+            status.record_failure(started, duration, &e);
+            if status.consecutive_failures >= config.update.max_failures {
+                error!(
+                    "branch {} failed to update {} times in a row",
+                    branch, status.consecutive_failures
+                );
+            }
+            // each webhook is only notified once its number of failures is reached
+            webhooks::notify(
+                &config,
+                WebhookPayload {
+                    revision: previous_rev.map(|rev| rev.to_string()),
+                    error: Some(e.report()),
+                    consecutive_failures: status.consecutive_failures,
+                    ..WebhookPayload::new(WebhookEvent::Failing, branch)
+                },
+            );
+        }
         Ok(()) => {
+            status.record_success(started, duration);
+            status.hydra = hydra_status;
+            if previous_rev.as_ref() != Some(&cs.flake.rev) {
+                let payload = |event| WebhookPayload {
+                    revision: Some(cs.flake.rev.to_string()),
+                    previous_revision: previous_rev.as_ref().map(|rev| rev.to_string()),
+                    ..WebhookPayload::new(event, branch)
+                };
+                webhooks::notify(&config, payload(WebhookEvent::Indexed));
+                if previous_rev.is_some() {
+                    webhooks::notify(&config, payload(WebhookEvent::RevisionChanged));
+                }
+            }
             // replace the old searcher with the updated one on success
-            let mut old = channel.write().unwrap();
-            *old = cs;
+            state.insert_channel(branch, cs);
         }
     }
+    status.consecutive_failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listen_parses_tcp_and_unix_addresses() {
+        assert!(matches!(
+            "[::1]:8000".parse::<Listen>(),
+            Ok(Listen::Tcp(addr)) if addr == SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 8000))
+        ));
+        assert!(matches!(
+            "0.0.0.0:80".parse::<Listen>(),
+            Ok(Listen::Tcp(addr)) if addr.port() == 80
+        ));
+        assert!(matches!(
+            "unix:/run/fc-search.sock".parse::<Listen>(),
+            Ok(Listen::Unix(path)) if path == Path::new("/run/fc-search.sock")
+        ));
+    }
+
+    #[test]
+    fn listen_rejects_invalid_addresses() {
+        assert!("unix:".parse::<Listen>().is_err());
+        assert!("localhost".parse::<Listen>().is_err());
+        assert!("8000".parse::<Listen>().is_err());
+    }
+
+    fn choices(names: &[&str]) -> Vec<ChannelChoice> {
+        names
+            .iter()
+            .map(|name| ChannelChoice {
+                name: name.parse().unwrap(),
+                label: name.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unknown_channels_fall_back_to_the_closest_production_channel() {
+        let branches = choices(&[
+            "fc-23.05-production",
+            "fc-23.11-dev",
+            "fc-23.11-production",
+            "fc-24.05-production",
+        ]);
+        let closest = |channel: &str| {
+            closest_production_channel(&channel.parse().unwrap(), &branches)
+                .map(|channel| channel.to_string())
+        };
+        assert_eq!(
+            closest("fc-23.11-staging").as_deref(),
+            Some("fc-23.11-production")
+        );
+        assert_eq!(
+            closest("fc-23.05-dev").as_deref(),
+            Some("fc-23.05-production")
+        );
+        // without a common release the newest production channel wins
+        assert_eq!(
+            closest("nixos-unstable").as_deref(),
+            Some("fc-24.05-production")
+        );
+    }
+
+    #[test]
+    fn no_fallback_without_production_channels() {
+        let branches = choices(&["fc-23.11-dev", "nixos-unstable"]);
+        assert_eq!(
+            closest_production_channel(&"fc-23.11-staging".parse().unwrap(), &branches),
+            None
+        );
+    }
 }
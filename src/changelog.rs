@@ -0,0 +1,78 @@
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::{Html, NixHtml};
+
+/// a section of the fc-nixos changelog, usually the notes of a single release
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChangelogEntry {
+    pub title: String,
+    /// position in the changelog, newer entries come first
+    pub position: usize,
+    /// markdown text of the section
+    pub text: String,
+}
+
+impl ChangelogEntry {
+    pub fn html(&self) -> Html {
+        self.text.as_html()
+    }
+}
+
+/// collect the release notes of a fc-nixos checkout: the fragments of the
+/// unreleased changes in `changelog.d` followed by the sections of `CHANGELOG.md`
+pub fn read_changelog(source: &Path) -> HashMap<String, ChangelogEntry> {
+    let mut sections = Vec::new();
+
+    if let Ok(dir) = std::fs::read_dir(source.join("changelog.d")) {
+        let fragments = dir
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "md"))
+            .sorted()
+            .filter_map(|path| std::fs::read_to_string(path).ok())
+            .join("\n\n");
+        if !fragments.trim().is_empty() {
+            sections.push(("Unreleased".to_string(), fragments));
+        }
+    }
+
+    if let Ok(changelog) = std::fs::read_to_string(source.join("CHANGELOG.md")) {
+        sections.extend(split_sections(&changelog));
+    }
+
+    let mut entries = HashMap::new();
+    for (position, (title, text)) in sections.into_iter().enumerate() {
+        let key = if entries.contains_key(&title) {
+            format!("{title} ({position})")
+        } else {
+            title.clone()
+        };
+        entries.insert(
+            key,
+            ChangelogEntry {
+                title,
+                position,
+                text: text.trim().to_string(),
+            },
+        );
+    }
+    entries
+}
+
+/// split a markdown changelog at its second level headings,
+/// anything before the first of them is the preamble of the file
+fn split_sections(changelog: &str) -> Vec<(String, String)> {
+    let mut sections: Vec<(String, String)> = Vec::new();
+    for line in changelog.lines() {
+        if let Some(title) = line.strip_prefix("## ") {
+            sections.push((title.trim().to_string(), String::new()));
+        } else if let Some((_, text)) = sections.last_mut() {
+            text.push_str(line);
+            text.push('\n');
+        }
+    }
+    sections
+}
@@ -0,0 +1,228 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
+
+use crate::nix::NixPackage;
+use crate::{FlakeRev, NaiveNixosOption};
+
+/// file in the channel dir holding the changes of the last update
+pub const CHANGES_FILE: &str = "changes.json";
+
+/// the options and packages that differ between two revisions of a channel
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct ChannelChanges {
+    pub added_options: Vec<String>,
+    pub removed_options: Vec<String>,
+    pub changed_options: Vec<String>,
+    pub added_packages: Vec<String>,
+    pub removed_packages: Vec<String>,
+    pub updated_packages: Vec<PackageUpdate>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PackageUpdate {
+    pub name: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+/// the changes a successful update brought to a channel
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChannelUpdate {
+    pub from: FlakeRev,
+    pub to: FlakeRev,
+    /// unix timestamp of the update
+    pub updated_at: u64,
+    pub changes: ChannelChanges,
+}
+
+/// options that stop working when upgrading from one channel to another
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct UpgradeReport {
+    pub from: String,
+    pub to: String,
+    /// options of `from` that `to` does not have anymore
+    pub removed_options: Vec<String>,
+    /// options of both channels that are marked as deprecated in `to`
+    pub deprecated_options: Vec<String>,
+}
+
+impl UpgradeReport {
+    pub fn between(
+        from: &str,
+        from_options: &HashMap<String, NaiveNixosOption>,
+        to: &str,
+        to_options: &HashMap<String, NaiveNixosOption>,
+    ) -> Self {
+        let (removed, _, common) = split_keys(from_options, to_options);
+        Self {
+            from: from.to_string(),
+            to: to.to_string(),
+            removed_options: removed,
+            deprecated_options: common
+                .into_iter()
+                .filter(|name| deprecated(&to_options[name]))
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.removed_options.is_empty() && self.deprecated_options.is_empty()
+    }
+}
+
+impl ChannelChanges {
+    pub fn between(
+        from_options: &HashMap<String, NaiveNixosOption>,
+        from_packages: &HashMap<String, NixPackage>,
+        to_options: &HashMap<String, NaiveNixosOption>,
+        to_packages: &HashMap<String, NixPackage>,
+    ) -> Self {
+        let mut changes = Self::default();
+
+        let (removed, added, common) = split_keys(from_options, to_options);
+        changes.added_options = added;
+        changes.removed_options = removed;
+        changes.changed_options = common
+            .into_iter()
+            .filter(|name| option_changed(&from_options[name], &to_options[name]))
+            .collect();
+
+        let (removed, added, common) = split_keys(from_packages, to_packages);
+        changes.added_packages = added;
+        changes.removed_packages = removed;
+        changes.updated_packages = common
+            .into_iter()
+            .filter_map(|name| {
+                let (from, to) = (&from_packages[&name].version, &to_packages[&name].version);
+                (from != to).then(|| PackageUpdate {
+                    from: from.clone(),
+                    to: to.clone(),
+                    name,
+                })
+            })
+            .collect();
+
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added_options.is_empty()
+            && self.removed_options.is_empty()
+            && self.changed_options.is_empty()
+            && self.added_packages.is_empty()
+            && self.removed_packages.is_empty()
+            && self.updated_packages.is_empty()
+    }
+}
+
+impl ChannelUpdate {
+    /// the changes of the last update of the channel in `branch_path`, if any were recorded
+    pub fn read(branch_path: &Path) -> Option<Self> {
+        std::fs::read_to_string(branch_path.join(CHANGES_FILE))
+            .ok()
+            .and_then(|c| serde_json::from_str(&c).ok())
+    }
+
+    pub fn write(&self, branch_path: &Path) -> anyhow::Result<()> {
+        std::fs::write(
+            branch_path.join(CHANGES_FILE),
+            serde_json::to_string(self).context("failed to serialize the channel changes")?,
+        )
+        .context("failed to save the channel changes")
+    }
+}
+
+/// a field of an option that differs between two channels, as a unified diff of its lines
+#[derive(Debug, Serialize, Clone)]
+pub struct FieldDiff {
+    pub field: &'static str,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DiffLine {
+    pub tag: DiffTag,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffTag {
+    Unchanged,
+    Removed,
+    Added,
+}
+
+impl DiffTag {
+    /// the prefix of the line in a unified diff
+    pub fn sign(&self) -> char {
+        match self {
+            DiffTag::Unchanged => ' ',
+            DiffTag::Removed => '-',
+            DiffTag::Added => '+',
+        }
+    }
+}
+
+/// the fields an upgrade from the option `from` to the option `to` changes,
+/// empty if the type, default and description are the same
+pub fn option_diff(from: &NaiveNixosOption, to: &NaiveNixosOption) -> Vec<FieldDiff> {
+    [
+        ("type", from.option_type.as_str(), to.option_type.as_str()),
+        ("default", from.default.text(), to.default.text()),
+        (
+            "description",
+            from.description.text(),
+            to.description.text(),
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, from, to)| from != to)
+    .map(|(field, from, to)| FieldDiff {
+        field,
+        lines: TextDiff::from_lines(from, to)
+            .iter_all_changes()
+            .map(|change| DiffLine {
+                tag: match change.tag() {
+                    ChangeTag::Equal => DiffTag::Unchanged,
+                    ChangeTag::Delete => DiffTag::Removed,
+                    ChangeTag::Insert => DiffTag::Added,
+                },
+                text: change.value().trim_end_matches('\n').to_string(),
+            })
+            .collect(),
+    })
+    .collect()
+}
+
+/// nixpkgs has no flag for deprecated options, they say so in their description
+fn deprecated(option: &NaiveNixosOption) -> bool {
+    let description = option.description.text().to_lowercase();
+    description.contains("deprecated") || description.contains("obsolete")
+}
+
+/// declarations are ignored, their links always differ between revisions
+fn option_changed(from: &NaiveNixosOption, to: &NaiveNixosOption) -> bool {
+    from.option_type != to.option_type
+        || from.default != to.default
+        || from.description != to.description
+        || from.read_only != to.read_only
+}
+
+/// sorted names of the entries only in `from`, only in `to` and in both
+fn split_keys<T>(
+    from: &HashMap<String, T>,
+    to: &HashMap<String, T>,
+) -> (Vec<String>, Vec<String>, Vec<String>) {
+    let from_keys: BTreeSet<_> = from.keys().cloned().collect();
+    let to_keys: BTreeSet<_> = to.keys().cloned().collect();
+    (
+        from_keys.difference(&to_keys).cloned().collect(),
+        to_keys.difference(&from_keys).cloned().collect(),
+        from_keys.intersection(&to_keys).cloned().collect(),
+    )
+}
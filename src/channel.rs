@@ -0,0 +1,218 @@
+use std::borrow::Borrow;
+use std::fmt::Display;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// the name a channel is served under, like `fc-23.11-production` or `nixos-unstable`.
+///
+/// the name is used as directory in the state dir, so it is checked to be a single path segment
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Channel(String);
+
+/// longest channel name that is accepted
+const MAX_LENGTH: usize = 100;
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid channel name {name:?}: {reason}")]
+pub struct InvalidChannel {
+    name: String,
+    reason: &'static str,
+}
+
+/// the stage of a fc-nixos branch, given by the suffix of its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Environment {
+    Production,
+    Staging,
+    Dev,
+}
+
+/// a nixos release like `23.11`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Release {
+    pub year: u8,
+    pub month: u8,
+}
+
+impl Display for Release {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}.{:02}", self.year, self.month)
+    }
+}
+
+impl FromStr for Release {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (year, month) = s.split_once('.').ok_or(())?;
+        if year.len() != 2 || month.len() != 2 {
+            return Err(());
+        }
+        Ok(Self {
+            year: year.parse().map_err(|_| ())?,
+            month: month.parse().map_err(|_| ())?,
+        })
+    }
+}
+
+impl Channel {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// the environment of fc-nixos branches like `fc-23.11-staging`
+    pub fn environment(&self) -> Option<Environment> {
+        match self.0.rsplit_once('-')?.1 {
+            "production" => Some(Environment::Production),
+            "staging" => Some(Environment::Staging),
+            "dev" => Some(Environment::Dev),
+            _ => None,
+        }
+    }
+
+    pub fn is_production(&self) -> bool {
+        self.environment() == Some(Environment::Production)
+    }
+
+    /// the nixos release the channel is based on, if its name contains one
+    /// like `fc-23.11-dev` or `nixos-23.11`
+    pub fn release(&self) -> Option<Release> {
+        self.0.split('-').find_map(|part| part.parse().ok())
+    }
+
+    /// the production channel with the newest release, searched when no channel is selected
+    pub fn default_of<'a>(channels: impl IntoIterator<Item = &'a Channel>) -> Option<&'a Channel> {
+        channels
+            .into_iter()
+            .filter(|channel| channel.is_production())
+            .max_by_key(|channel| (channel.release(), *channel))
+    }
+}
+
+impl FromStr for Channel {
+    type Err = InvalidChannel;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason| InvalidChannel {
+            name: name.to_string(),
+            reason,
+        };
+        if name.is_empty() {
+            return Err(invalid("the name is empty"));
+        }
+        if name.len() > MAX_LENGTH {
+            return Err(invalid("the name is too long"));
+        }
+        if name.starts_with('.') {
+            return Err(invalid("the name starts with a dot"));
+        }
+        if !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+'))
+        {
+            return Err(invalid(
+                "only letters, digits and `-`, `_`, `.` or `+` are allowed",
+            ));
+        }
+        Ok(Self(name.to_string()))
+    }
+}
+
+impl TryFrom<String> for Channel {
+    type Error = InvalidChannel;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        name.parse()
+    }
+}
+
+impl From<Channel> for String {
+    fn from(channel: Channel) -> Self {
+        channel.0
+    }
+}
+
+impl Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Deref for Channel {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Channel {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Channel {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_channel_names() {
+        for name in [
+            "fc-23.11-production",
+            "nixos-unstable",
+            "my_flake+modules",
+            "a",
+        ] {
+            assert_eq!(name.parse::<Channel>().unwrap().as_str(), name);
+        }
+    }
+
+    #[test]
+    fn invalid_channel_names() {
+        let too_long = "a".repeat(MAX_LENGTH + 1);
+        for name in [
+            "", ".", "..", ".hidden", "a/b", "../etc", "a b", "ä", &too_long,
+        ] {
+            assert!(name.parse::<Channel>().is_err(), "{name:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn environment_and_release() {
+        let channel: Channel = "fc-23.11-staging".parse().unwrap();
+        assert_eq!(channel.environment(), Some(Environment::Staging));
+        assert_eq!(
+            channel.release(),
+            Some(Release {
+                year: 23,
+                month: 11
+            })
+        );
+
+        let channel: Channel = "nixos-unstable".parse().unwrap();
+        assert_eq!(channel.environment(), None);
+        assert_eq!(channel.release(), None);
+    }
+
+    #[test]
+    fn default_channel_is_the_newest_production_channel() {
+        let channels: Vec<Channel> = ["fc-23.11-production", "fc-24.05-dev", "fc-23.05-production"]
+            .iter()
+            .map(|name| name.parse().unwrap())
+            .collect();
+        assert_eq!(
+            Channel::default_of(&channels).map(Channel::as_str),
+            Some("fc-23.11-production")
+        );
+    }
+}
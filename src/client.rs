@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, USER_AGENT};
+use serde::de::DeserializeOwned;
+use url::Url;
+
+use crate::changes::{ChannelUpdate, UpgradeReport};
+use crate::channel::Channel;
+use crate::error::{self, ErrorClass};
+use crate::nix::NixPackage;
+use crate::status::{ChannelInfo, StatusEntry};
+use crate::tree::TreeNode;
+use crate::NaiveNixosOption;
+
+/// timeout of a single request, exports of large channels take a while
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// client of the json api of a running fc-search, sharing the response types with the server
+#[derive(Debug, Clone)]
+pub struct Client {
+    base_url: Url,
+    http: reqwest::Client,
+}
+
+impl Client {
+    /// a client of the fc-search served at `base_url`, like `https://search.flyingcircus.io`
+    pub fn new(base_url: Url) -> error::Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        headers.insert(USER_AGENT, HeaderValue::from_static("fc-search"));
+        let http = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .network("could not build request client")?;
+        Ok(Self { base_url, http })
+    }
+
+    /// the update status of all channels, like the status page
+    pub async fn status(&self) -> error::Result<Vec<StatusEntry>> {
+        self.get(&["status"], &[]).await
+    }
+
+    /// the indexed revision of `channel` and the nixpkgs it was evaluated with
+    pub async fn channel_info(&self, channel: &Channel) -> error::Result<ChannelInfo> {
+        self.get(&["channel-info"], &[("channel", channel.as_str())])
+            .await
+    }
+
+    /// what the last update of `channel` changed, if it was updated since it was indexed
+    pub async fn channel_changes(&self, channel: &Channel) -> error::Result<Option<ChannelUpdate>> {
+        self.get(&["channels", channel.as_str(), "changes"], &[])
+            .await
+    }
+
+    /// the options that are removed or deprecated when upgrading from `from` to `to`
+    pub async fn upgrade_report(
+        &self,
+        from: &Channel,
+        to: &Channel,
+    ) -> error::Result<UpgradeReport> {
+        self.get(&["channels", from.as_str(), "upgrade", to.as_str()], &[])
+            .await
+    }
+
+    /// the option namespace of `channel` below `prefix`, `depth` levels deep
+    pub async fn option_tree(
+        &self,
+        channel: &Channel,
+        prefix: &str,
+        depth: usize,
+    ) -> error::Result<TreeNode> {
+        self.get(
+            &["api", "v1", "tree", channel.as_str()],
+            &[("prefix", prefix), ("depth", &depth.to_string())],
+        )
+        .await
+    }
+
+    /// all options of `channel`, keyed by their name
+    pub async fn options(
+        &self,
+        channel: &Channel,
+    ) -> error::Result<HashMap<String, NaiveNixosOption>> {
+        self.get(&["api", "v1", "export", channel.as_str(), "options"], &[])
+            .await
+    }
+
+    /// all packages of `channel`, keyed by their attribute name
+    pub async fn packages(&self, channel: &Channel) -> error::Result<HashMap<String, NixPackage>> {
+        self.get(&["api", "v1", "export", channel.as_str(), "packages"], &[])
+            .await
+    }
+
+    /// a config snippet setting `option`
+    pub async fn snippet(&self, channel: &Channel, option: &str) -> error::Result<String> {
+        let url = self.url(
+            &["api", "v1", "snippet"],
+            &[("channel", channel.as_str()), ("option", option)],
+        );
+        let response = self.send(url.clone()).await?;
+        response
+            .text()
+            .await
+            .network(format!("could not read {url}"))
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        segments: &[&str],
+        query: &[(&str, &str)],
+    ) -> error::Result<T> {
+        let url = self.url(segments, query);
+        let body = self
+            .send(url.clone())
+            .await?
+            .bytes()
+            .await
+            .network(format!("could not read {url}"))?;
+        serde_json::from_slice(&body).network(format!("unexpected response of {url}"))
+    }
+
+    async fn send(&self, url: Url) -> error::Result<reqwest::Response> {
+        self.http
+            .get(url.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .network(format!("could not fetch {url}"))
+    }
+
+    /// the endpoint at `segments` below the base url, each segment is percent-encoded
+    fn url(&self, segments: &[&str], query: &[(&str, &str)]) -> Url {
+        let mut url = self.base_url.clone();
+        if let Ok(mut path) = url.path_segments_mut() {
+            path.pop_if_empty().extend(segments);
+        }
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+        url
+    }
+}
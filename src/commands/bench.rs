@@ -0,0 +1,156 @@
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use fc_search::search::ChannelSearcher;
+use serde::Deserialize;
+
+use super::open_channel;
+
+/// Replay recorded queries against a channel in the state directory and report
+/// their latency and the ranks of the results they are expected to find
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Channel to search
+    #[arg(long)]
+    channel: String,
+
+    /// File with one json query per line, like
+    /// `{"query": "nginx", "corpus": "options", "expected": "services.nginx.enable"}`.
+    /// `corpus` defaults to options, `expected` is optional
+    queries: PathBuf,
+
+    /// How often every query is run, the latency is measured over all runs
+    #[arg(long, default_value_t = 5)]
+    runs: u32,
+
+    /// Results searched for the expected result
+    #[arg(long, default_value_t = 50)]
+    depth: u8,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Corpus {
+    #[default]
+    Options,
+    Packages,
+}
+
+#[derive(Deserialize, Debug)]
+struct RecordedQuery {
+    query: String,
+    #[serde(default)]
+    corpus: Corpus,
+    /// option name or package attribute that should rank high
+    expected: Option<String>,
+}
+
+pub fn run(args: Args, state_dir: &Path) -> anyhow::Result<()> {
+    let searcher = open_channel(state_dir, &args.channel)?;
+    let queries = read_queries(&args.queries)?;
+    anyhow::ensure!(!queries.is_empty(), "no queries to replay");
+
+    let mut latencies: Vec<(Corpus, Duration)> = Vec::new();
+    let mut ranks: Vec<(&RecordedQuery, Option<usize>)> = Vec::new();
+    for query in &queries {
+        let mut results = Vec::new();
+        for _ in 0..args.runs.max(1) {
+            let start = Instant::now();
+            results = search(&searcher, query, args.depth);
+            latencies.push((query.corpus, start.elapsed()));
+        }
+
+        if let Some(ref expected) = query.expected {
+            let rank = results.iter().position(|name| name == expected);
+            ranks.push((query, rank.map(|r| r + 1)));
+        }
+    }
+
+    for corpus in [Corpus::Options, Corpus::Packages] {
+        let mut durations = latencies
+            .iter()
+            .filter(|(c, _)| *c == corpus)
+            .map(|(_, d)| *d)
+            .collect::<Vec<_>>();
+        if durations.is_empty() {
+            continue;
+        }
+        durations.sort();
+        println!(
+            "{corpus:?}: {} searches, p50 {:?} p90 {:?} p99 {:?} max {:?}",
+            durations.len(),
+            percentile(&durations, 50),
+            percentile(&durations, 90),
+            percentile(&durations, 99),
+            durations[durations.len() - 1],
+        );
+    }
+
+    if ranks.is_empty() {
+        return Ok(());
+    }
+    let found = ranks.iter().filter(|(_, rank)| rank.is_some()).count();
+    let reciprocal_ranks: f64 = ranks
+        .iter()
+        .filter_map(|(_, rank)| *rank)
+        .map(|rank| 1. / rank as f64)
+        .sum();
+    println!(
+        "expected results: {found} of {} found, mean reciprocal rank {:.3}",
+        ranks.len(),
+        reciprocal_ranks / ranks.len() as f64
+    );
+
+    // worst ranks first, they are the ones to look at
+    ranks.sort_by_key(|(_, rank)| std::cmp::Reverse(rank.unwrap_or(usize::MAX)));
+    for (query, rank) in ranks {
+        let rank = rank.map_or_else(|| "-".to_string(), |r| r.to_string());
+        println!(
+            "{rank:>4} {:?} -> {}",
+            query.query,
+            query.expected.as_deref().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+fn read_queries(path: &Path) -> anyhow::Result<Vec<RecordedQuery>> {
+    let file =
+        std::fs::File::open(path).with_context(|| format!("could not read {}", path.display()))?;
+    let mut queries = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        queries.push(
+            serde_json::from_str(&line)
+                .with_context(|| format!("invalid query on line {}", i + 1))?,
+        );
+    }
+    Ok(queries)
+}
+
+/// names of the first `depth` results of the query
+fn search(searcher: &ChannelSearcher, query: &RecordedQuery, depth: u8) -> Vec<String> {
+    match query.corpus {
+        Corpus::Options => searcher
+            .search_options(&query.query, None, &[], depth, 1)
+            .into_iter()
+            .map(|option| option.name)
+            .collect(),
+        Corpus::Packages => searcher
+            .search_packages(&query.query, depth, 1)
+            .into_iter()
+            .map(|package| package.attribute_name)
+            .collect(),
+    }
+}
+
+/// nearest-rank percentile of sorted durations
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
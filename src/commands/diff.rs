@@ -0,0 +1,54 @@
+use std::path::Path;
+
+use fc_search::changes::ChannelChanges;
+
+use super::{indexed, open_channel};
+
+/// Show the options and packages that differ between two channels in the state directory
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Channel to compare against
+    from: String,
+
+    /// Channel that is compared
+    to: String,
+}
+
+pub fn run(args: Args, state_dir: &Path) -> anyhow::Result<()> {
+    let from = open_channel(state_dir, &args.from)?;
+    let to = open_channel(state_dir, &args.to)?;
+
+    let changes = ChannelChanges::between(
+        indexed(from.options())?,
+        indexed(from.packages())?,
+        indexed(to.options())?,
+        indexed(to.packages())?,
+    );
+
+    for name in changes.added_options {
+        println!("+ option {name}");
+    }
+    for name in changes.removed_options {
+        println!("- option {name}");
+    }
+    for name in changes.changed_options {
+        println!("~ option {name}");
+    }
+
+    for name in changes.added_packages {
+        println!("+ package {name}");
+    }
+    for name in changes.removed_packages {
+        println!("- package {name}");
+    }
+    for update in changes.updated_packages {
+        println!(
+            "~ package {} {} -> {}",
+            update.name,
+            update.from.as_deref().unwrap_or("?"),
+            update.to.as_deref().unwrap_or("?")
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use anyhow::Context;
+use fc_search::config::Config;
+use fc_search::nix;
+use fc_search::{github_api_url, hydra};
+use reqwest::Client;
+use serde::Deserialize;
+
+/// Check the environment fc-search runs in and explain how to fix what is missing
+#[derive(clap::Args, Debug)]
+pub struct Args {}
+
+/// warn if the state dir has less space left, evaluated channels need a few hundred megabytes each
+const MIN_FREE_BYTES: u64 = 5 * 1024 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct GithubRateLimit {
+    rate: GithubRate,
+}
+
+#[derive(Deserialize)]
+struct GithubRate {
+    limit: u64,
+    remaining: u64,
+}
+
+/// print the outcome of a check, returns whether it passed
+fn report(name: &str, result: anyhow::Result<String>, hint: &str) -> bool {
+    match result {
+        Ok(detail) => {
+            println!("ok    {name}: {detail}");
+            true
+        }
+        Err(e) => {
+            println!("FAIL  {name}: {e:#}");
+            println!("      {hint}");
+            false
+        }
+    }
+}
+
+async fn check_hydra(client: &Client) -> anyhow::Result<String> {
+    let base_url = hydra::base_url();
+    let response = client
+        .get(format!("{base_url}/project/flyingcircus"))
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .context("hydra is not reachable")?;
+    anyhow::ensure!(
+        response.status().is_success(),
+        "hydra responded with {}",
+        response.status()
+    );
+    Ok(format!("{base_url} is reachable"))
+}
+
+async fn check_github(client: &Client) -> anyhow::Result<String> {
+    let response = client
+        .get(format!("{}/rate_limit", github_api_url()))
+        .header("Accept", "application/json")
+        .header("User-Agent", "fc-search")
+        .send()
+        .await
+        .context("the github api is not reachable")?;
+    anyhow::ensure!(
+        response.status().is_success(),
+        "the github api responded with {}",
+        response.status()
+    );
+    check_rate_limit(&response.bytes().await?)
+}
+
+/// check the response of the github `rate_limit` endpoint for requests left
+fn check_rate_limit(body: &[u8]) -> anyhow::Result<String> {
+    let limit: GithubRateLimit =
+        serde_json::from_slice(body).context("unexpected response from the github api")?;
+    anyhow::ensure!(
+        limit.rate.remaining > 0,
+        "the github api rate limit of {} requests is exhausted",
+        limit.rate.limit
+    );
+    Ok(format!(
+        "{} of {} api requests left",
+        limit.rate.remaining, limit.rate.limit
+    ))
+}
+
+fn check_state_dir(state_dir: &Path) -> anyhow::Result<String> {
+    std::fs::create_dir_all(state_dir)
+        .with_context(|| format!("could not create {}", state_dir.display()))?;
+    tempfile::tempfile_in(state_dir)
+        .with_context(|| format!("{} is not writable", state_dir.display()))?;
+
+    let path = CString::new(state_dir.as_os_str().as_bytes())?;
+    // SAFETY: statvfs is plain old data, all zeroes is a valid value
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is a valid c string and `stat` is a properly sized buffer
+    let ret = unsafe { libc::statvfs(path.as_ptr(), &mut stat) };
+    anyhow::ensure!(
+        ret == 0,
+        "could not determine the free space: {}",
+        std::io::Error::last_os_error()
+    );
+    let free = stat.f_bavail.saturating_mul(stat.f_frsize);
+    anyhow::ensure!(
+        free >= MIN_FREE_BYTES,
+        "only {} MiB free in {}",
+        free / 1024 / 1024,
+        state_dir.display()
+    );
+    Ok(format!(
+        "{} is writable, {} GiB free",
+        state_dir.display(),
+        free / 1024 / 1024 / 1024
+    ))
+}
+
+pub async fn run(_args: Args, state_dir: Option<&Path>, config: &Config) -> anyhow::Result<()> {
+    let mut healthy = report(
+        "nix",
+        nix::check_nix(&config.nix).map_err(anyhow::Error::from),
+        "install nix and make sure `nix` is in PATH. \
+         flakes are enabled by fc-search itself, check the `nix.settings` of the config",
+    );
+
+    let client = Client::builder().build()?;
+    healthy &= report(
+        "hydra",
+        check_hydra(&client).await,
+        "branches are discovered on hydra, check the network and proxy settings",
+    );
+    healthy &= report(
+        "github",
+        check_github(&client).await,
+        "the latest revisions are looked up on github, check the network \
+         or wait for the rate limit to reset",
+    );
+
+    match state_dir {
+        Some(state_dir) => {
+            healthy &= report(
+                "state dir",
+                check_state_dir(state_dir),
+                "indices are written to the state dir, fix its permissions or free up space",
+            );
+        }
+        None => println!("skip  state dir: no --state-dir given"),
+    }
+
+    anyhow::ensure!(healthy, "some checks failed");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limit_with_requests_left_passes() {
+        let body = br#"{"resources": {}, "rate": {"limit": 60, "remaining": 59, "reset": 0}}"#;
+        assert_eq!(
+            check_rate_limit(body).unwrap(),
+            "59 of 60 api requests left"
+        );
+    }
+
+    #[test]
+    fn exhausted_rate_limit_fails() {
+        let body = br#"{"rate": {"limit": 60, "remaining": 0}}"#;
+        let e = check_rate_limit(body).unwrap_err();
+        assert!(e.to_string().contains("60 requests is exhausted"));
+    }
+
+    #[test]
+    fn unexpected_response_fails() {
+        assert!(check_rate_limit(b"<html></html>").is_err());
+        assert!(check_rate_limit(br#"{"message": "Not Found"}"#).is_err());
+    }
+
+    #[test]
+    fn report_returns_whether_the_check_passed() {
+        assert!(report("check", Ok("fine".to_string()), "hint"));
+        assert!(!report("check", Err(anyhow::anyhow!("broken")), "hint"));
+    }
+
+    #[test]
+    fn writable_state_dir_passes_unless_the_disk_is_full() {
+        let dir = tempfile::tempdir().unwrap();
+        match check_state_dir(dir.path()) {
+            Ok(detail) => assert!(detail.contains("is writable")),
+            Err(e) => assert!(e.to_string().contains("MiB free")),
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use super::{indexed, open_channel};
+
+/// Dump the complete options or packages of a channel in the state directory
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Channel to export
+    #[arg(long)]
+    channel: String,
+
+    /// Data to export
+    #[arg(long, value_enum, default_value_t = Corpus::Options)]
+    corpus: Corpus,
+
+    #[arg(long, value_enum, default_value_t = Format::Json)]
+    format: Format,
+
+    /// File to write to instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Corpus {
+    Options,
+    Packages,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// a single json object keyed by option name or attribute path
+    #[default]
+    Json,
+    /// one json object per line
+    Jsonl,
+    /// one row per entry, nested values are written as json
+    Csv,
+}
+
+pub fn run(args: Args, state_dir: &Path) -> anyhow::Result<()> {
+    let searcher = open_channel(state_dir, &args.channel)?;
+
+    let mut out: Box<dyn Write> = match args.output {
+        Some(ref path) => {
+            Box::new(BufWriter::new(File::create(path).with_context(|| {
+                format!("could not create {}", path.display())
+            })?))
+        }
+        None => Box::new(BufWriter::new(std::io::stdout().lock())),
+    };
+
+    match args.corpus {
+        Corpus::Options => write_entries(
+            &mut out,
+            indexed(searcher.options())?,
+            args.corpus,
+            args.format,
+        )?,
+        Corpus::Packages => write_entries(
+            &mut out,
+            indexed(searcher.packages())?,
+            args.corpus,
+            args.format,
+        )?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn write_entries<T: Serialize>(
+    out: &mut dyn Write,
+    entries: &HashMap<String, T>,
+    corpus: Corpus,
+    format: Format,
+) -> anyhow::Result<()> {
+    // sorted for reproducible exports
+    let entries: BTreeMap<_, _> = entries.iter().collect();
+
+    let mut encoder = EntryEncoder::new(format, corpus);
+    for (key, entry) in entries {
+        out.write_all(&encoder.entry(key, entry)?)?;
+    }
+    out.write_all(&encoder.finish())?;
+    Ok(())
+}
+
+impl Corpus {
+    /// the columns of a csv export, the same for every export of the corpus
+    /// no matter which fields its first entry has
+    pub fn csv_columns(&self) -> &'static [&'static str] {
+        match self {
+            Corpus::Options => &[
+                "name",
+                "declarations",
+                "description",
+                "default",
+                "example",
+                "option_type",
+                "read_only",
+                "related_packages",
+                "set_by_roles",
+            ],
+            Corpus::Packages => &[
+                "attribute_name",
+                "name",
+                "version",
+                "description",
+                "camelCase",
+                "default_output",
+                "outputs",
+                "license",
+                "homepage",
+                "maintainers",
+                "systems",
+                "knownVulnerabilities",
+            ],
+        }
+    }
+}
+
+impl Format {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Jsonl => "application/x-ndjson",
+            Format::Csv => "text/csv",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Jsonl => "jsonl",
+            Format::Csv => "csv",
+        }
+    }
+}
+
+/// encodes the entries of an export one at a time, so they can be written as they are encoded
+pub struct EntryEncoder {
+    format: Format,
+    corpus: Corpus,
+    entries: usize,
+}
+
+impl EntryEncoder {
+    pub fn new(format: Format, corpus: Corpus) -> Self {
+        Self {
+            format,
+            corpus,
+            entries: 0,
+        }
+    }
+
+    /// the encoded entry, preceded by the start of the export for the first one
+    pub fn entry<T: Serialize>(&mut self, key: &str, entry: &T) -> anyhow::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        match self.format {
+            Format::Json => {
+                out.push(if self.entries == 0 { b'{' } else { b',' });
+                serde_json::to_writer(&mut out, key)?;
+                out.push(b':');
+                serde_json::to_writer(&mut out, entry)?;
+            }
+            Format::Jsonl => {
+                serde_json::to_writer(&mut out, entry)?;
+                writeln!(out)?;
+            }
+            Format::Csv => {
+                let serde_json::Value::Object(fields) = serde_json::to_value(entry)? else {
+                    anyhow::bail!("only structs can be exported as csv");
+                };
+                let columns = self.corpus.csv_columns();
+                if self.entries == 0 {
+                    write_csv_row(&mut out, columns.iter().copied())?;
+                }
+                // fields that are left out of an entry are empty
+                let values: Vec<String> = columns
+                    .iter()
+                    .map(|column| fields.get(*column).map(csv_value).unwrap_or_default())
+                    .collect();
+                write_csv_row(&mut out, values.iter().map(String::as_str))?;
+            }
+        }
+        self.entries += 1;
+        Ok(out)
+    }
+
+    /// the end of the export after the last entry
+    pub fn finish(&self) -> Vec<u8> {
+        match self.format {
+            Format::Json if self.entries == 0 => b"{}\n".to_vec(),
+            Format::Json => b"}\n".to_vec(),
+            Format::Jsonl | Format::Csv => Vec::new(),
+        }
+    }
+}
+
+/// strings are written as they are, everything else as json
+fn csv_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// a row of a rfc 4180 csv file
+fn write_csv_row<'a>(
+    out: &mut dyn Write,
+    fields: impl Iterator<Item = &'a str>,
+) -> anyhow::Result<()> {
+    let row = fields
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    write!(out, "{row}\r\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(fields: &[&str]) -> String {
+        let mut out = Vec::new();
+        write_csv_row(&mut out, fields.iter().copied()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(row(&["plain", ""]), "plain,\r\n");
+        assert_eq!(row(&["a,b"]), "\"a,b\"\r\n");
+        assert_eq!(row(&["say \"hi\""]), "\"say \"\"hi\"\"\"\r\n");
+        assert_eq!(row(&["two\nlines", "cr\r"]), "\"two\nlines\",\"cr\r\"\r\n");
+    }
+
+    #[test]
+    fn csv_header_does_not_depend_on_the_first_entry() {
+        #[derive(Serialize)]
+        struct Partial {
+            name: &'static str,
+            read_only: bool,
+        }
+
+        let mut encoder = EntryEncoder::new(Format::Csv, Corpus::Options);
+        let out = encoder
+            .entry(
+                "a",
+                &Partial {
+                    name: "a",
+                    read_only: true,
+                },
+            )
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let mut lines = out.split("\r\n");
+        assert_eq!(
+            lines.next().unwrap(),
+            Corpus::Options.csv_columns().join(",")
+        );
+        assert_eq!(lines.next().unwrap(), "a,,,,,,true,,");
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use fc_search::config::{Config, ExtraFlake};
+use fc_search::hydra::get_fcio_flake_uris;
+use fc_search::nix::build_options_for_fcio_branch;
+use fc_search::progress::ProgressReporter;
+use fc_search::search::ChannelSearcher;
+use fc_search::state::StateLock;
+use fc_search::storage::{self, ObjectStorage};
+use fc_search::{get_extra_flakes, get_upstream_flakes, Flake, FlakeRev};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use super::split_keys;
+
+/// Build the option and package indices of fc-nixos branches into the state directory
+/// without running the web frontend
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Branch or extra flake channel to index, can be given multiple times.
+    /// Defaults to the branches discovered on hydra, all extra flakes and upstream channels
+    #[arg(long = "branch")]
+    branches: Vec<String>,
+
+    /// Index the nixos modules of a flake as an extra channel,
+    /// given as `channel=github:owner/repo/branch`. Implies `--branch`
+    #[arg(long = "flake", value_parser = parse_flake)]
+    flakes: Vec<ExtraFlake>,
+
+    /// Index a branch at a fixed revision instead of its latest one,
+    /// given as `branch=rev`. Implies `--branch`
+    #[arg(long = "rev", value_parser = parse_rev)]
+    revs: Vec<(String, String)>,
+
+    /// Evaluate the channels and report how they differ from the indexed ones
+    /// without writing to the state directory
+    #[arg(long)]
+    dry_run: bool,
+}
+
+fn parse_rev(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(branch, rev)| (branch.to_string(), rev.to_string()))
+        .ok_or_else(|| format!("expected `branch=rev`, got `{s}`"))
+}
+
+fn parse_flake(s: &str) -> Result<ExtraFlake, String> {
+    let (channel, url) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `channel=url`, got `{s}`"))?;
+    let flake = ExtraFlake {
+        channel: channel.to_string(),
+        url: Some(url.to_string()),
+        owner: None,
+        repo: None,
+        branch: None,
+    };
+    flake.github().map_err(|e| e.to_string())?;
+    Ok(flake)
+}
+
+/// the update and retire settings of `config` are not used
+pub async fn run(args: Args, state_dir: &Path, mut config: Config) -> anyhow::Result<()> {
+    // a dry run only reads the state dir and may run next to the server
+    let _state_lock = if args.dry_run {
+        None
+    } else {
+        Some(StateLock::try_acquire(state_dir)?)
+    };
+
+    let mut branches = args.branches;
+    for flake in args.flakes {
+        branches.push(flake.channel.clone());
+        config.flakes.retain(|f| f.channel != flake.channel);
+        config.flakes.push(flake);
+    }
+
+    let revs: HashMap<String, String> = args.revs.into_iter().collect();
+    for branch in revs.keys() {
+        if !branches.contains(branch) {
+            branches.push(branch.clone());
+        }
+    }
+
+    let flakes = if branches.is_empty() {
+        let mut flakes = get_fcio_flake_uris().await?;
+        flakes.extend(get_extra_flakes(&config.flakes).await);
+        flakes.extend(get_upstream_flakes(&config.upstream).await);
+        flakes
+    } else {
+        let mut flakes = Vec::new();
+        for branch in &branches {
+            let flake = match config.flakes.iter().find(|f| &f.channel == branch) {
+                Some(extra) => Flake::extra(extra).await?,
+                None => Flake::new("flyingcircusio", "fc-nixos", branch).await?,
+            };
+            flakes.push(flake);
+        }
+        flakes
+    };
+
+    if args.dry_run {
+        return dry_run(flakes, &revs, state_dir, &config);
+    }
+
+    let storage = config
+        .storage
+        .backend
+        .as_ref()
+        .map(ObjectStorage::new)
+        .transpose()?;
+
+    // the command runs until every channel is indexed
+    let cancel = CancellationToken::new();
+    let mut failed = Vec::new();
+    for flake in flakes {
+        let mut searcher = ChannelSearcher::in_statedir(state_dir, &flake);
+        let channel = flake.channel().to_string();
+        let result = match revs.get(&channel) {
+            Some(rev) => searcher.update_to(FlakeRev::Specific(rev.clone()), &config.nix, &cancel),
+            None => searcher.update(&config.nix, &cancel).await,
+        };
+
+        if result.is_ok() {
+            if let Err(e) = searcher.update_embeddings(&config.embeddings).await {
+                warn!(
+                    "could not compute the embeddings of {}: {}",
+                    channel,
+                    e.report()
+                );
+            }
+        }
+
+        if let (Ok(()), Some(storage)) = (&result, &storage) {
+            if let Err(e) = storage::publish(storage, state_dir, &channel).await {
+                warn!("could not publish {}: {:#}", channel, e);
+            }
+        }
+
+        match result {
+            Ok(()) => info!("indexed branch {} at {:?}", channel, searcher.flake.rev),
+            Err(e) => {
+                error!("error indexing branch {}: {}", channel, e.report());
+                failed.push(channel);
+            }
+        }
+    }
+
+    anyhow::ensure!(failed.is_empty(), "failed to index {:?}", failed);
+    Ok(())
+}
+
+/// evaluate the flakes and print how the result differs from the indexed channels
+fn dry_run(
+    flakes: Vec<Flake>,
+    revs: &HashMap<String, String>,
+    state_dir: &Path,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let empty = (HashMap::new(), HashMap::new());
+    let mut failed = Vec::new();
+    for mut flake in flakes {
+        let channel = flake.channel().to_string();
+        if let Some(rev) = revs.get(&channel) {
+            flake.rev = FlakeRev::Specific(rev.clone());
+        }
+
+        let build = match build_options_for_fcio_branch(
+            &flake,
+            &config.nix,
+            &ProgressReporter::default(),
+            &CancellationToken::new(),
+        ) {
+            Ok(build) => build,
+            Err(e) => {
+                error!("error evaluating branch {}: {}", channel, e.report());
+                failed.push(channel);
+                continue;
+            }
+        };
+
+        let indexed = ChannelSearcher::open_from_statedir(state_dir, &channel).ok();
+        match indexed {
+            Some(ref indexed) => println!("{channel}: {} -> {}", indexed.flake.rev, flake.rev),
+            None => println!("{channel}: not indexed yet -> {}", flake.rev),
+        }
+        let (old_options, old_packages) = indexed
+            .as_ref()
+            .and_then(|i| i.options().zip(i.packages()))
+            .unwrap_or((&empty.0, &empty.1));
+
+        let (removed, added, _) = split_keys(old_options, &build.options);
+        println!(
+            "  options: {} -> {} (+{} -{})",
+            old_options.len(),
+            build.options.len(),
+            added.len(),
+            removed.len()
+        );
+        for name in added {
+            println!("  + option {name}");
+        }
+        for name in removed {
+            println!("  - option {name}");
+        }
+
+        let (removed, added, _) = split_keys(old_packages, &build.packages);
+        println!(
+            "  packages: {} -> {} (+{} -{})",
+            old_packages.len(),
+            build.packages.len(),
+            added.len(),
+            removed.len()
+        );
+        if !build.warnings.is_empty() {
+            println!("  {} evaluation warnings", build.warnings.len());
+        }
+    }
+
+    anyhow::ensure!(failed.is_empty(), "failed to evaluate {:?}", failed);
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+use anyhow::Context;
+use fc_search::search::ChannelSearcher;
+
+pub mod bench;
+pub mod diff;
+pub mod doctor;
+pub mod export;
+pub mod index;
+pub mod search;
+pub mod site;
+
+/// open a channel that was indexed into the state dir before
+fn open_channel(state_dir: &Path, channel: &str) -> anyhow::Result<ChannelSearcher> {
+    ChannelSearcher::open_from_statedir(state_dir, channel).with_context(|| {
+        format!(
+            "channel {channel} is not indexed in {}",
+            state_dir.display()
+        )
+    })
+}
+
+/// entries of a channel opened with [`open_channel`], which is always indexed
+fn indexed<T>(entries: Option<T>) -> anyhow::Result<T> {
+    entries.context("the channel is not indexed")
+}
+
+/// names of the entries only in `from`, only in `to` and in both
+fn split_keys<'a, T>(
+    from: &'a HashMap<String, T>,
+    to: &'a HashMap<String, T>,
+) -> (
+    BTreeSet<&'a String>,
+    BTreeSet<&'a String>,
+    BTreeSet<&'a String>,
+) {
+    let from_keys: BTreeSet<_> = from.keys().collect();
+    let to_keys: BTreeSet<_> = to.keys().collect();
+    (
+        &from_keys - &to_keys,
+        &to_keys - &from_keys,
+        &from_keys & &to_keys,
+    )
+}
@@ -0,0 +1,98 @@
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use super::open_channel;
+
+/// Search a channel in the state directory without starting the web frontend
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Channel to search
+    #[arg(long)]
+    channel: String,
+
+    /// Corpus to search in
+    #[arg(long, value_enum, default_value_t = Corpus::Options)]
+    corpus: Corpus,
+
+    /// Number of results per page
+    #[arg(short, long, default_value_t = 10)]
+    limit: u8,
+
+    /// Page of the results to print
+    #[arg(long, default_value_t = 1)]
+    page: u8,
+
+    /// Print the results as a json array instead of one line per result
+    #[arg(long)]
+    json: bool,
+
+    query: String,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Corpus {
+    Options,
+    Packages,
+    HomeManager,
+    Changelog,
+}
+
+fn print_results<T: Serialize>(
+    results: Vec<T>,
+    json: bool,
+    line: impl Fn(&T) -> String,
+) -> anyhow::Result<()> {
+    if json {
+        serde_json::to_writer_pretty(std::io::stdout().lock(), &results)?;
+        println!();
+    } else {
+        for result in &results {
+            println!("{}", line(result));
+        }
+    }
+    Ok(())
+}
+
+pub fn run(args: Args, state_dir: &Path) -> anyhow::Result<()> {
+    let searcher = open_channel(state_dir, &args.channel)?;
+    let (query, limit, page) = (args.query.as_str(), args.limit, args.page);
+
+    match args.corpus {
+        Corpus::Options => print_results(
+            searcher.search_options(query, None, &[], limit, page),
+            args.json,
+            |option| format!("{}: {}", option.name, option.option_type),
+        ),
+        Corpus::Packages => print_results(
+            searcher.search_packages(query, limit, page),
+            args.json,
+            |package| {
+                format!(
+                    "{} {}: {}",
+                    package.attribute_name,
+                    package.version.as_deref().unwrap_or("-"),
+                    package.description.as_deref().unwrap_or_default()
+                )
+            },
+        ),
+        Corpus::HomeManager => {
+            anyhow::ensure!(
+                searcher.has_home_manager(),
+                "no home-manager options were evaluated for channel {}",
+                args.channel
+            );
+            print_results(
+                searcher.search_home_manager(query, limit, page),
+                args.json,
+                |option| format!("{}: {}", option.name, option.option_type),
+            )
+        }
+        Corpus::Changelog => print_results(
+            searcher.search_changelog(query, limit, page),
+            args.json,
+            |entry| entry.title.clone(),
+        ),
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use askama::Template;
+use fc_search::nix::NixPackage;
+use fc_search::{Flake, NaiveNixosOption, NixHtml};
+use serde::Serialize;
+
+use super::{indexed, open_channel};
+use crate::backend::Asset;
+
+/// Render the option and package pages of a channel in the state directory into a static site,
+/// searchable in the browser with a pre-built index, to publish a snapshot without the daemon
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Channel to export
+    #[arg(long)]
+    channel: String,
+
+    /// Directory to write the site to, existing pages are overwritten
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+/// list of all pages loaded by the search of the index page
+const SEARCH_INDEX_FILE: &str = "search-index.json";
+
+/// descriptions in the search index are cut to this many characters
+const INDEX_DESCRIPTION_LENGTH: usize = 200;
+
+/// an entry of the search index
+#[derive(Serialize)]
+struct IndexEntry<'a> {
+    name: &'a str,
+    kind: &'static str,
+    description: String,
+    /// page of the entry relative to the index page
+    url: String,
+}
+
+#[derive(Template)]
+#[template(path = "site/index.html")]
+struct IndexTemplate<'a> {
+    /// path of the site root relative to the page
+    root: &'static str,
+    channel: &'a str,
+    flake: &'a Flake,
+    n_options: usize,
+    n_packages: usize,
+    search_index: &'static str,
+}
+
+#[derive(Template)]
+#[template(path = "site/option.html")]
+struct OptionTemplate<'a> {
+    root: &'static str,
+    channel: &'a str,
+    item: &'a NaiveNixosOption,
+    sub_options: Vec<NaiveNixosOption>,
+}
+
+impl OptionTemplate<'_> {
+    fn page_href(&self, name: &str) -> String {
+        page_href(name)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "site/package.html")]
+struct PackageTemplate<'a> {
+    root: &'static str,
+    channel: &'a str,
+    item: &'a NixPackage,
+}
+
+pub fn run(args: Args, state_dir: &Path) -> anyhow::Result<()> {
+    let searcher = open_channel(state_dir, &args.channel)?;
+    // sorted for reproducible search indices
+    let options: BTreeMap<_, _> = indexed(searcher.options())?.iter().collect();
+    let packages: BTreeMap<_, _> = indexed(searcher.packages())?.iter().collect();
+
+    let options_dir = args.output.join("options");
+    let packages_dir = args.output.join("packages");
+    let assets_dir = args.output.join("assets");
+    for dir in [&options_dir, &packages_dir, &assets_dir] {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("could not create {}", dir.display()))?;
+    }
+
+    let mut index = Vec::with_capacity(options.len() + packages.len());
+    for (name, option) in &options {
+        let sub_options = if option.has_sub_options() {
+            searcher.sub_options(name)
+        } else {
+            Vec::new()
+        };
+        write_page(
+            &options_dir.join(page_file(name)),
+            &OptionTemplate {
+                root: "../",
+                channel: &args.channel,
+                item: option,
+                sub_options,
+            },
+        )?;
+        index.push(IndexEntry {
+            name,
+            kind: "option",
+            description: index_description(option.description.text()),
+            url: format!("options/{}", page_href(name)),
+        });
+    }
+    for (name, package) in &packages {
+        write_page(
+            &packages_dir.join(page_file(name)),
+            &PackageTemplate {
+                root: "../",
+                channel: &args.channel,
+                item: package,
+            },
+        )?;
+        index.push(IndexEntry {
+            name,
+            kind: "package",
+            description: index_description(package.description.as_deref().unwrap_or_default()),
+            url: format!("packages/{}", page_href(name)),
+        });
+    }
+
+    let index_path = args.output.join(SEARCH_INDEX_FILE);
+    std::fs::write(&index_path, serde_json::to_vec(&index)?)
+        .with_context(|| format!("could not write {}", index_path.display()))?;
+    write_page(
+        &args.output.join("index.html"),
+        &IndexTemplate {
+            root: "",
+            channel: &args.channel,
+            flake: &searcher.flake,
+            n_options: options.len(),
+            n_packages: packages.len(),
+            search_index: SEARCH_INDEX_FILE,
+        },
+    )?;
+
+    let stylesheet =
+        Asset::get("tailwind.css").context("the stylesheet is missing in this build")?;
+    std::fs::write(assets_dir.join("tailwind.css"), stylesheet.data)
+        .context("could not write the stylesheet")?;
+
+    println!(
+        "wrote {} option and {} package pages of {} to {}",
+        options.len(),
+        packages.len(),
+        args.channel,
+        args.output.display()
+    );
+    Ok(())
+}
+
+fn write_page(path: &Path, page: &impl Template) -> anyhow::Result<()> {
+    let html = page
+        .render()
+        .with_context(|| format!("could not render {}", path.display()))?;
+    std::fs::write(path, html).with_context(|| format!("could not write {}", path.display()))
+}
+
+/// file name of the page of an option or package, names like `services.<name>.enable`
+/// are percent-encoded so every name maps to a distinct and portable file name
+fn page_file(name: &str) -> String {
+    let encoded: String = url::form_urlencoded::byte_serialize(name.as_bytes()).collect();
+    format!("{encoded}.html")
+}
+
+/// link to the page of `name`, relative to the directory of the page.
+/// the file name is encoded once more since web servers decode the path once
+fn page_href(name: &str) -> String {
+    url::form_urlencoded::byte_serialize(page_file(name).as_bytes()).collect()
+}
+
+/// the start of a description, without line breaks
+fn index_description(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(INDEX_DESCRIPTION_LENGTH)
+        .collect()
+}
@@ -0,0 +1,798 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use url::Url;
+
+/// settings read from the optional toml config file
+#[derive(Debug, Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub update: UpdateConfig,
+    pub retire: RetireConfig,
+    pub nix: NixConfig,
+
+    /// branches that are always indexed at a fixed revision instead of their latest one
+    pub pins: HashMap<String, String>,
+
+    /// flakes with nixos modules that are served as additional channels
+    pub flakes: Vec<ExtraFlake>,
+
+    pub upstream: UpstreamConfig,
+
+    pub ranking: RankingConfig,
+
+    pub embeddings: EmbeddingsConfig,
+
+    pub hydra: HydraConfig,
+
+    pub github: GithubConfig,
+
+    /// log filter in the syntax of `RUST_LOG`, e.g. `fc_search=debug`.
+    /// `RUST_LOG` takes precedence on startup
+    pub log_level: Option<String>,
+
+    pub admin: AdminConfig,
+
+    /// path prefix like `/search` to serve the frontend under, for mounting it
+    /// as a sub-path of another site behind a reverse proxy. only read on startup
+    pub base_path: String,
+
+    pub proxy: ProxyConfig,
+
+    /// urls that are notified about channel events
+    pub webhooks: Vec<WebhookConfig>,
+
+    pub storage: StorageConfig,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read config file {}", path.display()))?;
+        let config: Self = toml::from_str(&content).context("could not parse config file")?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut channels = HashSet::new();
+        for flake in &self.flakes {
+            flake
+                .github()
+                .with_context(|| format!("invalid flake for channel {}", flake.channel))?;
+            anyhow::ensure!(
+                channels.insert(flake.channel.as_str()),
+                "channel {} is configured more than once",
+                flake.channel
+            );
+        }
+        anyhow::ensure!(
+            self.base_path.is_empty()
+                || (self.base_path.starts_with('/') && !self.base_path.ends_with('/')),
+            "the base path {:?} must start and must not end with a `/`",
+            self.base_path
+        );
+        anyhow::ensure!(
+            self.hydra
+                .repository
+                .split_once('/')
+                .is_some_and(|(owner, repo)| !owner.is_empty()
+                    && !repo.is_empty()
+                    && !repo.contains('/')),
+            "the hydra repository {:?} must be given as `owner/repo`",
+            self.hydra.repository
+        );
+        for webhook in &self.webhooks {
+            anyhow::ensure!(
+                webhook.format != WebhookFormat::Matrix
+                    || (webhook.room.is_some() && webhook.token.is_some()),
+                "the matrix webhook {} requires a `room` and a `token`",
+                webhook.url
+            );
+        }
+        Ok(())
+    }
+}
+
+/// upstream nixos channels that are indexed alongside the fc channels for comparison
+#[derive(Debug, Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamConfig {
+    pub enable: bool,
+
+    /// channels to index, defaults to `nixos-unstable` and the current stable release
+    pub channels: Option<Vec<String>>,
+
+    /// mark the results of fc channels that also exist on search.nixos.org,
+    /// the option and package lists of their nixos release are fetched from channels.nixos.org
+    pub cross_reference: bool,
+}
+
+/// an additional flake, given either as `url` or as `owner`, `repo` and `branch`.
+/// only flakes hosted on github are supported
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ExtraFlake {
+    /// name the channel is listed under
+    pub channel: String,
+
+    /// flake url like `github:owner/repo/branch` or `https://github.com/owner/repo/tree/branch`
+    pub url: Option<String>,
+
+    pub owner: Option<String>,
+    pub repo: Option<String>,
+    pub branch: Option<String>,
+}
+
+impl ExtraFlake {
+    /// owner, repository and branch of the flake on github
+    pub fn github(&self) -> anyhow::Result<(String, String, String)> {
+        match (&self.url, &self.owner, &self.repo, &self.branch) {
+            (Some(url), None, None, None) => parse_github_url(url),
+            (None, Some(owner), Some(repo), Some(branch)) => {
+                Ok((owner.clone(), repo.clone(), branch.clone()))
+            }
+            _ => anyhow::bail!("expected either `url` or all of `owner`, `repo` and `branch`"),
+        }
+    }
+}
+
+fn parse_github_url(url: &str) -> anyhow::Result<(String, String, String)> {
+    let path = url
+        .strip_prefix("github:")
+        .or_else(|| {
+            url.strip_prefix("https://github.com/")
+                .map(|p| p.trim_end_matches('/'))
+        })
+        .with_context(|| format!("{url} is not a github flake url"))?;
+
+    let parts = path.split('/').collect::<Vec<_>>();
+    match parts.as_slice() {
+        [owner, repo, branch] | [owner, repo, "tree", branch] => {
+            Ok((owner.to_string(), repo.to_string(), branch.to_string()))
+        }
+        _ => anyhow::bail!("{url} does not name a branch, expected `github:owner/repo/branch`"),
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpdateConfig {
+    /// update interval of channels without a matching schedule,
+    /// also used for discovering new branches on hydra
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+
+    /// the first schedule matching a branch determines its update interval
+    pub schedules: Vec<Schedule>,
+
+    /// delay before retrying a failed update, doubled on every consecutive failure
+    #[serde(with = "humantime_serde")]
+    pub retry_initial: Duration,
+
+    /// upper bound for the delay between retries
+    #[serde(with = "humantime_serde")]
+    pub retry_max: Duration,
+
+    /// number of consecutive failed updates after which the health check fails
+    pub max_failures: u32,
+}
+
+impl Default for UpdateConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_hours(5),
+            schedules: Vec::new(),
+            retry_initial: Duration::from_mins(5),
+            retry_max: Duration::from_hours(1),
+            max_failures: 5,
+        }
+    }
+}
+
+impl UpdateConfig {
+    pub fn interval_for(&self, branch: &str) -> Duration {
+        self.schedules
+            .iter()
+            .find(|s| glob_matches(&s.branches, branch))
+            .map(|s| s.interval)
+            .unwrap_or(self.interval)
+    }
+
+    /// capped exponential backoff after `failures` consecutive failed updates
+    pub fn retry_delay(&self, branch: &str, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.retry_initial
+            .saturating_mul(factor)
+            .min(self.retry_max)
+            .min(self.interval_for(branch))
+    }
+}
+
+/// settings for the nix invocations that evaluate a channel
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct NixConfig {
+    /// evaluations running longer than this are killed
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+
+    pub limits: ResourceLimits,
+
+    /// also evaluate the home-manager options matching the nixpkgs of fc-nixos branches
+    pub home_manager: bool,
+
+    /// nix settings passed as `--option name value`, e.g. `builders`, `substituters`
+    /// or `max-jobs = "0"` to offload the evaluation to a remote builder
+    pub settings: BTreeMap<String, String>,
+
+    /// systems to evaluate packages for
+    pub systems: Vec<String>,
+
+    pub gc: GcConfig,
+}
+
+impl Default for NixConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_hours(1),
+            limits: ResourceLimits::default(),
+            home_manager: false,
+            settings: BTreeMap::new(),
+            systems: vec!["x86_64-linux".to_string(), "aarch64-linux".to_string()],
+            gc: GcConfig::default(),
+        }
+    }
+}
+
+/// resource limits for the evaluation, enforced by running nix in a transient
+/// systemd scope. values use the syntax of the systemd properties
+#[derive(Debug, Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResourceLimits {
+    /// `MemoryMax` of the scope, e.g. `8G`
+    pub memory_max: Option<String>,
+
+    /// `CPUQuota` of the scope, e.g. `200%`
+    pub cpu_quota: Option<String>,
+
+    /// create the scope in the user's service manager instead of the system one
+    pub user: bool,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        self.memory_max.is_none() && self.cpu_quota.is_none()
+    }
+}
+
+/// garbage collection of the nix store, the build outputs are only referenced while
+/// they are read, so repeated evaluations do not fill the store
+#[derive(Debug, Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct GcConfig {
+    /// run `nix store gc` after every evaluation
+    pub enable: bool,
+
+    /// stop after freeing this many bytes, like `10G`. everything unreferenced is deleted without it
+    pub max: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetireConfig {
+    /// how long a branch that disappeared from hydra stays available
+    #[serde(with = "humantime_serde")]
+    pub grace_period: Duration,
+
+    /// delete the state of a branch once its grace period is over
+    pub delete_state: bool,
+}
+
+impl Default for RetireConfig {
+    fn default() -> Self {
+        Self {
+            grace_period: Duration::from_days(14),
+            delete_state: false,
+        }
+    }
+}
+
+/// an url that is sent a json payload or a chat message when one of its events happens to a channel
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// the receiver, for matrix the url of the homeserver
+    pub url: Url,
+
+    #[serde(default)]
+    pub format: WebhookFormat,
+
+    /// events to send, all of them if empty
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+
+    /// only send events of channels matching a pattern like `*-production`
+    pub channels: Option<String>,
+
+    /// consecutive failed updates of a channel that trigger the `failing` event,
+    /// defaults to `update.max_failures`
+    pub failures: Option<u32>,
+
+    /// matrix room id like `!abc:example.org`
+    pub room: Option<String>,
+
+    /// sent as bearer token, the access token of the bot user for matrix
+    pub token: Option<String>,
+}
+
+impl WebhookConfig {
+    /// whether the webhook is subscribed to `event` of `channel`
+    pub fn wants(&self, event: WebhookEvent, channel: &str) -> bool {
+        (self.events.is_empty() || self.events.contains(&event))
+            && self
+                .channels
+                .as_deref()
+                .is_none_or(|pattern| glob_matches(pattern, channel))
+    }
+
+    /// whether `failures` consecutive failed updates trigger the `failing` event
+    pub fn failing_after(&self, failures: u32, max_failures: u32) -> bool {
+        failures == self.failures.unwrap_or(max_failures)
+    }
+}
+
+/// what is sent to a webhook
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// the event as json object
+    #[default]
+    Json,
+    /// a message for a slack incoming webhook, also understood by mattermost and others
+    Slack,
+    /// a notice sent to a room with the matrix client-server api
+    Matrix,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// a channel was indexed, for the first time or at a new revision
+    Indexed,
+    /// an indexed channel moved to another revision
+    RevisionChanged,
+    /// a channel failed to update a number of times in a row, see [`WebhookConfig::failures`]
+    Failing,
+}
+
+/// the hydra the fc-nixos branches are discovered on and what is shown about their builds
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HydraConfig {
+    /// a self-hosted hydra building fc-nixos or a fork of it
+    pub base_url: Url,
+
+    /// `owner/repo` on github whose branches are indexed, jobsets building other
+    /// repositories are skipped
+    pub repository: String,
+
+    /// job like `release` whose build status in the evaluation of the indexed revision is
+    /// polled on every update. only the evaluation is linked without one
+    pub build_job: Option<String>,
+}
+
+impl Default for HydraConfig {
+    fn default() -> Self {
+        Self {
+            base_url: Url::parse("https://hydra.flyingcircus.io").expect("valid default url"),
+            repository: "flyingcircusio/fc-nixos".to_string(),
+            build_job: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct GithubConfig {
+    /// api the latest revisions of the branches are fetched from, like the `/api/v3` of a
+    /// github enterprise server
+    pub api_url: Url,
+}
+
+impl Default for GithubConfig {
+    fn default() -> Self {
+        Self {
+            api_url: Url::parse("https://api.github.com").expect("valid default url"),
+        }
+    }
+}
+
+/// shared storage the indexed channels are published to, so servers started with `--pull`
+/// can serve them without evaluating anything themselves
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// nothing is published without one
+    pub backend: Option<StorageBackend>,
+
+    /// how often servers started with `--pull` look for newly published channels and
+    /// servers started with `--watch` for new index generations in the state dir
+    #[serde(with = "humantime_serde")]
+    pub poll_interval: Duration,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: None,
+            poll_interval: Duration::from_mins(1),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// a directory, e.g. on a network share
+    Dir {
+        path: PathBuf,
+    },
+    S3(S3Config),
+}
+
+/// an s3 compatible bucket, like minio or ceph
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct S3Config {
+    /// like `https://s3.example.org`, buckets are addressed in the path
+    pub endpoint: Url,
+
+    pub bucket: String,
+
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+
+    /// prepended to all keys, like `fc-search/`
+    #[serde(default)]
+    pub prefix: String,
+
+    /// taken from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` if not set
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+/// access to the `/admin` endpoints of the server
+#[derive(Debug, Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// bearer token the admin endpoints require, they are disabled without one
+    pub token: Option<String>,
+}
+
+/// the reverse proxies and load balancers in front of the server
+#[derive(Debug, Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// addresses or networks like `10.0.0.0/8` that are trusted to name the client in the
+    /// `Forwarded` or `X-Forwarded-For` header. connections over a unix socket are always trusted
+    pub trusted: Vec<IpNetwork>,
+}
+
+impl ProxyConfig {
+    pub fn trusts(&self, ip: IpAddr) -> bool {
+        self.trusted.iter().any(|network| network.contains(ip))
+    }
+}
+
+/// an ip network like `10.0.0.0/8` or `2001:db8::/32`, a single address without a prefix length
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_length: u8,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // clients of dual-stack sockets show up as ipv4-mapped ipv6 addresses
+        match (self.address, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                prefix_matches(&network.octets(), &ip.octets(), self.prefix_length)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_matches(&network.octets(), &ip.octets(), self.prefix_length)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// whether the first `length` bits of both addresses are equal
+fn prefix_matches(a: &[u8], b: &[u8], length: u8) -> bool {
+    let (bytes, bits) = ((length / 8) as usize, length % 8);
+    if a[..bytes] != b[..bytes] {
+        return false;
+    }
+    bits == 0 || (a[bytes] ^ b[bytes]) >> (8 - bits) == 0
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_length) = match s.split_once('/') {
+            Some((address, length)) => (address, Some(length)),
+            None => (s, None),
+        };
+        let address: IpAddr = address
+            .parse()
+            .map_err(|e| format!("invalid address in {s}: {e}"))?;
+        let max_length = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_length = match prefix_length {
+            Some(length) => length
+                .parse()
+                .ok()
+                .filter(|length| *length <= max_length)
+                .ok_or_else(|| format!("invalid prefix length in {s}"))?,
+            None => max_length,
+        };
+        Ok(Self {
+            address,
+            prefix_length,
+        })
+    }
+}
+
+impl TryFrom<String> for IpNetwork {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// score multipliers applied to option search results
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RankingConfig {
+    /// options of the flyingcircus platform
+    pub fcio_boost: f32,
+
+    /// `enable` options
+    pub enable_boost: f32,
+
+    /// options of roles, which usually only switch on other options
+    pub roles_boost: f32,
+}
+
+impl Default for RankingConfig {
+    fn default() -> Self {
+        Self {
+            fcio_boost: 1.3,
+            enable_boost: 1.05,
+            roles_boost: 0.8,
+        }
+    }
+}
+
+/// vectors of the option descriptions, so queries describing what an option does
+/// also find options that do not contain the words of the query
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmbeddingsConfig {
+    /// an openai compatible embeddings endpoint like `http://localhost:11434/v1/embeddings`
+    /// of a local ollama or llama.cpp server, embeddings are disabled without one
+    pub endpoint: Option<Url>,
+
+    /// model the endpoint computes the vectors with, changing it recomputes them
+    pub model: String,
+
+    /// sent as bearer token
+    pub token: Option<String>,
+
+    /// option descriptions sent per request while indexing
+    pub batch_size: usize,
+
+    /// number of the options nearest to a query that are added to its results
+    pub neighbours: usize,
+
+    /// options less similar to the query are not added
+    pub min_similarity: f32,
+
+    /// score of an option identical in meaning to the query, keyword matches score around 1 to 10
+    pub weight: f32,
+}
+
+impl Default for EmbeddingsConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            model: "nomic-embed-text".to_string(),
+            token: None,
+            batch_size: 64,
+            neighbours: 50,
+            min_similarity: 0.5,
+            weight: 4.0,
+        }
+    }
+}
+
+/// update interval for all branches matching a pattern like `*-production`
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    pub branches: String,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+/// match `name` against a pattern that may contain a single `*` wildcard
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            name.len() >= prefix.len() + suffix.len()
+                && name.starts_with(prefix)
+                && name.ends_with(suffix)
+        }
+        None => pattern == name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(toml: &str) -> anyhow::Result<()> {
+        toml::from_str::<Config>(toml)?.validate()
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        validate("").unwrap();
+    }
+
+    #[test]
+    fn flakes_need_a_github_url_or_owner_repo_and_branch() {
+        validate(
+            r#"
+            [[flakes]]
+            channel = "a"
+            url = "github:owner/repo/main"
+
+            [[flakes]]
+            channel = "b"
+            url = "https://github.com/owner/repo/tree/main/"
+
+            [[flakes]]
+            channel = "c"
+            owner = "owner"
+            repo = "repo"
+            branch = "main"
+            "#,
+        )
+        .unwrap();
+
+        for flake in [
+            r#"url = "github:owner/repo""#,
+            r#"url = "gitlab:owner/repo/main""#,
+            r#"owner = "owner""#,
+            r#"url = "github:owner/repo/main"
+               owner = "owner""#,
+        ] {
+            let toml = format!("[[flakes]]\nchannel = \"a\"\n{flake}");
+            assert!(validate(&toml).is_err(), "{flake} was accepted");
+        }
+    }
+
+    #[test]
+    fn channels_are_unique() {
+        let e = validate(
+            r#"
+            [[flakes]]
+            channel = "a"
+            url = "github:owner/repo/main"
+
+            [[flakes]]
+            channel = "a"
+            url = "github:owner/other/main"
+            "#,
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("more than once"));
+    }
+
+    #[test]
+    fn hydra_repository_is_owner_and_repo() {
+        validate("[hydra]\nrepository = \"owner/fc-nixos\"").unwrap();
+        for repository in ["fc-nixos", "owner/", "/fc-nixos", "owner/fc-nixos/main"] {
+            let toml = format!("[hydra]\nrepository = \"{repository}\"");
+            assert!(validate(&toml).is_err(), "{repository} was accepted");
+        }
+    }
+
+    #[test]
+    fn base_path_starts_but_does_not_end_with_a_slash() {
+        validate(r#"base_path = "/search""#).unwrap();
+        assert!(validate(r#"base_path = "search""#).is_err());
+        assert!(validate(r#"base_path = "/search/""#).is_err());
+        assert!(validate(r#"base_path = "/""#).is_err());
+    }
+
+    #[test]
+    fn matrix_webhooks_need_a_room_and_a_token() {
+        let webhook = r#"
+            [[webhooks]]
+            url = "https://matrix.example.org"
+            format = "matrix"
+        "#;
+        assert!(validate(webhook).is_err());
+        validate(&format!(
+            "{webhook}\nroom = \"!abc:example.org\"\ntoken = \"secret\""
+        ))
+        .unwrap();
+    }
+
+    fn network(s: &str) -> IpNetwork {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ip_networks_are_parsed() {
+        assert_eq!(
+            network("10.0.0.0/8"),
+            IpNetwork {
+                address: ip("10.0.0.0"),
+                prefix_length: 8
+            }
+        );
+        assert_eq!(network("192.0.2.1").prefix_length, 32);
+        assert_eq!(network("2001:db8::/32").prefix_length, 32);
+        assert_eq!(network("2001:db8::1").prefix_length, 128);
+        for invalid in [
+            "",
+            "10.0.0.0/33",
+            "2001:db8::/129",
+            "10.0.0.0/",
+            "host/8",
+            "10.0.0/8",
+        ] {
+            assert!(
+                invalid.parse::<IpNetwork>().is_err(),
+                "{invalid} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn ip_networks_contain_their_addresses() {
+        let private = network("10.0.0.0/8");
+        assert!(private.contains(ip("10.1.2.3")));
+        assert!(!private.contains(ip("11.0.0.1")));
+        // ipv4 clients of dual-stack sockets
+        assert!(private.contains(ip("::ffff:10.1.2.3")));
+        assert!(!private.contains(ip("2001:db8::1")));
+
+        let odd = network("192.0.2.0/23");
+        assert!(odd.contains(ip("192.0.3.255")));
+        assert!(!odd.contains(ip("192.0.4.0")));
+
+        assert!(network("0.0.0.0/0").contains(ip("203.0.113.9")));
+        assert!(network("2001:db8::/32").contains(ip("2001:db8:ffff::1")));
+        assert!(!network("2001:db8::/32").contains(ip("2001:db9::1")));
+        assert!(network("192.0.2.1").contains(ip("192.0.2.1")));
+        assert!(!network("192.0.2.1").contains(ip("192.0.2.2")));
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use itertools::Itertools;
+use lru::LruCache;
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::config::EmbeddingsConfig;
+use crate::error::{self, ErrorClass, FcSearchError};
+use crate::progress::{Progress, ProgressReporter};
+use crate::NaiveNixosOption;
+
+/// name of the cache holding the embeddings of a channel's options
+pub const EMBEDDINGS_CACHE: &str = "embeddings";
+
+/// descriptions are cut to this many characters, longer texts exceed the context of small models
+const MAX_TEXT_LENGTH: usize = 2000;
+
+/// timeout of a request while indexing
+const INDEX_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// queries are embedded while the user waits, slower answers fall back to the keyword search
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// model and text of an embedded query
+type QueryKey = (String, String);
+
+/// vectors of recent queries, keyed by the model and the query
+static QUERY_VECTORS: LazyLock<Mutex<LruCache<QueryKey, Arc<Vec<f32>>>>> =
+    LazyLock::new(|| Mutex::new(LruCache::new(NonZeroUsize::new(1024).unwrap())));
+
+/// the normalized vectors of the options of a channel
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Embeddings {
+    /// the model the vectors were computed with, only queries embedded by it can be compared
+    pub model: String,
+    dimensions: usize,
+    names: Vec<String>,
+    /// the vectors of all names one after another
+    vectors: Vec<f32>,
+}
+
+impl Embeddings {
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// the options nearest to `query` with their score, see [`EmbeddingsConfig::weight`]
+    pub fn nearest(&self, query: &[f32], config: &EmbeddingsConfig) -> Vec<(String, f32)> {
+        if query.len() != self.dimensions || self.dimensions == 0 {
+            debug!("the query vector does not match the embeddings");
+            return Vec::new();
+        }
+        let query = normalized(query.to_vec());
+        self.vectors
+            .chunks_exact(self.dimensions)
+            .zip(&self.names)
+            .map(|(vector, name)| (name, dot(vector, &query)))
+            .filter(|(_, similarity)| *similarity >= config.min_similarity)
+            .sorted_by(|a, b| b.1.total_cmp(&a.1))
+            .take(config.neighbours)
+            .map(|(name, similarity)| (name.clone(), similarity * config.weight))
+            .collect_vec()
+    }
+}
+
+/// request body of an openai compatible embeddings endpoint
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+    /// position of the input, answers may be in any order
+    index: usize,
+}
+
+/// compute the embeddings of all `options`, reporting the progress like the indexing
+pub async fn embed_options(
+    config: &EmbeddingsConfig,
+    options: &HashMap<String, NaiveNixosOption>,
+    progress: &ProgressReporter,
+) -> error::Result<Embeddings> {
+    let client = client(INDEX_TIMEOUT)?;
+    let names = options.keys().sorted().cloned().collect_vec();
+    let total = names.len();
+    let mut dimensions = 0;
+    let mut vectors = Vec::new();
+
+    for (i, batch) in names.chunks(config.batch_size.max(1)).enumerate() {
+        progress.report(Progress::Indexing {
+            corpus: "embeddings".into(),
+            indexed: i * config.batch_size.max(1),
+            total,
+        });
+        let texts = batch
+            .iter()
+            .map(|name| option_text(name, &options[name]))
+            .collect_vec();
+        for vector in embed(&client, config, &texts).await? {
+            if dimensions == 0 {
+                dimensions = vector.len();
+            } else if vector.len() != dimensions {
+                return Err(FcSearchError::network(
+                    "the embeddings endpoint returned vectors of different lengths",
+                ));
+            }
+            vectors.extend(normalized(vector));
+        }
+    }
+
+    info!(
+        "computed {total} embeddings with {dimensions} dimensions using {}",
+        config.model
+    );
+    Ok(Embeddings {
+        model: config.model.clone(),
+        dimensions,
+        names,
+        vectors,
+    })
+}
+
+/// the vector of a search query, `None` if embeddings are disabled
+pub async fn embed_query(
+    config: &EmbeddingsConfig,
+    query: &str,
+) -> error::Result<Option<Arc<Vec<f32>>>> {
+    if config.endpoint.is_none() {
+        return Ok(None);
+    }
+    let key = (config.model.clone(), query.to_string());
+    if let Some(vector) = QUERY_VECTORS.lock().unwrap().get(&key) {
+        return Ok(Some(vector.clone()));
+    }
+
+    let client = client(QUERY_TIMEOUT)?;
+    let vector = embed(&client, config, &[query.to_string()])
+        .await?
+        .pop()
+        .network("the embeddings endpoint returned no vector")?;
+    let vector = Arc::new(vector);
+    QUERY_VECTORS.lock().unwrap().put(key, vector.clone());
+    Ok(Some(vector))
+}
+
+fn client(timeout: Duration) -> error::Result<Client> {
+    Client::builder()
+        .timeout(timeout)
+        .build()
+        .network("could not build request client")
+}
+
+/// the vectors of `texts` in their order
+async fn embed(
+    client: &Client,
+    config: &EmbeddingsConfig,
+    texts: &[String],
+) -> error::Result<Vec<Vec<f32>>> {
+    let endpoint = config
+        .endpoint
+        .as_ref()
+        .network("no embeddings endpoint is configured")?;
+    let body = serde_json::to_string(&EmbeddingRequest {
+        model: &config.model,
+        input: texts,
+    })
+    .network("could not serialize the embeddings request")?;
+
+    let mut request = client
+        .post(endpoint.clone())
+        .header(CONTENT_TYPE, "application/json")
+        .header("User-Agent", "fc-search")
+        .body(body);
+    if let Some(ref token) = config.token {
+        request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+    }
+    let response = request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .network("could not reach the embeddings endpoint")?;
+    let body = response
+        .bytes()
+        .await
+        .network("could not read the embeddings")?;
+    let response: EmbeddingResponse =
+        serde_json::from_slice(&body).network("unexpected answer of the embeddings endpoint")?;
+
+    if response.data.len() != texts.len() {
+        return Err(FcSearchError::network(format!(
+            "the embeddings endpoint returned {} vectors for {} texts",
+            response.data.len(),
+            texts.len()
+        )));
+    }
+    Ok(response
+        .data
+        .into_iter()
+        .sorted_by_key(|data| data.index)
+        .map(|data| data.embedding)
+        .collect_vec())
+}
+
+/// what is embedded of an option, its name carries meaning as well
+fn option_text(name: &str, option: &NaiveNixosOption) -> String {
+    let text = format!("{name}: {}", option.description.text());
+    text.chars().take(MAX_TEXT_LENGTH).collect()
+}
+
+fn normalized(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = dot(&vector, &vector).sqrt();
+    if norm > 0. {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+    vector
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
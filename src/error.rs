@@ -0,0 +1,156 @@
+use std::error::Error;
+
+/// the cause of an [`FcSearchError`]
+type Source = Box<dyn Error + Send + Sync>;
+
+pub type Result<T, E = FcSearchError> = std::result::Result<T, E>;
+
+/// errors of the library, classified by what the caller can do about them
+#[derive(Debug, thiserror::Error)]
+pub enum FcSearchError {
+    /// nix could not evaluate or build a channel, retrying only helps once the channel is fixed
+    #[error("{message}")]
+    Evaluation {
+        message: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// github, hydra or a substituter could not be reached or answered unexpectedly, usually transient
+    #[error("{message}")]
+    Network {
+        message: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// the data of a channel in the state dir is missing or unreadable, rebuilding it helps
+    #[error("{message}")]
+    Cache {
+        message: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// an index could not be created, written or searched
+    #[error("{message}")]
+    Query {
+        message: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// a flake in the config file can not be resolved
+    #[error("{message}")]
+    Config { message: String },
+}
+
+impl FcSearchError {
+    pub fn evaluation(message: impl Into<String>) -> Self {
+        Self::Evaluation {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub fn network(message: impl Into<String>) -> Self {
+        Self::Network {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub fn cache(message: impl Into<String>) -> Self {
+        Self::Cache {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub fn query(message: impl Into<String>) -> Self {
+        Self::Query {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    /// short name of the class of the error, e.g. for the status page
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Evaluation { .. } => "evaluation",
+            Self::Network { .. } => "network",
+            Self::Cache { .. } => "cache",
+            Self::Query { .. } => "query",
+            Self::Config { .. } => "config",
+        }
+    }
+
+    /// whether the same operation may succeed later without any changes
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Network { .. })
+    }
+
+    /// the message followed by the messages of all its causes
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        let mut source = self.source();
+        while let Some(cause) = source {
+            report.push_str(&format!(": {cause}"));
+            source = cause.source();
+        }
+        report
+    }
+}
+
+/// classify the error of a result or a missing value as an [`FcSearchError`],
+/// like [`anyhow::Context`] adds a message
+pub trait ErrorClass<T> {
+    fn evaluation(self, message: impl Into<String>) -> Result<T>;
+    fn network(self, message: impl Into<String>) -> Result<T>;
+    fn cache(self, message: impl Into<String>) -> Result<T>;
+    fn query(self, message: impl Into<String>) -> Result<T>;
+}
+
+impl<T, E: Into<Source>> ErrorClass<T> for std::result::Result<T, E> {
+    fn evaluation(self, message: impl Into<String>) -> Result<T> {
+        self.map_err(|e| FcSearchError::Evaluation {
+            message: message.into(),
+            source: Some(e.into()),
+        })
+    }
+
+    fn network(self, message: impl Into<String>) -> Result<T> {
+        self.map_err(|e| FcSearchError::Network {
+            message: message.into(),
+            source: Some(e.into()),
+        })
+    }
+
+    fn cache(self, message: impl Into<String>) -> Result<T> {
+        self.map_err(|e| FcSearchError::Cache {
+            message: message.into(),
+            source: Some(e.into()),
+        })
+    }
+
+    fn query(self, message: impl Into<String>) -> Result<T> {
+        self.map_err(|e| FcSearchError::Query {
+            message: message.into(),
+            source: Some(e.into()),
+        })
+    }
+}
+
+impl<T> ErrorClass<T> for Option<T> {
+    fn evaluation(self, message: impl Into<String>) -> Result<T> {
+        self.ok_or_else(|| FcSearchError::evaluation(message))
+    }
+
+    fn network(self, message: impl Into<String>) -> Result<T> {
+        self.ok_or_else(|| FcSearchError::network(message))
+    }
+
+    fn cache(self, message: impl Into<String>) -> Result<T> {
+        self.ok_or_else(|| FcSearchError::cache(message))
+    }
+
+    fn query(self, message: impl Into<String>) -> Result<T> {
+        self.ok_or_else(|| FcSearchError::query(message))
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
+
+use axum::http::{header, HeaderMap};
+use fc_search::config::ProxyConfig;
+
+/// the address of the client that sent a request, as far as the trusted proxies tell.
+/// added to the extensions of every request
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl ClientIp {
+    /// the client behind the trusted proxies the request passed, `peer` is the address the
+    /// connection came from, `None` for unix sockets which are only reachable by local proxies
+    pub fn resolve(peer: Option<SocketAddr>, headers: &HeaderMap, proxy: &ProxyConfig) -> Self {
+        let peer = peer.map(|peer| peer.ip().to_canonical());
+        if peer.is_some_and(|peer| !proxy.trusts(peer)) {
+            return Self(peer);
+        }
+
+        // every proxy appends the address it received the request from,
+        // so the client is the last address that was not added by a trusted proxy
+        let mut client = peer;
+        for hop in forwarded_for(headers).iter().rev() {
+            match hop {
+                Some(ip) => {
+                    client = Some(*ip);
+                    if !proxy.trusts(*ip) {
+                        break;
+                    }
+                }
+                // an obfuscated or unknown address ends what can be told about the client
+                None => break,
+            }
+        }
+        Self(client)
+    }
+}
+
+impl Display for ClientIp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(ip) => write!(f, "{ip}"),
+            None => f.write_str("unknown"),
+        }
+    }
+}
+
+/// the addresses in the `Forwarded` headers, or in `X-Forwarded-For` if there are none,
+/// from the client to the last proxy. `None` for addresses that are not ips
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name: header::HeaderName| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|element| !element.is_empty())
+            .collect::<Vec<_>>()
+    };
+
+    let forwarded = values(header::FORWARDED);
+    if !forwarded.is_empty() {
+        return forwarded
+            .into_iter()
+            .filter_map(|element| {
+                // like `for=192.0.2.60;proto=http` or `for="[2001:db8::17]:4711"`
+                element.split(';').find_map(|pair| {
+                    let (key, value) = pair.trim().split_once('=')?;
+                    key.eq_ignore_ascii_case("for")
+                        .then(|| parse_node(value.trim_matches('"')))
+                })
+            })
+            .collect();
+    }
+    values(header::HeaderName::from_static("x-forwarded-for"))
+        .into_iter()
+        .map(parse_node)
+        .collect()
+}
+
+/// the ip of a node like `192.0.2.60`, `192.0.2.60:4711`, `2001:db8::17` or `[2001:db8::17]:4711`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip.to_canonical());
+    }
+    if let Ok(address) = node.parse::<SocketAddr>() {
+        return Some(address.ip().to_canonical());
+    }
+    // ipv6 addresses in brackets without a port
+    node.strip_prefix('[')?
+        .strip_suffix(']')?
+        .parse::<IpAddr>()
+        .ok()
+        .map(|ip| ip.to_canonical())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxy(trusted: &[&str]) -> ProxyConfig {
+        ProxyConfig {
+            trusted: trusted
+                .iter()
+                .map(|network| network.parse().unwrap())
+                .collect(),
+        }
+    }
+
+    fn resolve(peer: &str, headers: &[(&str, &str)], proxy: &ProxyConfig) -> String {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(
+                header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        let peer = (!peer.is_empty()).then(|| peer.parse().unwrap());
+        ClientIp::resolve(peer, &map, proxy).to_string()
+    }
+
+    #[test]
+    fn untrusted_peers_are_the_client() {
+        let headers = [("x-forwarded-for", "203.0.113.9")];
+        assert_eq!(
+            resolve("192.0.2.1:1234", &headers, &proxy(&[])),
+            "192.0.2.1"
+        );
+    }
+
+    #[test]
+    fn trusted_proxies_name_the_client() {
+        let proxy = proxy(&["10.0.0.0/8"]);
+        let headers = [("x-forwarded-for", "203.0.113.9, 10.0.0.2")];
+        assert_eq!(resolve("10.0.0.1:1234", &headers, &proxy), "203.0.113.9");
+        // ipv4 peers of dual-stack sockets
+        assert_eq!(
+            resolve("[::ffff:10.0.0.1]:1234", &headers, &proxy),
+            "203.0.113.9"
+        );
+    }
+
+    #[test]
+    fn spoofed_addresses_before_an_untrusted_hop_are_ignored() {
+        let proxy = proxy(&["10.0.0.0/8"]);
+        let headers = [("x-forwarded-for", "1.2.3.4, 203.0.113.9")];
+        assert_eq!(resolve("10.0.0.1:1234", &headers, &proxy), "203.0.113.9");
+    }
+
+    #[test]
+    fn forwarded_takes_precedence_over_x_forwarded_for() {
+        let proxy = proxy(&["10.0.0.1"]);
+        let headers = [
+            ("x-forwarded-for", "203.0.113.1"),
+            ("forwarded", r#"for="[2001:db8::17]:4711";proto=https"#),
+        ];
+        assert_eq!(resolve("10.0.0.1:1234", &headers, &proxy), "2001:db8::17");
+    }
+
+    #[test]
+    fn unknown_hops_end_the_chain() {
+        let proxy = proxy(&["10.0.0.1"]);
+        let headers = [("forwarded", "for=_hidden, for=10.0.0.1")];
+        assert_eq!(resolve("10.0.0.1:1234", &headers, &proxy), "10.0.0.1");
+    }
+
+    #[test]
+    fn unix_socket_connections_are_trusted() {
+        let headers = [("x-forwarded-for", "203.0.113.9")];
+        assert_eq!(resolve("", &headers, &proxy(&[])), "203.0.113.9");
+        assert_eq!(resolve("", &[], &proxy(&[])), "unknown");
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{FlakeRev, LogError, Markup, NaiveNixosOption};
+
+/// file in the channel dir holding the history of its options
+pub const HISTORY_FILE: &str = "history.json";
+
+/// what happened to the options of a channel over all revisions it was indexed at
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct ChannelHistory {
+    pub options: HashMap<String, Vec<HistoryEntry>>,
+}
+
+/// a change of an option, recorded when the channel was updated to `rev`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+    pub rev: FlakeRev,
+    /// unix timestamp of the update
+    pub at: u64,
+    #[serde(flatten)]
+    pub event: OptionEvent,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum OptionEvent {
+    /// the option existed when the channel was first indexed, it may be older
+    Indexed,
+    Added,
+    DefaultChanged {
+        from: Markup,
+        to: Markup,
+    },
+    TypeChanged {
+        from: String,
+        to: String,
+    },
+    Removed,
+}
+
+impl HistoryEntry {
+    /// the day of the update, like `2024-05-31`
+    pub fn date(&self) -> String {
+        let time = UNIX_EPOCH + Duration::from_secs(self.at);
+        humantime::format_rfc3339(time)
+            .to_string()
+            .chars()
+            .take(10)
+            .collect()
+    }
+
+    /// the revision shortened like git does
+    pub fn short_rev(&self) -> String {
+        self.rev.to_string().chars().take(12).collect()
+    }
+}
+
+impl ChannelHistory {
+    /// the history of the channel in `branch_path`, empty if none was recorded yet
+    /// or if it can not be read
+    pub fn read(branch_path: &Path) -> Self {
+        let path = branch_path.join(HISTORY_FILE);
+        let content = match std::fs::read_to_string(&path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Self::default(),
+            result => result.log_to_option(&format!(
+                "could not read the option history {}",
+                path.display()
+            )),
+        };
+        content
+            .and_then(|content| {
+                serde_json::from_str(&content).log_to_option(&format!(
+                    "could not parse the option history {}",
+                    path.display()
+                ))
+            })
+            .unwrap_or_default()
+    }
+
+    /// replace the history of the channel in `branch_path`. it is written next to the old one
+    /// and moved over it, so readers never see a partially written history
+    pub fn write(&self, branch_path: &Path) -> anyhow::Result<()> {
+        let path = branch_path.join(HISTORY_FILE);
+        let partial = branch_path.join(format!("{HISTORY_FILE}.partial"));
+        std::fs::write(
+            &partial,
+            serde_json::to_string(self).context("failed to serialize the option history")?,
+        )
+        .context("failed to save the option history")?;
+        std::fs::rename(&partial, &path).context("failed to replace the option history")
+    }
+
+    /// the changes of an option from the oldest to the newest
+    pub fn of(&self, name: &str) -> &[HistoryEntry] {
+        self.options
+            .get(name)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// record the changes between the options `from` the previously indexed revision `to` the
+    /// options at `rev`. without previous options only appearing and vanishing options are known
+    pub fn record(
+        &mut self,
+        from: Option<&HashMap<String, NaiveNixosOption>>,
+        to: &HashMap<String, NaiveNixosOption>,
+        rev: &FlakeRev,
+        at: u64,
+    ) {
+        let first_index = self.options.is_empty();
+        let entry = |event| HistoryEntry {
+            rev: rev.clone(),
+            at,
+            event,
+        };
+
+        for (name, option) in to {
+            let entries = self.options.entry(name.clone()).or_default();
+            // channels indexed before the history was kept have nothing to compare against either
+            if first_index {
+                entries.push(entry(OptionEvent::Indexed));
+                continue;
+            }
+            let present = entries
+                .last()
+                .is_some_and(|e| e.event != OptionEvent::Removed);
+            let previous = from.and_then(|from| from.get(name));
+            match previous {
+                Some(previous) => {
+                    if previous.default != option.default {
+                        entries.push(entry(OptionEvent::DefaultChanged {
+                            from: previous.default.clone(),
+                            to: option.default.clone(),
+                        }));
+                    }
+                    if previous.option_type != option.option_type {
+                        entries.push(entry(OptionEvent::TypeChanged {
+                            from: previous.option_type.clone(),
+                            to: option.option_type.clone(),
+                        }));
+                    }
+                }
+                None if from.is_some() || !present => entries.push(entry(OptionEvent::Added)),
+                // still there, but there is nothing to compare it with
+                None => {}
+            }
+        }
+
+        for (name, entries) in &mut self.options {
+            let present = entries
+                .last()
+                .is_some_and(|e| e.event != OptionEvent::Removed);
+            if present && !to.contains_key(name) {
+                entries.push(entry(OptionEvent::Removed));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(default: &str, option_type: &str) -> NaiveNixosOption {
+        NaiveNixosOption {
+            name: String::new(),
+            declarations: Vec::new(),
+            description: Markup::default(),
+            default: Markup::Literal(default.to_string()),
+            example: Markup::default(),
+            option_type: option_type.to_string(),
+            read_only: false,
+            related_packages: Vec::new(),
+            set_by_roles: Vec::new(),
+        }
+    }
+
+    fn options(entries: &[(&str, &str, &str)]) -> HashMap<String, NaiveNixosOption> {
+        entries
+            .iter()
+            .map(|(name, default, option_type)| (name.to_string(), option(default, option_type)))
+            .collect()
+    }
+
+    fn rev(rev: &str) -> FlakeRev {
+        FlakeRev::Specific(rev.to_string())
+    }
+
+    fn events(history: &ChannelHistory, name: &str) -> Vec<OptionEvent> {
+        history.of(name).iter().map(|e| e.event.clone()).collect()
+    }
+
+    #[test]
+    fn first_index_marks_every_option_as_indexed() {
+        let mut history = ChannelHistory::default();
+        history.record(None, &options(&[("a", "1", "int")]), &rev("r1"), 1);
+        assert_eq!(events(&history, "a"), [OptionEvent::Indexed]);
+        assert_eq!(history.of("a")[0].at, 1);
+        assert!(history.of("b").is_empty());
+    }
+
+    #[test]
+    fn changes_between_revisions_are_recorded() {
+        let first = options(&[("a", "1", "int"), ("b", "x", "str"), ("c", "", "bool")]);
+        let second = options(&[("a", "2", "int"), ("b", "x", "lines"), ("d", "", "bool")]);
+        let mut history = ChannelHistory::default();
+        history.record(None, &first, &rev("r1"), 1);
+        history.record(Some(&first), &second, &rev("r2"), 2);
+
+        assert_eq!(
+            events(&history, "a"),
+            [
+                OptionEvent::Indexed,
+                OptionEvent::DefaultChanged {
+                    from: Markup::Literal("1".to_string()),
+                    to: Markup::Literal("2".to_string()),
+                }
+            ]
+        );
+        assert_eq!(
+            events(&history, "b"),
+            [
+                OptionEvent::Indexed,
+                OptionEvent::TypeChanged {
+                    from: "str".to_string(),
+                    to: "lines".to_string(),
+                }
+            ]
+        );
+        assert_eq!(
+            events(&history, "c"),
+            [OptionEvent::Indexed, OptionEvent::Removed]
+        );
+        assert_eq!(events(&history, "d"), [OptionEvent::Added]);
+        assert_eq!(history.of("d")[0].rev, rev("r2"));
+    }
+
+    #[test]
+    fn without_previous_options_only_appearing_and_vanishing_options_are_recorded() {
+        let first = options(&[("a", "1", "int"), ("b", "", "bool")]);
+        let second = options(&[("a", "2", "int"), ("c", "", "bool")]);
+        let mut history = ChannelHistory::default();
+        history.record(None, &first, &rev("r1"), 1);
+        history.record(None, &second, &rev("r2"), 2);
+
+        assert_eq!(events(&history, "a"), [OptionEvent::Indexed]);
+        assert_eq!(
+            events(&history, "b"),
+            [OptionEvent::Indexed, OptionEvent::Removed]
+        );
+        assert_eq!(events(&history, "c"), [OptionEvent::Added]);
+
+        // a removed option that comes back is added again
+        history.record(None, &first, &rev("r3"), 3);
+        assert_eq!(
+            events(&history, "b"),
+            [
+                OptionEvent::Indexed,
+                OptionEvent::Removed,
+                OptionEvent::Added
+            ]
+        );
+    }
+
+    #[test]
+    fn history_survives_a_write_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(ChannelHistory::read(dir.path()).options.is_empty());
+
+        let mut history = ChannelHistory::default();
+        history.record(None, &options(&[("a", "1", "int")]), &rev("r1"), 1);
+        history.write(dir.path()).unwrap();
+        assert_eq!(
+            events(&ChannelHistory::read(dir.path()), "a"),
+            [OptionEvent::Indexed]
+        );
+        assert!(!dir.path().join(format!("{HISTORY_FILE}.partial")).exists());
+    }
+
+    #[test]
+    fn unreadable_history_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(HISTORY_FILE), "not json").unwrap();
+        assert!(ChannelHistory::read(dir.path()).options.is_empty());
+    }
+}
@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use itertools::Itertools;
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
+use reqwest::{Client, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
+use url::Url;
+
+use crate::config::HydraConfig;
+use crate::{Flake, FlakeRev};
+
+/// the hydra config of the server, replaced when the config is reloaded
+static CONFIG: LazyLock<ArcSwap<HydraConfig>> =
+    LazyLock::new(|| ArcSwap::from_pointee(HydraConfig::default()));
+
+/// use the given hydra and repository for all following requests
+pub fn configure(config: &HydraConfig) {
+    CONFIG.store(Arc::new(config.clone()));
+}
+
+/// url of the hydra the branches are discovered on, without a trailing slash
+pub fn base_url() -> String {
+    trimmed(&CONFIG.load().base_url)
+}
+
+fn trimmed(url: &Url) -> String {
+    url.as_str().trim_end_matches('/').to_string()
+}
+
+const PROJECT_ID: &str = "flyingcircus";
+
+/// timeout of a single request to hydra
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// requests failing with network errors or server errors are retried this often
+const MAX_ATTEMPTS: u32 = 4;
+
+/// delay before the first retry, doubled for every further one
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// pages of evaluations searched for the latest revision of a jobset
+const MAX_EVAL_PAGES: usize = 5;
+
+#[derive(Debug)]
+pub enum HydraError {
+    /// hydra could not be reached, also after retrying
+    Request { url: String, source: reqwest::Error },
+    /// hydra answered with an unsuccessful status code
+    Status { url: String, status: StatusCode },
+    /// the response was not in the expected format
+    Json {
+        url: String,
+        source: serde_json::Error,
+    },
+}
+
+impl Display for HydraError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HydraError::Request { url, source } => write!(f, "request to {url} failed: {source}"),
+            HydraError::Status { url, status } => write!(f, "{url} responded with {status}"),
+            HydraError::Json { url, source } => {
+                write!(f, "unexpected response from {url}: {source}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for HydraError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HydraError::Request { source, .. } => Some(source),
+            HydraError::Status { .. } => None,
+            HydraError::Json { source, .. } => Some(source),
+        }
+    }
+}
+
+impl HydraError {
+    /// whether the request may succeed when it is sent again
+    fn is_transient(&self) -> bool {
+        match self {
+            HydraError::Request { .. } => true,
+            HydraError::Status { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            HydraError::Json { .. } => false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Project {
+    #[serde(default)]
+    jobsets: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JobsetInput {
+    #[serde(default)]
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Jobset {
+    #[serde(default)]
+    inputs: HashMap<String, JobsetInput>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EvalInput {
+    revision: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Eval {
+    id: u64,
+    #[serde(default)]
+    jobsetevalinputs: HashMap<String, EvalInput>,
+}
+
+impl Eval {
+    /// the revision of the fc-nixos input
+    fn revision(&self) -> Option<&str> {
+        self.jobsetevalinputs.get("fc")?.revision.as_deref()
+    }
+}
+
+/// one page of the evaluations of a jobset, newest first
+#[derive(Debug, Deserialize)]
+struct Evals {
+    #[serde(default)]
+    evals: Vec<Eval>,
+    /// query string of the next page like `?page=2`
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Build {
+    #[serde(default)]
+    finished: u8,
+    /// 0 for a successful build, see `BuildStatus` in hydra
+    buildstatus: Option<u32>,
+}
+
+/// the hydra evaluation of the indexed revision of a channel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HydraStatus {
+    pub eval_id: u64,
+    /// status of the configured job, see [`crate::config::HydraConfig::build_job`]
+    pub build: Option<BuildStatus>,
+}
+
+impl HydraStatus {
+    pub fn eval_url(&self) -> String {
+        format!("{}/eval/{}", base_url(), self.eval_id)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildStatus {
+    /// queued or still building
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+impl Display for BuildStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BuildStatus::Pending => "building",
+            BuildStatus::Succeeded => "built",
+            BuildStatus::Failed => "failed",
+        })
+    }
+}
+
+fn client() -> Result<Client, HydraError> {
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+    Client::builder()
+        .default_headers(headers)
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(|source| HydraError::Request {
+            url: base_url().to_string(),
+            source,
+        })
+}
+
+/// fetch and parse `url`, retrying transient failures with an exponential backoff
+async fn get_json<T: DeserializeOwned>(client: &Client, url: &str) -> Result<T, HydraError> {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match try_get_json(client, url).await {
+            Err(e) if e.is_transient() && attempt < MAX_ATTEMPTS => {
+                debug!("attempt {attempt} failed, retrying in {backoff:?}: {e}");
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn try_get_json<T: DeserializeOwned>(client: &Client, url: &str) -> Result<T, HydraError> {
+    let request_error = |source| HydraError::Request {
+        url: url.to_string(),
+        source,
+    };
+    let response = client.get(url).send().await.map_err(request_error)?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(HydraError::Status {
+            url: url.to_string(),
+            status,
+        });
+    }
+    let body = response.bytes().await.map_err(request_error)?;
+    serde_json::from_slice(&body).map_err(|source| HydraError::Json {
+        url: url.to_string(),
+        source,
+    })
+}
+
+/// the newest evaluation of the jobset that matches `filter`, searching the first pages only
+async fn find_eval(
+    client: &Client,
+    jobset_id: &str,
+    filter: impl Fn(&Eval) -> bool,
+) -> Result<Option<Eval>, HydraError> {
+    let evals_url = format!("{}/jobset/{PROJECT_ID}/{jobset_id}/evals", base_url());
+    let mut url = evals_url.clone();
+    for _ in 0..MAX_EVAL_PAGES {
+        let page: Evals = get_json(client, &url).await?;
+        if let Some(eval) = page.evals.into_iter().find(&filter) {
+            return Ok(Some(eval));
+        }
+        match page.next {
+            Some(next) => url = format!("{evals_url}{next}"),
+            None => break,
+        }
+    }
+    Ok(None)
+}
+
+/// the revision of the fc-nixos input of the newest evaluation of the jobset
+pub async fn fetch_latest_rev(
+    client: &Client,
+    jobset_id: &str,
+) -> Result<Option<String>, HydraError> {
+    let eval = find_eval(client, jobset_id, |eval| eval.revision().is_some()).await?;
+    Ok(eval.and_then(|eval| eval.revision().map(str::to_string)))
+}
+
+/// the newest evaluation of `rev` in the jobset and, if `build_job` is given,
+/// whether that job was built in it
+pub async fn fetch_status(
+    jobset_id: &str,
+    rev: &str,
+    build_job: Option<&str>,
+) -> Result<Option<HydraStatus>, HydraError> {
+    let client = client()?;
+    let Some(eval) = find_eval(&client, jobset_id, |eval| eval.revision() == Some(rev)).await?
+    else {
+        debug!("no evaluation of {rev} found in jobset {jobset_id}");
+        return Ok(None);
+    };
+
+    let build = match build_job {
+        Some(job) => {
+            // hydra redirects to the build of the job in the evaluation
+            let url = format!("{}/eval/{}/job/{job}", base_url(), eval.id);
+            match get_json::<Build>(&client, &url).await {
+                Ok(build) => Some(match (build.finished, build.buildstatus) {
+                    (0, _) | (_, None) => BuildStatus::Pending,
+                    (_, Some(0)) => BuildStatus::Succeeded,
+                    (_, Some(_)) => BuildStatus::Failed,
+                }),
+                // the job is not part of every evaluation
+                Err(HydraError::Status { status, .. }) if status == StatusCode::NOT_FOUND => None,
+                Err(e) => return Err(e),
+            }
+        }
+        None => None,
+    };
+    Ok(Some(HydraStatus {
+        eval_id: eval.id,
+        build,
+    }))
+}
+
+/// a branch of the configured repository on github
+struct JobsetBranch {
+    branch: String,
+    owner: String,
+    repo: String,
+}
+
+/// the branch of the configured repository built by the jobset, `None` for jobsets building
+/// something else
+async fn jobset_branch(
+    client: &Client,
+    jobset_id: &str,
+) -> Result<Option<JobsetBranch>, HydraError> {
+    let url = format!("{}/jobset/{PROJECT_ID}/{jobset_id}", base_url());
+    let jobset: Jobset = get_json(client, &url).await?;
+
+    let Some(input) = jobset.inputs.get("fc") else {
+        warn!("jobset {jobset_id} has no input fc");
+        return Ok(None);
+    };
+    let repository = CONFIG.load().repository.clone();
+    let branch = input.value.split_once(' ').and_then(|(url, branch)| {
+        let path = url
+            .strip_prefix("https://github.com/")?
+            .trim_end_matches(".git");
+        if path != repository {
+            return None;
+        }
+        let (owner, repo) = path.split_once('/')?;
+        Some(JobsetBranch {
+            branch: branch.to_string(),
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+        })
+    });
+    match branch {
+        Some(branch) => Ok(Some(branch)),
+        None => {
+            warn!(
+                "input fc of jobset {jobset_id} is not a branch of {repository}: {:?}",
+                input.value
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// the fc-nixos branches built on hydra, newest first
+///
+/// jobsets with malformed responses are skipped, only failing to reach hydra is an error
+pub async fn get_fcio_flake_uris() -> Result<Vec<Flake>, HydraError> {
+    let client = client()?;
+
+    let project: Project =
+        get_json(&client, &format!("{}/project/{PROJECT_ID}", base_url())).await?;
+
+    let jobsets: Vec<_> = project
+        .jobsets
+        .iter()
+        .filter(|j| {
+            j.starts_with("fc-")
+                && (j.ends_with("production") || j.ends_with("dev") || j.ends_with("staging"))
+        })
+        .sorted()
+        .collect();
+
+    let mut branches: Vec<(JobsetBranch, &String)> = Vec::new();
+    for jobset_id in jobsets {
+        match jobset_branch(&client, jobset_id).await {
+            Ok(Some(branch)) => branches.push((branch, jobset_id)),
+            Ok(None) => {}
+            Err(e @ HydraError::Json { .. }) => warn!("skipping jobset {jobset_id}: {e}"),
+            Err(e) => return Err(e),
+        }
+    }
+
+    // index newest branches first to circumvent rate limits when indexing the more important newer branches
+    branches.sort_by(|(a, _), (b, _)| b.branch.cmp(&a.branch));
+
+    // only keep the newest 9 branches => 3 channels (dev, staging + prod each)
+    branches.truncate(3 * 3);
+
+    let mut flakes = Vec::new();
+    for (
+        JobsetBranch {
+            branch,
+            owner,
+            repo,
+        },
+        jobset_id,
+    ) in branches.into_iter()
+    {
+        let mut flake = match Flake::new(&owner, &repo, &branch).await {
+            Ok(s) => s,
+            Err(e) => {
+                error!(
+                    "error fetching information about branch {}: {}",
+                    branch,
+                    e.report()
+                );
+                continue;
+            }
+        };
+
+        flake.hydra_jobset = Some(jobset_id.clone());
+
+        // github is rate limited, the last revision evaluated on hydra is the next best thing
+        if flake.rev == FlakeRev::FallbackToCached {
+            match fetch_latest_rev(&client, jobset_id).await {
+                Ok(Some(rev)) => flake.rev = FlakeRev::Specific(rev),
+                Ok(None) => {}
+                Err(e) => warn!("could not fetch the latest evaluation of {jobset_id}: {e}"),
+            }
+        }
+        flakes.push(flake);
+    }
+
+    info!(
+        "fetched branches {:?} from hydra",
+        flakes.iter().map(|f| f.branch.clone()).collect_vec()
+    );
+
+    Ok(flakes)
+}
@@ -1,18 +1,40 @@
 #![feature(duration_constructors)]
 
+pub mod changelog;
+pub mod changes;
+pub mod channel;
+pub mod client;
+pub mod config;
+pub mod embeddings;
+pub mod error;
+pub mod history;
+pub mod hydra;
 pub mod nix;
+pub mod progress;
+pub mod roles;
 pub mod search;
-
-use anyhow::Context;
+pub mod state;
+pub mod status;
+pub mod storage;
+pub mod tree;
+pub mod upstream;
+
+use arc_swap::ArcSwap;
+use config::{ExtraFlake, GithubConfig, UpstreamConfig};
+use error::{ErrorClass, FcSearchError};
 use nix::NixosOption;
 
 use itertools::Itertools;
-use reqwest::header::HeaderMap;
+use lru::LruCache;
+use rayon::prelude::*;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
 use std::collections::HashMap;
 use std::fmt::Display;
-use tracing::{debug, error, info, warn};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, LazyLock, Mutex};
+use tracing::{debug, error, warn};
 use url::Url;
 
 use self::nix::Expression;
@@ -21,11 +43,141 @@ use self::nix::Expression;
 pub struct NaiveNixosOption {
     pub name: String,
     pub declarations: Vec<Html>,
-    pub description: Html,
-    pub default: Html,
-    pub example: Html,
+    pub description: Markup,
+    pub default: Markup,
+    pub example: Markup,
     pub option_type: String,
     pub read_only: bool,
+    #[serde(default)]
+    pub related_packages: Vec<String>,
+    /// fc-nixos roles whose modules define a value for the option
+    #[serde(default)]
+    pub set_by_roles: Vec<String>,
+}
+
+/// where an option is declared
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OptionSource {
+    /// declared or extended by the channel itself, e.g. by fc-nixos
+    Fc,
+    /// only declared in nixpkgs or home-manager
+    Upstream,
+}
+
+impl OptionSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OptionSource::Fc => "fc",
+            OptionSource::Upstream => "upstream",
+        }
+    }
+}
+
+impl NaiveNixosOption {
+    /// the allowed values of an enum type like `one of "a", "b", "c"`
+    pub fn choices(&self) -> Vec<String> {
+        let Some((_, rest)) = self.option_type.split_once("one of ") else {
+            return Vec::new();
+        };
+
+        let mut choices = Vec::new();
+        let mut chars = rest.chars().peekable();
+        while let Some(&first) = chars.peek() {
+            let mut value = String::new();
+            if first == '"' {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => value.extend(chars.next()),
+                        '"' => break,
+                        c => value.push(c),
+                    }
+                }
+            } else {
+                // numbers and other literals are not quoted
+                while let Some(c) = chars.next_if(|c| *c != ',' && *c != ')') {
+                    value.push(c);
+                }
+                value = value.trim().to_string();
+            }
+            choices.push(value);
+
+            // the values are separated by `, `, anything else ends the enum
+            if chars.next() != Some(',') {
+                break;
+            }
+            while chars.next_if_eq(&' ').is_some() {}
+        }
+        choices
+    }
+
+    /// whether the option has options of its own, like `attribute set of (submodule)`
+    pub fn has_sub_options(&self) -> bool {
+        self.option_type.contains("submodule")
+    }
+
+    pub fn source(&self) -> OptionSource {
+        let upstream = !self.declarations.is_empty()
+            && self.declarations.iter().all(|d| {
+                let d = d.0.to_lowercase();
+                d.contains("github.com/nixos/nixpkgs/")
+                    || d.contains("github.com/nix-community/home-manager/")
+            });
+        if upstream {
+            OptionSource::Upstream
+        } else {
+            OptionSource::Fc
+        }
+    }
+
+    /// a config line setting the option to a placeholder of its type,
+    /// e.g. `services.nginx.enable = true;`
+    pub fn nix_snippet(&self) -> String {
+        // placeholders in the name like `<name>` have to be set to an attribute name
+        let name = self
+            .name
+            .split('.')
+            .map(
+                |part| match part.strip_prefix('<').and_then(|p| p.strip_suffix('>')) {
+                    Some(placeholder) => format!("\"{placeholder}\""),
+                    None => part.to_string(),
+                },
+            )
+            .join(".");
+        format!("{name} = {};", placeholder_value(&self.option_type))
+    }
+}
+
+/// an example value of the nixos option type `option_type`
+fn placeholder_value(option_type: &str) -> String {
+    let option_type = option_type.trim();
+    if let Some(inner) = option_type.strip_prefix("null or ") {
+        return placeholder_value(inner);
+    }
+    if let Some(choices) = option_type.strip_prefix("one of ") {
+        if let Some(first) = choices.split(", ").next() {
+            return first.to_string();
+        }
+    }
+
+    let value = match option_type {
+        "boolean" => "true",
+        "package" => "pkgs.hello",
+        "floating point number" => "0.0",
+        t if t.starts_with("list of") => "[ ]",
+        t if t.starts_with("attribute set")
+            || t.starts_with("lazy attribute set")
+            || t.starts_with("submodule") =>
+        {
+            "{ }"
+        }
+        t if t.contains("integer") => "0",
+        t if t.contains("path") => "/path/to/file",
+        t if t.starts_with("str") || t.contains("string") => "\"\"",
+        _ => "null",
+    };
+    value.to_string()
 }
 
 pub trait NixHtml {
@@ -44,12 +196,167 @@ impl<T: NixHtml> NixHtml for Option<T> {
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Html(pub String);
 
+/// number of characters above which long option fields are only shown as a preview
+pub const PREVIEW_LENGTH: usize = 800;
+
+impl Html {
+    /// the text of the html cut to `max` characters,
+    /// `None` if it is short enough to be shown completely.
+    /// `max` is also taken by reference, which is how templates pass their fields
+    pub fn preview(&self, max: impl Borrow<usize>) -> Option<String> {
+        let max = *max.borrow();
+        if self.0.len() <= max {
+            return None;
+        }
+
+        let mut text = String::new();
+        let mut in_tag = false;
+        for c in self.0.chars() {
+            match c {
+                '<' => in_tag = true,
+                '>' if in_tag => in_tag = false,
+                c if !in_tag => text.push(c),
+                _ => {}
+            }
+        }
+        let text = text
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&#39;", "'")
+            .replace("&amp;", "&");
+
+        let preview: String = text.trim().chars().take(max).collect();
+        (preview.len() < text.trim().len()).then(|| format!("{preview}…"))
+    }
+}
+
 impl Display for Html {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(&self.0)
     }
 }
 
+/// escape text to be shown as is in html, the inverse of the unescaping in [`Html::preview`]
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// render markdown to html. the texts come from third-party modules and packages,
+/// so everything that could run scripts in the search results is removed
+fn render_markdown(text: &str) -> String {
+    ammonia::clean(&markdown::to_html(text))
+}
+
+/// rendered markdown of recently shown option texts, keyed by their source
+static RENDERED_MARKDOWN: LazyLock<Mutex<LruCache<String, String>>> =
+    LazyLock::new(|| Mutex::new(LruCache::new(NonZeroUsize::new(4096).unwrap())));
+
+/// option text as it was evaluated, only rendered to html when it is shown
+#[derive(Debug, PartialEq, Serialize, Clone)]
+#[serde(tag = "kind", content = "text", rename_all = "lowercase")]
+pub enum Markup {
+    /// shown as is, e.g. a nix expression
+    Literal(String),
+    Markdown(String),
+    /// html rendered when the channel was indexed, read from caches written before the text was kept
+    Rendered(String),
+}
+
+impl<'de> Deserialize<'de> for Markup {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(tag = "kind", content = "text", rename_all = "lowercase")]
+        enum Tagged {
+            Literal(String),
+            Markdown(String),
+            Rendered(String),
+        }
+
+        /// older caches store the rendered html as a plain string
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Stored {
+            Tagged(Tagged),
+            Html(String),
+        }
+
+        Ok(match Stored::deserialize(deserializer)? {
+            Stored::Tagged(Tagged::Literal(text)) => Markup::Literal(text),
+            Stored::Tagged(Tagged::Markdown(text)) => Markup::Markdown(text),
+            // the html may come from another writer or predate the escaping of literals
+            Stored::Tagged(Tagged::Rendered(html)) | Stored::Html(html) => {
+                Markup::Rendered(ammonia::clean(&html))
+            }
+        })
+    }
+}
+
+impl Default for Markup {
+    fn default() -> Self {
+        Markup::Literal(String::new())
+    }
+}
+
+impl From<Expression> for Markup {
+    fn from(expression: Expression) -> Self {
+        match expression.option_type {
+            nix::ExpressionType::LiteralExpression => Markup::Literal(expression.text),
+            nix::ExpressionType::LiteralMd => Markup::Markdown(expression.text),
+        }
+    }
+}
+
+impl Markup {
+    /// the unrendered text
+    pub fn text(&self) -> &str {
+        match self {
+            Markup::Literal(text) | Markup::Markdown(text) | Markup::Rendered(text) => text,
+        }
+    }
+
+    pub fn html(&self) -> Html {
+        let text = match self {
+            Markup::Literal(text) => return Html(escape_html(text)),
+            Markup::Rendered(html) => return Html(html.clone()),
+            Markup::Markdown(text) => text,
+        };
+        if let Some(html) = RENDERED_MARKDOWN.lock().unwrap().get(text) {
+            return Html(html.clone());
+        }
+
+        // rendered without holding the lock, a concurrent render of the same text is harmless
+        let html = render_markdown(text);
+        RENDERED_MARKDOWN
+            .lock()
+            .unwrap()
+            .put(text.clone(), html.clone());
+        Html(html)
+    }
+
+    /// see [`Html::preview`]
+    pub fn preview(&self, max: impl Borrow<usize>) -> Option<String> {
+        self.html().preview(max)
+    }
+}
+
+impl Display for Markup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.html().0)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Declaration {
     Naive(String),
@@ -59,11 +366,13 @@ pub enum Declaration {
 impl NixHtml for Declaration {
     fn as_html(&self) -> Html {
         match self {
-            Declaration::Naive(s) => Html(format!("<i>{}</i>", s)),
-            Declaration::Processed(url) => Html(format!(
-                "<a class=\"text-blue-900 hover:underline\" href=\"{}\">{}</a>",
-                url, url
-            )),
+            Declaration::Naive(s) => Html(format!("<i>{}</i>", escape_html(s))),
+            Declaration::Processed(url) => {
+                let url = escape_html(url.as_str());
+                Html(format!(
+                    "<a class=\"text-blue-900 hover:underline\" href=\"{url}\">{url}</a>"
+                ))
+            }
         }
     }
 }
@@ -71,33 +380,18 @@ impl NixHtml for Declaration {
 impl NixHtml for Expression {
     fn as_html(&self) -> Html {
         match self.option_type {
-            nix::ExpressionType::LiteralExpression => Html(self.text.clone()),
-            nix::ExpressionType::LiteralMd => Html(markdown::to_html(&self.text)),
+            nix::ExpressionType::LiteralExpression => Html(escape_html(&self.text)),
+            nix::ExpressionType::LiteralMd => Html(render_markdown(&self.text)),
         }
     }
 }
 
 impl NixHtml for String {
     fn as_html(&self) -> Html {
-        Html(markdown::to_html(self))
+        Html(render_markdown(self))
     }
 }
 
-#[derive(Debug, Deserialize)]
-struct Project {
-    jobsets: Vec<String>,
-}
-
-#[derive(Debug, Deserialize)]
-struct JobsetInput {
-    value: String,
-}
-
-#[derive(Debug, Deserialize)]
-struct Jobset {
-    inputs: HashMap<String, JobsetInput>,
-}
-
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub enum FlakeRev {
     Specific(String),
@@ -105,12 +399,63 @@ pub enum FlakeRev {
     FallbackToCached,
 }
 
+impl Display for FlakeRev {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FlakeRev::Specific(rev) => f.write_str(rev),
+            FlakeRev::Latest => f.write_str("latest"),
+            FlakeRev::FallbackToCached => f.write_str("cached"),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Flake {
     pub owner: String,
     pub name: String,
     pub branch: String,
     pub rev: FlakeRev,
+
+    /// channel name of flakes that are not fc-nixos branches, see [`config::ExtraFlake`]
+    #[serde(default)]
+    pub channel: Option<String>,
+
+    #[serde(default)]
+    pub kind: FlakeKind,
+
+    /// the hydra jobset building the branch, only known for fc-nixos branches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hydra_jobset: Option<String>,
+}
+
+/// determines how the options and packages of a flake are evaluated
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone, Copy)]
+pub enum FlakeKind {
+    /// a branch of fc-nixos
+    #[default]
+    FcNixos,
+    /// a flake exporting nixos modules
+    Modules,
+    /// an upstream nixos channel
+    Nixpkgs,
+}
+
+/// url of the github api without a trailing slash, replaced when the config is reloaded
+static GITHUB_API_URL: LazyLock<ArcSwap<String>> =
+    LazyLock::new(|| ArcSwap::from_pointee(trimmed_url(&GithubConfig::default().api_url)));
+
+/// use the given github api for all following requests
+pub fn set_github_api_url(url: &Url) {
+    GITHUB_API_URL.store(Arc::new(trimmed_url(url)));
+}
+
+/// url of the github api, without a trailing slash
+pub fn github_api_url() -> Arc<String> {
+    GITHUB_API_URL.load_full()
+}
+
+fn trimmed_url(url: &Url) -> String {
+    url.as_str().trim_end_matches('/').to_string()
 }
 
 #[derive(Deserialize)]
@@ -125,7 +470,7 @@ struct GithubBranchInfo {
 }
 
 impl Flake {
-    pub async fn new(owner: &str, name: &str, branch: &str) -> anyhow::Result<Self> {
+    pub async fn new(owner: &str, name: &str, branch: &str) -> error::Result<Self> {
         let rev = Self::get_latest_rev(owner, name, branch)
             .await
             .unwrap_or_else(|_| {
@@ -137,9 +482,43 @@ impl Flake {
             name: name.to_string(),
             branch: branch.to_string(),
             rev,
+            channel: None,
+            kind: FlakeKind::FcNixos,
+            hydra_jobset: None,
         })
     }
 
+    /// resolve a flake from the config file to its latest revision
+    pub async fn extra(extra: &ExtraFlake) -> error::Result<Self> {
+        let (owner, name, branch) = extra.github().map_err(|e| FcSearchError::Config {
+            message: format!("invalid flake {}: {e}", extra.channel),
+        })?;
+        let mut flake = Self::new(&owner, &name, &branch).await?;
+        flake.channel = Some(extra.channel.clone());
+        flake.kind = FlakeKind::Modules;
+        Ok(flake)
+    }
+
+    /// an upstream nixos channel like `nixos-unstable`
+    pub async fn upstream(channel: &str) -> error::Result<Self> {
+        let mut flake = Self::new("nixos", "nixpkgs", channel).await?;
+        flake.kind = FlakeKind::Nixpkgs;
+        Ok(flake)
+    }
+
+    /// the name the flake is served under, fc-nixos branches use their branch name
+    pub fn channel(&self) -> &str {
+        self.channel.as_deref().unwrap_or(&self.branch)
+    }
+
+    /// the channel name as shown to users, upstream channels are marked as such
+    pub fn label(&self) -> String {
+        match self.kind {
+            FlakeKind::Nixpkgs => format!("{} (upstream)", self.channel()),
+            _ => self.channel().to_string(),
+        }
+    }
+
     pub fn flake_uri(&self) -> String {
         match &self.rev {
             FlakeRev::Specific(r) => format!("github:{}/{}?rev={r}", self.owner, self.name),
@@ -154,14 +533,33 @@ impl Flake {
         )
     }
 
-    pub async fn get_latest_rev(owner: &str, name: &str, branch: &str) -> anyhow::Result<FlakeRev> {
+    /// link to the indexed commit, if the revision is known
+    pub fn commit_url(&self) -> Option<String> {
+        self.rev_url(&self.rev)
+    }
+
+    /// link to a commit of the flake's repository, if the revision is known
+    pub fn rev_url(&self, rev: &FlakeRev) -> Option<String> {
+        match rev {
+            FlakeRev::Specific(r) => Some(format!(
+                "https://github.com/{}/{}/commit/{r}",
+                self.owner, self.name
+            )),
+            _ => None,
+        }
+    }
+
+    pub async fn get_latest_rev(owner: &str, name: &str, branch: &str) -> error::Result<FlakeRev> {
         let client = Client::builder()
             .build()
             .expect("could not build request client");
 
         let url = format!(
-            "https://api.github.com/repos/{}/{}/branches/{}",
-            owner, name, branch
+            "{}/repos/{}/{}/branches/{}",
+            github_api_url(),
+            owner,
+            name,
+            branch
         );
 
         let response = client
@@ -170,21 +568,22 @@ impl Flake {
             .header("User-Agent", "fc-search")
             .send()
             .await
-            .context("unable to fetch repository info")?;
-
-        anyhow::ensure!(
-            response.status().is_success(),
-            "response from github was not successful: {}",
-            response
-                .status()
-                .canonical_reason()
-                .unwrap_or("(no canonical reason)")
-        );
+            .network("unable to fetch repository info")?;
+
+        if !response.status().is_success() {
+            return Err(FcSearchError::network(format!(
+                "response from github was not successful: {}",
+                response
+                    .status()
+                    .canonical_reason()
+                    .unwrap_or("(no canonical reason)")
+            )));
+        }
 
         let response_text = response
             .text()
             .await
-            .context("expected to get text for api response from github")?;
+            .network("expected to get text for api response from github")?;
 
         let ghinfo: GithubBranchInfo = match serde_json::from_str(&response_text) {
             Ok(s) => s,
@@ -193,148 +592,136 @@ impl Flake {
                     "did not get json in the expected format from the github api {} {}",
                     response_text, e
                 );
-                anyhow::bail!("invalid json");
+                return Err(FcSearchError::network("invalid json"));
             }
         };
 
-        anyhow::ensure!(
-            ghinfo.name.eq(branch),
-            "got an api response for a different branch: {}",
-            ghinfo.name
-        );
+        if !ghinfo.name.eq(branch) {
+            return Err(FcSearchError::network(format!(
+                "got an api response for a different branch: {}",
+                ghinfo.name
+            )));
+        }
         debug!("latest rev is {}", ghinfo.commit.sha);
 
         Ok(FlakeRev::Specific(ghinfo.commit.sha))
     }
 }
 
-const HYDRA_BASE_URL: &str = "https://hydra.flyingcircus.io";
-
-pub async fn get_fcio_flake_uris() -> anyhow::Result<Vec<Flake>> {
-    let mut headers = HeaderMap::new();
-    headers.insert("Accept", "application/json".parse()?);
-    let client = Client::builder().default_headers(headers).build()?;
-
-    let project_id = "flyingcircus";
-
-    let query_result = client
-        .get(format!("{HYDRA_BASE_URL}/project/{project_id}"))
-        .send()
-        .await?
-        .text()
-        .await?;
-
-    let project: Project = serde_json::from_str(&query_result)?;
-
-    let jobsets: Vec<_> = project
-        .jobsets
-        .iter()
-        .filter(|j| {
-            j.starts_with("fc-")
-                && (j.ends_with("production") || j.ends_with("dev") || j.ends_with("staging"))
-        })
-        .sorted()
-        .collect();
-
-    let mut branches: Vec<String> = Vec::new();
-
-    for jobset_id in jobsets {
-        let jobset = client
-            .get(format!("{HYDRA_BASE_URL}/jobset/{project_id}/{jobset_id}"))
-            .send()
-            .await?
-            .text()
-            .await?;
+/// the nixos release that is currently stable, releases are published in may and november
+fn current_stable_channel() -> String {
+    // rfc3339 timestamps start with `YYYY-MM`
+    let now = humantime::format_rfc3339(std::time::SystemTime::now()).to_string();
+    let year: u32 = now[2..4].parse().expect("rfc3339 timestamp has a year");
+    let month: u32 = now[5..7].parse().expect("rfc3339 timestamp has a month");
+    match month {
+        1..=5 => format!("nixos-{}.11", year - 1),
+        6..=11 => format!("nixos-{year}.05"),
+        _ => format!("nixos-{year}.11"),
+    }
+}
 
-        let jobset: Jobset = serde_json::from_str(&jobset).unwrap();
+/// resolve the upstream nixos channels to index, skipping the ones that fail
+pub async fn get_upstream_flakes(config: &UpstreamConfig) -> Vec<Flake> {
+    if !config.enable {
+        return Vec::new();
+    }
 
-        match jobset.inputs.get("fc") {
-            Some(input) => {
-                let (repo, branch) = input
-                    .value
-                    .split_once(' ')
-                    .expect("value does not have scheme `uri branch`");
+    let channels = config
+        .channels
+        .clone()
+        .unwrap_or_else(|| vec!["nixos-unstable".to_string(), current_stable_channel()]);
 
-                // TODO error handling?
-                assert_eq!(repo, "https://github.com/flyingcircusio/fc-nixos");
-                branches.push(branch.to_string());
-            }
-            _ => {
-                warn!("jobset {:?} has no input fc", jobset);
-            }
+    let mut flakes = Vec::new();
+    for channel in channels {
+        match Flake::upstream(&channel).await {
+            Ok(f) => flakes.push(f),
+            Err(e) => error!(
+                "error fetching information about channel {}: {}",
+                channel,
+                e.report()
+            ),
         }
     }
+    flakes
+}
 
-    // index newest branches first to circumvent rate limits when indexing the more important newer branches
-    branches.sort();
-    branches.reverse();
-
-    // only keep the newest 9 branches => 3 channels (dev, staging + prod each)
-    branches.truncate(3 * 3);
-
+/// resolve all extra flakes from the config file, skipping the ones that fail
+pub async fn get_extra_flakes(extra: &[ExtraFlake]) -> Vec<Flake> {
     let mut flakes = Vec::new();
-    for branch in branches.into_iter() {
-        match Flake::new("flyingcircusio", "fc-nixos", &branch).await {
-            Ok(s) => flakes.push(s),
-            Err(e) => error!("error fetching information about branch {}: {e:?}", branch),
-        };
+    for flake in extra {
+        match Flake::extra(flake).await {
+            Ok(f) => flakes.push(f),
+            Err(e) => error!(
+                "error fetching information about flake {}: {}",
+                flake.channel,
+                e.report()
+            ),
+        }
     }
+    flakes
+}
 
-    info!(
-        "fetched branches {:?} from hydra",
-        flakes.iter().map(|f| f.branch.clone()).collect_vec()
-    );
-
-    Ok(flakes)
+/// link to the file declaring an option, if the declaration is a url
+fn parse_declaration(decl: &str) -> error::Result<Declaration> {
+    // declarations are suffixed with the line of the definition if it is known
+    let (file, line) = match decl.rsplit_once(':') {
+        Some((file, line)) if line.parse::<u32>().is_ok() => (file, Some(line)),
+        _ => (decl, None),
+    };
+    let Ok(mut url) = Url::parse(file) else {
+        return Ok(Declaration::Naive(file.to_string()));
+    };
+    if !url.path().ends_with(".nix") {
+        url = url
+            .join("default.nix")
+            .evaluation(format!("could not link the declaration {decl}"))?;
+    }
+    url.set_fragment(line.map(|l| format!("L{l}")).as_deref());
+    Ok(Declaration::Processed(url))
 }
 
+/// converts the options on all cores, a channel has tens of thousands of them
 pub fn option_to_naive(
     options: &HashMap<String, NixosOption>,
 ) -> HashMap<String, NaiveNixosOption> {
-    let mut out = HashMap::new();
-    for (name, option) in options.iter() {
-        let declarations = option
-            .declarations
-            .iter()
-            .map(|decl| match Url::parse(decl) {
-                Ok(mut url) => {
-                    if !url.path().ends_with(".nix") {
-                        url = url
-                            .join("default.nix")
-                            .expect("could not join url with simple string");
-                    }
-                    Declaration::Processed(url).as_html()
-                }
-                Err(_) => Declaration::Naive(decl.to_string()).as_html(),
-            })
-            .collect_vec();
-
-        out.insert(
-            name.clone(),
-            NaiveNixosOption {
-                name: name.to_string(),
-                declarations,
-                description: option
-                    .description
-                    .clone()
-                    .map(|e| e.as_html())
-                    .unwrap_or_default(),
-                default: option
-                    .default
-                    .clone()
-                    .map(|e| e.as_html())
-                    .unwrap_or_default(),
-                example: option
-                    .example
-                    .clone()
-                    .map(|e| e.as_html())
-                    .unwrap_or_default(),
-                option_type: option.option_type.clone(),
-                read_only: option.read_only,
-            },
-        );
-    }
-    out
+    options
+        .par_iter()
+        .map(|(name, option)| {
+            let declarations = option
+                .declarations
+                .iter()
+                .map(|decl| {
+                    // an odd declaration is only shown as text instead of failing the channel
+                    parse_declaration(decl)
+                        .unwrap_or_else(|e| {
+                            debug!("declaration of {name} is not a link: {}", e.report());
+                            Declaration::Naive(decl.to_string())
+                        })
+                        .as_html()
+                })
+                .collect_vec();
+
+            (
+                name.clone(),
+                NaiveNixosOption {
+                    name: name.to_string(),
+                    declarations,
+                    description: option
+                        .description
+                        .clone()
+                        .map(Markup::Markdown)
+                        .unwrap_or_default(),
+                    default: option.default.clone().map(Markup::from).unwrap_or_default(),
+                    example: option.example.clone().map(Markup::from).unwrap_or_default(),
+                    option_type: option.option_type.clone(),
+                    read_only: option.read_only,
+                    related_packages: option.related_packages.clone(),
+                    set_by_roles: option.set_by_roles.clone(),
+                },
+            )
+        })
+        .collect()
 }
 
 pub trait LogError<T> {
@@ -346,3 +733,53 @@ impl<T, E: Display> LogError<T> for Result<T, E> {
         self.map_err(|e| error!("{}: {e}", context)).ok()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markup_round_trips() {
+        for markup in [
+            Markup::Literal("pkgs.hello".to_string()),
+            Markup::Markdown("*hello*".to_string()),
+            Markup::Rendered("<p>hello</p>".to_string()),
+        ] {
+            let json = serde_json::to_string(&markup).unwrap();
+            assert_eq!(serde_json::from_str::<Markup>(&json).unwrap(), markup);
+        }
+    }
+
+    #[test]
+    fn markup_reads_the_html_of_old_caches() {
+        let markup: Markup = serde_json::from_str(r#""<p>hello</p>""#).unwrap();
+        assert_eq!(markup, Markup::Rendered("<p>hello</p>".to_string()));
+        assert_eq!(markup.html(), Html("<p>hello</p>".to_string()));
+    }
+
+    #[test]
+    fn stored_html_is_sanitized() {
+        for json in [
+            r#""<p>hello</p><script>alert(1)</script>""#,
+            r#"{"kind":"rendered","text":"<p>hello</p><script>alert(1)</script>"}"#,
+        ] {
+            let markup: Markup = serde_json::from_str(json).unwrap();
+            assert_eq!(markup.html(), Html("<p>hello</p>".to_string()));
+        }
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        assert_eq!(escape_html("plain text"), "plain text");
+        assert_eq!(
+            escape_html(r#"<script>alert("x" & 'y')</script>"#),
+            "&lt;script&gt;alert(&quot;x&quot; &amp; &#39;y&#39;)&lt;/script&gt;"
+        );
+    }
+
+    #[test]
+    fn literal_markup_is_escaped() {
+        let markup = Markup::Literal("<b>".to_string());
+        assert_eq!(markup.html(), Html("&lt;b&gt;".to_string()));
+    }
+}
@@ -1,58 +1,281 @@
 #![feature(duration_constructors)]
 
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::process::exit;
 
-use clap::Parser;
+use anyhow::Context;
+use clap::{Parser, Subcommand, ValueEnum};
+use fc_search::config::Config;
 use tempfile::TempDir;
 use tracing::{info, warn};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter};
+use url::Url;
 
 mod backend;
+mod commands;
+mod forwarded;
+mod webhooks;
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
-    /// Port to run on
-    #[arg(short, long, default_value_t = 8000)]
-    port: u16,
-
     /// Path to a state directory for caching indexed data.
-    /// If not provided will cache in memory
-    #[arg(long)]
+    /// If not provided `serve` will cache in memory
+    #[arg(long, global = true)]
     state_dir: Option<PathBuf>,
 
+    /// Path to a toml config file
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Format of the log output on stderr
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Arguments of `serve`, which runs when no subcommand is given
+    #[command(flatten)]
+    serve: ServeArgs,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum LogFormat {
+    /// human readable lines
+    Text,
+    /// one json object per event, for log aggregation
+    Json,
+}
+
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    /// Address to listen on, `host:port` or `unix:/path/to/socket`
+    #[arg(short, long, default_value = "0.0.0.0:8000")]
+    listen: backend::Listen,
+
+    /// Deprecated, use `--listen 0.0.0.0:<port>` instead
+    #[arg(short, long, hide = true, conflicts_with = "listen")]
+    port: Option<u16>,
+
     /// fetch + index a single branch at a specific revision
     /// only use for testing purposes
     /// default behaviour is to fetch all branches from hydra
     /// and build all of them
     #[arg(long)]
     test: bool,
+
+    /// do not index channels, instead serve and hot-reload the indices
+    /// that an external indexer writes to the state directory.
+    /// any number of watching servers can share a state directory on a network filesystem
+    #[arg(long)]
+    watch: bool,
+
+    /// do not contact hydra or github and never evaluate channels,
+    /// only serve the channels that are already indexed in the state directory
+    #[arg(long)]
+    offline: bool,
+
+    /// do not index channels, instead serve the channels another instance publishes
+    /// to the `storage` of the config
+    #[arg(long, conflicts_with_all = ["test", "watch", "offline", "seed_url"])]
+    pull: bool,
+
+    /// Restore a snapshot from this url into an empty state directory before starting,
+    /// see the `snapshot` command
+    #[arg(long)]
+    seed_url: Option<Url>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Index channels and serve the web frontend
+    Serve(ServeArgs),
+
+    Index(commands::index::Args),
+
+    Search(commands::search::Args),
+
+    Diff(commands::diff::Args),
+
+    Export(commands::export::Args),
+
+    ExportSite(commands::site::Args),
+
+    Doctor(commands::doctor::Args),
+
+    Bench(commands::bench::Args),
+
+    /// Write a zstd compressed tarball of the state directory
+    Snapshot {
+        /// File to write the snapshot to
+        output: PathBuf,
+
+        /// Leave out the search indices, they are rebuilt from the evaluated data on restore.
+        /// Makes for much smaller snapshots to publish as `--seed-url`
+        #[arg(long)]
+        without_indices: bool,
+    },
+
+    /// Restore a snapshot into an empty state directory
+    Restore {
+        /// Snapshot file created by the `snapshot` command
+        input: PathBuf,
+    },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    // deployments predating the subcommands start the server without one
+    let command = args.command.unwrap_or(Command::Serve(args.serve));
+
+    let config = match args.config {
+        Some(ref path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    fc_search::search::set_ranking(config.ranking.clone());
+    fc_search::hydra::configure(&config.hydra);
+    fc_search::set_github_api_url(&config.github.api_url);
+
+    // commands printing results keep quiet unless something goes wrong
+    let default_filter = match command {
+        Command::Search(_)
+        | Command::Diff(_)
+        | Command::Export(_)
+        | Command::ExportSite(_)
+        | Command::Doctor(_)
+        | Command::Bench(_) => "fc_search=warn",
+        _ => "fc_search=debug,tokio=trace,runtime=trace",
+    };
 
     // enable tokio-console for testing
-    if args.test {
+    let log_reload = if matches!(command, Command::Serve(ServeArgs { test: true, .. })) {
         console_subscriber::init();
+        None
     } else {
+        let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+            EnvFilter::new(config.log_level.as_deref().unwrap_or(default_filter))
+        });
+        let (filter, handle) = reload::Layer::new(filter);
+        let json = args.log_format == LogFormat::Json;
         tracing_subscriber::registry()
-            .with(
-                tracing_subscriber::EnvFilter::try_from_default_env()
-                    .unwrap_or_else(|_| "fc_search=debug,tokio=trace,runtime=trace".into()),
-            )
-            .with(tracing_subscriber::fmt::layer())
+            .with(filter)
+            .with((!json).then(|| tracing_subscriber::fmt::layer().with_writer(std::io::stderr)))
+            .with(json.then(|| {
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_writer(std::io::stderr)
+            }))
             .init();
+        Some(handle)
+    };
+
+    match command {
+        Command::Serve(serve) => {
+            let reload = backend::Reload {
+                config_path: args.config,
+                log: log_reload,
+                default_log_level: default_filter.to_string(),
+            };
+            run_server(serve, args.state_dir, config, reload).await
+        }
+        Command::Index(index) => {
+            let state_dir = args.state_dir.context("index requires --state-dir")?;
+            commands::index::run(index, &state_dir, config).await
+        }
+        Command::Search(search) => {
+            let state_dir = args.state_dir.context("search requires --state-dir")?;
+            commands::search::run(search, &state_dir)
+        }
+        Command::Diff(diff) => {
+            let state_dir = args.state_dir.context("diff requires --state-dir")?;
+            commands::diff::run(diff, &state_dir)
+        }
+        Command::Export(export) => {
+            let state_dir = args.state_dir.context("export requires --state-dir")?;
+            commands::export::run(export, &state_dir)
+        }
+        Command::ExportSite(site) => {
+            let state_dir = args.state_dir.context("export-site requires --state-dir")?;
+            commands::site::run(site, &state_dir)
+        }
+        Command::Doctor(doctor) => {
+            commands::doctor::run(doctor, args.state_dir.as_deref(), &config).await
+        }
+        Command::Bench(bench) => {
+            let state_dir = args.state_dir.context("bench requires --state-dir")?;
+            commands::bench::run(bench, &state_dir)
+        }
+        Command::Snapshot {
+            output,
+            without_indices,
+        } => {
+            let state_dir = args.state_dir.context("snapshot requires --state-dir")?;
+            // waiting for the update lock blocks
+            tokio::task::spawn_blocking(move || {
+                fc_search::state::snapshot(&state_dir, &output, !without_indices)
+            })
+            .await?
+        }
+        Command::Restore { input } => {
+            let state_dir = args.state_dir.context("restore requires --state-dir")?;
+            tokio::task::spawn_blocking(move || fc_search::state::restore(&input, &state_dir))
+                .await?
+        }
     }
+}
+
+async fn run_server(
+    args: ServeArgs,
+    state_dir: Option<PathBuf>,
+    config: Config,
+    reload: backend::Reload,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !args.watch || state_dir.is_some(),
+        "--watch requires --state-dir"
+    );
+    anyhow::ensure!(
+        !args.offline || state_dir.is_some(),
+        "--offline requires --state-dir"
+    );
+    anyhow::ensure!(
+        !args.watch || args.seed_url.is_none(),
+        "--seed-url can not be combined with --watch, the external indexer owns the state dir"
+    );
+
+    let listen = match args.port {
+        Some(port) => {
+            warn!("--port is deprecated, use --listen 0.0.0.0:{port} instead");
+            backend::Listen::Tcp(SocketAddr::from(([0, 0, 0, 0], port)))
+        }
+        None => args.listen,
+    };
+
+    let mode = if args.pull {
+        backend::Mode::Pull
+    } else if args.watch {
+        backend::Mode::Watch
+    } else if args.offline {
+        backend::Mode::Offline
+    } else {
+        backend::Mode::Index { test: args.test }
+    };
 
-    if let Some(state_dir) = args.state_dir {
+    if let Some(state_dir) = state_dir {
         info!("Persistent state dir is {}", state_dir.display());
-        backend::run(args.port, &state_dir, args.test).await?;
+        if let Some(ref url) = args.seed_url {
+            fc_search::state::seed(url, &state_dir).await?;
+        }
+        backend::run(listen, &state_dir, mode, config, reload).await?;
     } else {
         let temp_state_dir = TempDir::new().unwrap();
         info!("Temporary state dir is {}", temp_state_dir.path().display());
+        if let Some(ref url) = args.seed_url {
+            fc_search::state::seed(url, temp_state_dir.path()).await?;
+        }
 
         // remove the temp dir on ctrl-c
         let path: PathBuf = temp_state_dir.path().to_path_buf();
@@ -64,7 +287,7 @@ async fn main() -> anyhow::Result<()> {
         })
         .expect("failed to set a handler for c-c");
 
-        backend::run(args.port, temp_state_dir.path(), args.test).await?;
+        backend::run(listen, temp_state_dir.path(), mode, config, reload).await?;
     }
 
     Ok(())
@@ -1,15 +1,25 @@
 use itertools::Itertools;
+use rayon::prelude::*;
 use rust_embed::RustEmbed;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Display;
-use std::io::Write;
-use std::path::PathBuf;
-use std::process::Command;
-use tracing::{debug, error};
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
 use url::Url;
 
-use crate::{option_to_naive, Flake, NaiveNixosOption, NixHtml};
+use crate::changelog::{read_changelog, ChangelogEntry};
+use crate::config::NixConfig;
+use crate::error::{self, ErrorClass, FcSearchError};
+use crate::progress::{Progress, ProgressReporter};
+use crate::{escape_html, option_to_naive, Flake, FlakeKind, NaiveNixosOption, NixHtml};
 
 #[derive(Deserialize, Debug, Serialize, Clone)]
 pub enum ExpressionType {
@@ -37,6 +47,12 @@ pub struct NixosOption {
     pub read_only: bool,
     #[serde(rename = "type")]
     pub option_type: String,
+    /// attribute paths of the packages listed in `relatedPackages`
+    #[serde(rename = "relatedPackageNames", default)]
+    pub related_packages: Vec<String>,
+    /// fc-nixos roles whose modules define a value for the option
+    #[serde(rename = "setByRoles", default)]
+    pub set_by_roles: Vec<String>,
 }
 
 #[derive(Deserialize, Debug, Serialize, Clone, PartialEq, Eq, Hash)]
@@ -57,7 +73,7 @@ pub enum License {
 impl NixHtml for License {
     fn as_html(&self) -> crate::Html {
         match self {
-            Self::Verbatim(s) => crate::Html(format!("<p>{s}</p>")),
+            Self::Verbatim(s) => crate::Html(format!("<p>{}</p>", escape_html(s))),
             Self::Informative {
                 full_name,
                 short_name,
@@ -74,9 +90,11 @@ impl NixHtml for License {
                         })
                         .to_string()
                 });
+                let x = escape_html(&x);
                 match url {
                     Some(ref u) => crate::Html(format!(
-                        "<p><a class=\"text-blue-900 hover:underline\" href={u}>{x}</a></p>"
+                        "<p><a class=\"text-blue-900 hover:underline\" href=\"{}\">{x}</a></p>",
+                        escape_html(u.as_str())
                     )),
                     None => crate::Html(format!("<p>{x}</p>")),
                 }
@@ -101,15 +119,16 @@ impl<T: NixHtml + Eq + std::hash::Hash> NixHtml for Plurality<T> {
             Self::None => crate::Html("<p></p>".to_string()),
             Self::Single(l) => l.as_html(),
             Self::Multiple(m) => crate::Html(m.iter().unique().map(|f| f.as_html().0).join("")),
-            Self::Fallback(m) => crate::Html(format!("<code>{m}</code>")),
+            Self::Fallback(m) => crate::Html(format!("<code>{}</code>", escape_html(m))),
         }
     }
 }
 
 impl NixHtml for Url {
     fn as_html(&self) -> crate::Html {
+        let url = escape_html(self.as_str());
         crate::Html(format!(
-            "<p><a class=\"text-blue-900 hover:underline\" href={self}>{self}</a></p>",
+            "<p><a class=\"text-blue-900 hover:underline\" href=\"{url}\">{url}</a></p>",
         ))
     }
 }
@@ -134,107 +153,641 @@ pub struct NixPackage {
     pub version: Option<String>,
     #[serde(default)]
     pub homepage: Plurality<Url>,
+    #[serde(default)]
+    pub maintainers: Vec<Maintainer>,
+    /// systems the package is available on
+    #[serde(default)]
+    pub systems: Vec<String>,
+    /// security issues of this version, packages with known vulnerabilities are marked insecure
+    #[serde(rename = "knownVulnerabilities", default)]
+    pub known_vulnerabilities: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct Maintainer {
+    pub name: Option<String>,
+    pub github: Option<String>,
 }
 
 #[derive(RustEmbed)]
 #[folder = "nix/"]
 struct NixFiles;
 
-#[tracing::instrument(skip(flake), fields(branch = flake.branch))]
-pub fn build_options_for_fcio_branch(
+/// output of `nix flake metadata --json`
+#[derive(Deserialize, Debug)]
+struct FlakeMetadata {
+    locked: LockedGithubRef,
+    path: PathBuf,
+}
+
+/// a github flake reference pinned to a revision
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LockedGithubRef {
+    owner: String,
+    repo: String,
+    rev: String,
+    nar_hash: String,
+}
+
+impl LockedGithubRef {
+    fn flake_ref(&self) -> String {
+        locked_github_ref(&self.owner, &self.repo, &self.rev, &self.nar_hash)
+    }
+}
+
+/// output of `nix flake prefetch --json`
+#[derive(Deserialize, Debug)]
+struct FlakePrefetch {
+    hash: String,
+}
+
+/// the part of the `versions.json` of fc-nixos pinning nixpkgs
+#[derive(Deserialize, Debug)]
+struct Versions {
+    nixpkgs: PinnedVersion,
+}
+
+#[derive(Deserialize, Debug)]
+struct PinnedVersion {
+    rev: String,
+}
+
+/// flake references that were fetched into the store ahead of the evaluation,
+/// pinned by revision and content hash so the evaluation can run in pure mode
+#[derive(Debug)]
+struct Prefetched {
+    flake_ref: String,
+    /// nixpkgs of fc-nixos branches, which is pinned in `versions.json` instead of a flake input
+    nixpkgs_ref: Option<String>,
+}
+
+fn locked_github_ref(owner: &str, repo: &str, rev: &str, nar_hash: &str) -> String {
+    let nar_hash: String = url::form_urlencoded::byte_serialize(nar_hash.as_bytes()).collect();
+    format!("github:{owner}/{repo}/{rev}?narHash={nar_hash}")
+}
+
+/// the nix system double of the machine we run on
+fn nix_system() -> String {
+    let os = match std::env::consts::OS {
+        "macos" => "darwin",
+        os => os,
+    };
+    format!("{}-{os}", std::env::consts::ARCH)
+}
+
+/// the configured systems as a nix list, the system we run on is evaluated first
+fn nix_systems(nix_config: &NixConfig) -> String {
+    let own = nix_system();
+    let systems = nix_config
+        .systems
+        .iter()
+        .sorted_by_key(|s| **s != own)
+        .map(|s| format!("{s:?}"))
+        .join(" ");
+    format!("[ {systems} ]")
+}
+
+/// deserialize a json file of a build output while reading it, they can be large
+fn read_build_json<T: DeserializeOwned>(path: &Path) -> error::Result<T> {
+    let file =
+        std::fs::File::open(path).evaluation(format!("could not read {}", path.display()))?;
+    serde_json::from_reader(BufReader::new(file))
+        .evaluation(format!("could not deserialize {}", path.display()))
+}
+
+/// run a nix command that prints json and parse its output, see [`run_watched`]
+fn nix_json<T: DeserializeOwned>(
+    nix_config: &NixConfig,
+    args: &[&str],
+    cancel: &CancellationToken,
+) -> error::Result<T> {
+    let what = format!("nix {}", args.join(" "));
+    let mut cmd = nix_command(nix_config);
+    cmd.args(args);
+    let mut stderr = String::new();
+    let (status, stdout) = run_watched(cmd, &what, nix_config, cancel, |line| {
+        stderr.push_str(line);
+        stderr.push('\n');
+    })?;
+    if !status.success() {
+        return Err(FcSearchError::evaluation(format!(
+            "{what} failed\n{stderr}"
+        )));
+    }
+    serde_json::from_slice(&stdout).evaluation(format!("unexpected output of {what}"))
+}
+
+/// version of the nix used for evaluations, fails if it can not evaluate flakes
+pub fn check_nix(nix_config: &NixConfig) -> error::Result<String> {
+    let cancel = CancellationToken::new();
+    let flakes: bool = nix_json(
+        nix_config,
+        &["eval", "--json", "--expr", "builtins ? getFlake"],
+        &cancel,
+    )?;
+    if !flakes {
+        return Err(FcSearchError::evaluation("nix can not evaluate flakes"));
+    }
+    nix_json(
+        nix_config,
+        &["eval", "--json", "--expr", "builtins.nixVersion"],
+        &cancel,
+    )
+}
+
+/// fetching fails mostly because github or a substituter can not be reached
+fn fetch_error(message: &str, error: FcSearchError) -> FcSearchError {
+    FcSearchError::Network {
+        message: message.to_string(),
+        source: Some(error.into()),
+    }
+}
+
+/// fetch the flake, its inputs and the nixpkgs of fc-nixos into the store.
+/// fetching is watched like the build, a hanging download does not block the update forever
+fn prefetch(
     flake: &Flake,
-) -> anyhow::Result<(
-    HashMap<String, NaiveNixosOption>,
-    HashMap<String, NixPackage>,
-)> {
-    let eval_nixfile = {
-        let data = NixFiles::get("eval.nix").unwrap().data;
-        let mut tmp = tempfile::NamedTempFile::new()?;
-        tmp.write_all(&data)?;
-        tmp
+    nix_config: &NixConfig,
+    cancel: &CancellationToken,
+) -> error::Result<Prefetched> {
+    debug!("prefetching {}", flake.flake_uri());
+    let metadata: FlakeMetadata = nix_json(
+        nix_config,
+        &["flake", "metadata", "--json", &flake.flake_uri()],
+        cancel,
+    )
+    .map_err(|e| fetch_error("could not fetch the flake", e))?;
+    let flake_ref = metadata.locked.flake_ref();
+    let _: serde_json::Value = nix_json(
+        nix_config,
+        &["flake", "archive", "--json", &flake_ref],
+        cancel,
+    )
+    .map_err(|e| fetch_error("could not fetch the inputs of the flake", e))?;
+
+    let nixpkgs_ref = match flake.kind {
+        FlakeKind::FcNixos => {
+            let versions_json = ["release/versions.json", "versions.json"]
+                .into_iter()
+                .map(|p| metadata.path.join(p))
+                .find(|p| p.exists())
+                .evaluation("fc-nixos has no versions.json")?;
+            let versions: Versions = serde_json::from_str(
+                &std::fs::read_to_string(versions_json)
+                    .evaluation("could not read versions.json")?,
+            )
+            .evaluation("could not parse versions.json")?;
+
+            let nixpkgs_uri = format!("github:nixos/nixpkgs/{}", versions.nixpkgs.rev);
+            let prefetched: FlakePrefetch = nix_json(
+                nix_config,
+                &["flake", "prefetch", "--json", &nixpkgs_uri],
+                cancel,
+            )
+            .map_err(|e| fetch_error("could not fetch nixpkgs", e))?;
+            Some(locked_github_ref(
+                "nixos",
+                "nixpkgs",
+                &versions.nixpkgs.rev,
+                &prefetched.hash,
+            ))
+        }
+        _ => None,
     };
 
-    debug!("starting nix-instantiate");
-    let derivation_cmd = Command::new("nix-instantiate")
-        .arg(eval_nixfile.path())
-        .args(["--argstr", "flake", &flake.flake_uri()])
-        .output()?;
-
-    drop(eval_nixfile);
-
-    if !derivation_cmd.status.success() {
-        let stderr = String::from_utf8(derivation_cmd.stderr).expect("valid utf-8 in stderr");
-        error!("failed instantiating: {}", stderr);
-        anyhow::bail!(
-            "failed to instantiate options for {}\nstderr: {}",
-            flake.flake_uri(),
-            stderr
-        );
+    debug!("prefetched {}", flake_ref);
+    Ok(Prefetched {
+        flake_ref,
+        nixpkgs_ref,
+    })
+}
+
+/// result entry of `nix build --json`
+#[derive(Deserialize, Debug)]
+struct NixBuildResult {
+    outputs: HashMap<String, PathBuf>,
+}
+
+/// a line of `--log-format internal-json` output, prefixed with `@nix `
+#[derive(Deserialize, Debug)]
+struct NixLogLine {
+    action: String,
+    level: Option<u8>,
+    msg: Option<String>,
+    text: Option<String>,
+}
+
+/// messages collected from the structured log of a nix invocation
+#[derive(Debug, Default)]
+struct NixLog {
+    errors: Vec<String>,
+    /// evaluation warnings and `builtins.trace` output
+    warnings: Vec<String>,
+}
+
+impl NixLog {
+    fn process(&mut self, line: &str) {
+        let Some(entry) = line
+            .strip_prefix("@nix ")
+            .and_then(|json| serde_json::from_str::<NixLogLine>(json).ok())
+        else {
+            debug!("nix: {}", line);
+            return;
+        };
+
+        match (entry.action.as_str(), entry.level, entry.msg, entry.text) {
+            ("msg", Some(0), Some(msg), _) if msg.starts_with("trace:") => self.warnings.push(msg),
+            ("msg", Some(0), Some(msg), _) => self.errors.push(msg),
+            ("msg", Some(1), Some(msg), _) => self.warnings.push(msg),
+            ("msg", _, Some(msg), _) => debug!("nix: {}", msg),
+            ("start", _, _, Some(text)) if !text.is_empty() => debug!("nix: {}", text),
+            _ => {}
+        }
     }
-    debug!("finished nix-instantiate");
-
-    let derivation_output = std::str::from_utf8(&derivation_cmd.stdout)
-        .expect("valid utf-8")
-        .trim_end();
-
-    debug!("starting nix-build");
-    let build_cmd = Command::new("nix-build")
-        .arg("--no-out-link")
-        .arg(derivation_output)
-        .output()?;
-
-    if !build_cmd.status.success() {
-        let stderr = String::from_utf8(build_cmd.stderr).expect("valid utf-8 in stderr");
-        error!("failed building: {}", stderr);
-        anyhow::bail!(
-            "failed to build options for {}\nstderr: {}",
-            flake.flake_uri(),
-            stderr
-        );
+}
+
+/// the `nix` command, wrapped in a resource limited systemd scope if configured
+fn nix_command(nix_config: &NixConfig) -> Command {
+    let limits = &nix_config.limits;
+    let mut cmd = if limits.is_empty() {
+        Command::new("nix")
+    } else {
+        let mut cmd = Command::new("systemd-run");
+        cmd.args(["--scope", "--quiet", "--collect"]);
+        if limits.user {
+            cmd.arg("--user");
+        }
+        if let Some(ref memory_max) = limits.memory_max {
+            cmd.arg("-p").arg(format!("MemoryMax={memory_max}"));
+        }
+        if let Some(ref cpu_quota) = limits.cpu_quota {
+            cmd.arg("-p").arg(format!("CPUQuota={cpu_quota}"));
+        }
+        cmd.arg("nix");
+        cmd
+    };
+    cmd.args(["--extra-experimental-features", "nix-command flakes"]);
+    for (name, value) in &nix_config.settings {
+        cmd.arg("--option").arg(name).arg(value);
     }
-    debug!("finished nix-build");
+    cmd
+}
+
+/// an indirect gc root keeping a build output in the store while it is read
+struct GcRoot {
+    dir: tempfile::TempDir,
+}
 
-    let build_output = std::str::from_utf8(&build_cmd.stdout)
-        .expect("valid utf-8")
-        .trim_end();
+impl GcRoot {
+    fn new() -> error::Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix("fc-search-gcroot")
+            .tempdir()
+            .evaluation("could not create the directory of the gc root")?;
+        Ok(Self { dir })
+    }
 
-    let path = PathBuf::from(build_output);
+    /// the out link of the build, nix registers it as root
+    fn link(&self) -> PathBuf {
+        self.dir.path().join("result")
+    }
+
+    /// remove the out link, nix treats roots pointing to missing links as stale
+    fn release(self) {
+        let link = self.link();
+        match self.dir.close() {
+            Ok(()) => debug!("released the gc root {}", link.display()),
+            Err(e) => warn!("could not release the gc root {}: {e}", link.display()),
+        }
+    }
+}
+
+/// delete unreferenced store paths, at most the configured amount
+fn collect_store_garbage(nix_config: &NixConfig) {
+    let mut cmd = nix_command(nix_config);
+    cmd.args(["store", "gc"]);
+    if let Some(ref max) = nix_config.gc.max {
+        cmd.args(["--max", max]);
+    }
+    debug!("collecting the garbage of the nix store");
+    match cmd.output() {
+        Ok(output) if output.status.success() => {
+            info!(
+                "collected the garbage of the nix store: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(output) => warn!(
+            "nix store gc failed\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        ),
+        Err(e) => warn!("could not run nix store gc: {e}"),
+    }
+}
+
+/// how often a running nix build checks whether it was cancelled
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// why the watchdog of a nix build killed it
+enum Killed {
+    TimedOut,
+    Cancelled,
+}
+
+/// run a nix command in its own process group and return its exit status and output,
+/// every line of its stderr is passed to `on_stderr`.
+/// nix and all processes it spawned are killed once the configured timeout expires or `cancel` is cancelled
+fn run_watched(
+    mut cmd: Command,
+    what: &str,
+    nix_config: &NixConfig,
+    cancel: &CancellationToken,
+    mut on_stderr: impl FnMut(&str),
+) -> error::Result<(ExitStatus, Vec<u8>)> {
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .evaluation(format!("could not start {what}"))?;
+
+    let pid = child.id() as libc::pid_t;
+    let timeout = nix_config.timeout;
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    let cancel = cancel.clone();
+    let watchdog = std::thread::spawn(move || {
+        let started = Instant::now();
+        let killed = loop {
+            let left = timeout.saturating_sub(started.elapsed());
+            if let Ok(()) | Err(RecvTimeoutError::Disconnected) =
+                done_rx.recv_timeout(left.min(CANCEL_POLL_INTERVAL))
+            {
+                return None;
+            }
+            if cancel.is_cancelled() {
+                break Killed::Cancelled;
+            }
+            if started.elapsed() >= timeout {
+                break Killed::TimedOut;
+            }
+        };
+        // SAFETY: kill has no memory safety preconditions, a negative pid
+        // addresses the process group nix was started in
+        unsafe { libc::kill(-pid, libc::SIGKILL) };
+        Some(killed)
+    });
+
+    // both pipes are drained at the same time, nix blocks once the one that is not read is full
+    let mut stdout = child.stdout.take().expect("stdout of nix is piped");
+    let stdout_reader = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+
+    let stderr = child.stderr.take().expect("stderr of nix is piped");
+    for line in BufReader::new(stderr).lines() {
+        on_stderr(&line.evaluation(format!("could not read the log of {what}"))?);
+    }
+    let status = child.wait();
+    let _ = done_tx.send(());
+    let killed = watchdog.join().unwrap_or(None);
+    let status = status.evaluation(format!("{what} failed to run"))?;
+    let stdout = stdout_reader
+        .join()
+        .expect("reading the output of nix panicked")
+        .evaluation(format!("could not read the output of {what}"))?;
+
+    match killed {
+        Some(Killed::TimedOut) => Err(FcSearchError::evaluation(format!(
+            "{what} timed out after {}",
+            humantime::format_duration(timeout)
+        ))),
+        Some(Killed::Cancelled) => Err(FcSearchError::evaluation(format!("{what} was cancelled"))),
+        None => Ok((status, stdout)),
+    }
+}
+
+/// build the eval file for the prefetched flake and return the output path and the evaluation warnings.
+/// the output is kept alive by `gc_root` until it is released, the build is watched by [`run_watched`]
+fn nix_build(
+    eval_file: &Path,
+    prefetched: &Prefetched,
+    pure: bool,
+    gc_root: &GcRoot,
+    nix_config: &NixConfig,
+    cancel: &CancellationToken,
+) -> error::Result<(PathBuf, Vec<String>)> {
+    debug!("starting nix build");
+    let mut cmd = nix_command(nix_config);
+    cmd.arg("build")
+        .arg("--json")
+        .arg("--out-link")
+        .arg(gc_root.link())
+        .args(["--log-format", "internal-json"])
+        .arg("--file")
+        .arg(eval_file)
+        .args(["--argstr", "flake", &prefetched.flake_ref])
+        .args(["--argstr", "system", &nix_system()])
+        .args(["--arg", "systems", &nix_systems(nix_config)])
+        .args(["--arg", "homeManager", &nix_config.home_manager.to_string()]);
+    if !pure {
+        cmd.arg("--impure");
+    }
+    if let Some(ref nixpkgs_ref) = prefetched.nixpkgs_ref {
+        cmd.args(["--argstr", "nixpkgsRef", nixpkgs_ref]);
+    }
+    let mut log = NixLog::default();
+    let (status, stdout) = run_watched(
+        cmd,
+        &format!("nix build for {}", prefetched.flake_ref),
+        nix_config,
+        cancel,
+        |line| log.process(line),
+    )?;
+
+    if !status.success() {
+        let errors = log.errors.join("\n");
+        error!("failed building: {}", errors);
+        return Err(FcSearchError::evaluation(format!(
+            "failed to build options for {}\n{}",
+            prefetched.flake_ref, errors
+        )));
+    }
+    debug!("finished nix build");
+
+    let results: Vec<NixBuildResult> =
+        serde_json::from_slice(&stdout).evaluation("unexpected output of nix build")?;
+    let path = results
+        .into_iter()
+        .next()
+        .and_then(|mut r| r.outputs.remove("out"))
+        .evaluation("nix build did not report an output path")?;
+
+    if !log.warnings.is_empty() {
+        warn!("evaluation printed {} warnings", log.warnings.len());
+    }
+    Ok((path, log.warnings))
+}
+
+/// the nixpkgs a channel was evaluated with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NixpkgsInput {
+    pub rev: String,
+    /// hash of the source as in a flake lock, like `sha256-...`
+    pub nar_hash: String,
+}
+
+impl NixpkgsInput {
+    pub fn commit_url(&self) -> String {
+        format!("https://github.com/nixos/nixpkgs/commit/{}", self.rev)
+    }
+}
+
+/// everything produced by evaluating a channel
+pub struct ChannelBuild {
+    pub options: HashMap<String, NaiveNixosOption>,
+    pub packages: HashMap<String, NixPackage>,
+    /// only evaluated if enabled in the [`NixConfig`]
+    pub home_manager_options: Option<HashMap<String, NaiveNixosOption>>,
+    pub changelog: HashMap<String, ChangelogEntry>,
+    /// warnings and traces printed during the evaluation
+    pub warnings: Vec<String>,
+    pub nixpkgs: NixpkgsInput,
+}
+
+#[tracing::instrument(skip(flake, nix_config, progress, cancel), fields(branch = flake.channel()))]
+pub fn build_options_for_fcio_branch(
+    flake: &Flake,
+    nix_config: &NixConfig,
+    progress: &ProgressReporter,
+    cancel: &CancellationToken,
+) -> error::Result<ChannelBuild> {
+    // extra flakes only provide nixos modules and packages, not a whole platform like fc-nixos
+    let eval_file = match flake.kind {
+        FlakeKind::FcNixos => "eval.nix",
+        FlakeKind::Modules => "eval-modules.nix",
+        FlakeKind::Nixpkgs => "eval-nixpkgs.nix",
+    };
+    // the eval files import the shared helpers next to them, so they are added to the store together
+    let eval_dir = {
+        let dir = tempfile::tempdir().evaluation("could not create the eval directory")?;
+        for name in NixFiles::iter() {
+            let data = NixFiles::get(&name).unwrap().data;
+            std::fs::write(dir.path().join(name.as_ref()), &data)
+                .evaluation("could not write the eval files")?;
+        }
+        dir
+    };
+
+    progress.report(Progress::Fetching);
+    let prefetched = prefetch(flake, nix_config, cancel)?;
+
+    // pure evaluation can only read from the store
+    let eval_store_path = {
+        let output = nix_command(nix_config)
+            .args(["store", "add-path", "--name", "fc-search-eval"])
+            .arg(eval_dir.path())
+            .output()
+            .evaluation("could not add the eval files to the store")?;
+        if !output.status.success() {
+            return Err(FcSearchError::evaluation(format!(
+                "could not add the eval files to the store\n{}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+        PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()).join(eval_file)
+    };
+    drop(eval_dir);
+
+    // home-manager is looked up by its release branch, which is not pinned anywhere
+    let pure = !(nix_config.home_manager && flake.kind == FlakeKind::FcNixos);
+
+    progress.report(Progress::Evaluating);
+    let gc_root = GcRoot::new()?;
+    let (path, warnings) = nix_build(
+        &eval_store_path,
+        &prefetched,
+        pure,
+        &gc_root,
+        nix_config,
+        cancel,
+    )?;
+    progress.report(Progress::Built);
 
     debug!("build output path is `{}`", path.display());
 
-    let options_json = std::fs::read_to_string(path.join("options.json")).unwrap();
-    let packages_json = std::fs::read_to_string(path.join("packages.json")).unwrap();
-    let nixpkgs_path = std::fs::read_to_string(path.join("nixpkgs"))
-        .expect("could not read path to nixpkgs in store")
-        .trim()
-        .to_string();
-    let fc_nixos_path = std::fs::read_to_string(path.join("fc-nixos"))
-        .expect("could not read path to fc-nixos in store")
-        .trim()
-        .to_string();
+    let read_path = |name: &str| {
+        std::fs::read_to_string(path.join(name))
+            .map(|p| p.trim().to_string())
+            .evaluation(format!("could not read {name} from the build output"))
+    };
+    let nixpkgs_path = read_path("nixpkgs")?;
+    let nixpkgs_rev = read_path("nixpkgs-rev")?;
+    let source_path = read_path("source")?;
 
     debug!("nixpkgs path is `{}`", nixpkgs_path);
-    debug!("fc_nixos path is `{}`", fc_nixos_path);
-
-    // TODO infer actual nixpkgs url from versions
-    let nixpkgs_url = "https://github.com/nixos/nixpkgs/blob/master";
-
-    let packages = serde_json::from_str(&packages_json)?;
-    let options =
-        serde_json::from_str(&options_json).map(|mut options: HashMap<String, NixosOption>| {
-            for (_, option) in options.iter_mut() {
-                for declaration in option.declarations.iter_mut() {
-                    let decl = if declaration.starts_with(&nixpkgs_path) {
-                        declaration.replace(&nixpkgs_path, nixpkgs_url)
-                    } else {
-                        declaration.replace(&fc_nixos_path, &flake.github_base_url())
-                    };
-
-                    *declaration = decl;
-                }
-            }
-            options
-        })?;
+    debug!("nixpkgs rev is `{}`", nixpkgs_rev);
+    let nixpkgs = NixpkgsInput {
+        rev: nixpkgs_rev.clone(),
+        nar_hash: read_path("nixpkgs-nar-hash")?,
+    };
+    debug!("flake path is `{}`", source_path);
+
+    // link to the exact revision that was evaluated so the links don't rot
+    let nixpkgs_url = format!("https://github.com/nixos/nixpkgs/blob/{nixpkgs_rev}");
+
+    let packages = read_build_json(&path.join("packages.json"))?;
+    let mut options: HashMap<String, NixosOption> = read_build_json(&path.join("options.json"))?;
+    options.par_iter_mut().for_each(|(_, option)| {
+        for declaration in option.declarations.iter_mut() {
+            let decl = if declaration.starts_with(&nixpkgs_path) {
+                declaration.replace(&nixpkgs_path, &nixpkgs_url)
+            } else {
+                declaration.replace(&source_path, &flake.github_base_url())
+            };
+
+            *declaration = decl;
+        }
+    });
     let options = option_to_naive(&options);
-    Ok((options, packages))
+
+    let home_manager_options = if path.join("home-manager-options.json").exists() {
+        let hm_path = read_path("home-manager")?;
+        let hm_url = format!(
+            "https://github.com/nix-community/home-manager/blob/{}",
+            read_path("home-manager-rev")?
+        );
+        let mut hm_options: HashMap<String, NixosOption> =
+            read_build_json(&path.join("home-manager-options.json"))?;
+        hm_options.par_iter_mut().for_each(|(_, option)| {
+            for declaration in option.declarations.iter_mut() {
+                *declaration = declaration.replace(&hm_path, &hm_url);
+            }
+        });
+        Some(option_to_naive(&hm_options))
+    } else {
+        None
+    };
+
+    // only fc-nixos keeps release notes in a known place
+    let changelog = match flake.kind {
+        FlakeKind::FcNixos => read_changelog(Path::new(&source_path)),
+        _ => HashMap::new(),
+    };
+
+    // everything was read from the output, so it may be collected
+    gc_root.release();
+    if nix_config.gc.enable {
+        collect_store_garbage(nix_config);
+    }
+
+    Ok(ChannelBuild {
+        options,
+        packages,
+        home_manager_options,
+        changelog,
+        warnings,
+        nixpkgs,
+    })
 }
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::sync::{Arc, RwLock};
+use tracing::info;
+
+/// stage of a running channel update
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum Progress {
+    #[default]
+    Idle,
+    Fetching,
+    Evaluating,
+    Built,
+    Indexing {
+        corpus: Cow<'static, str>,
+        indexed: usize,
+        total: usize,
+    },
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Progress::Idle => f.write_str("idle"),
+            Progress::Fetching => f.write_str("fetching sources"),
+            Progress::Evaluating => f.write_str("evaluating"),
+            Progress::Built => f.write_str("nix build finished"),
+            Progress::Indexing {
+                corpus,
+                indexed,
+                total,
+            } => write!(f, "indexing {corpus}: {indexed} of {total}"),
+        }
+    }
+}
+
+/// handle through which an update reports its progress.
+/// clones share the same state, so a cloned searcher reports to the original
+#[derive(Debug, Default, Clone)]
+pub struct ProgressReporter {
+    current: Arc<RwLock<Progress>>,
+}
+
+impl ProgressReporter {
+    pub fn report(&self, progress: Progress) {
+        if !matches!(progress, Progress::Indexing { .. }) {
+            info!(%progress, "update progress");
+        }
+        *self.current.write().unwrap() = progress;
+    }
+
+    pub fn current(&self) -> Progress {
+        self.current.read().unwrap().clone()
+    }
+
+    /// reset the progress to idle once the returned guard is dropped, on every exit path
+    pub fn idle_on_drop(&self) -> IdleOnDrop<'_> {
+        IdleOnDrop(self)
+    }
+}
+
+/// see [`ProgressReporter::idle_on_drop`]
+pub struct IdleOnDrop<'a>(&'a ProgressReporter);
+
+impl Drop for IdleOnDrop<'_> {
+    fn drop(&mut self) {
+        if self.0.current() != Progress::Idle {
+            self.0.report(Progress::Idle);
+        }
+    }
+}
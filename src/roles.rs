@@ -0,0 +1,46 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+
+use crate::{Markup, NaiveNixosOption};
+
+/// namespace of the options of the fc-nixos roles
+pub const ROLES_PREFIX: &str = "flyingcircus.roles.";
+
+/// a fc-nixos role and the options it provides
+#[derive(Debug, Serialize, Clone)]
+pub struct Role {
+    pub name: String,
+    /// description of the `enable` option of the role
+    pub description: Markup,
+    /// sorted names of the options below the namespace of the role
+    pub options: Vec<String>,
+}
+
+/// the roles with options in `options`, keyed by their name
+pub fn extract_roles(options: &HashMap<String, NaiveNixosOption>) -> BTreeMap<String, Role> {
+    let mut roles: BTreeMap<String, Role> = BTreeMap::new();
+    for (option_name, option) in options {
+        let Some((role_name, rest)) = option_name
+            .strip_prefix(ROLES_PREFIX)
+            .and_then(|name| name.split_once('.'))
+        else {
+            continue;
+        };
+
+        let role = roles.entry(role_name.to_string()).or_insert_with(|| Role {
+            name: role_name.to_string(),
+            description: Markup::default(),
+            options: Vec::new(),
+        });
+        if rest == "enable" {
+            role.description = option.description.clone();
+        }
+        role.options.push(option_name.clone());
+    }
+
+    for role in roles.values_mut() {
+        role.options.sort();
+    }
+    roles
+}
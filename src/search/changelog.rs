@@ -0,0 +1,174 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use tantivy::collector::{Collector, TopDocs};
+use tantivy::query::{BooleanQuery, BoostQuery, FuzzyTermQuery, Occur, Query, TermQuery};
+use tantivy::schema::{Schema, TextFieldIndexing, TextOptions, TEXT};
+use tantivy::{DocId, Document, Score, SegmentReader, Term};
+
+use super::{
+    name_ranks, open_or_create_index, FCFruit, GenericSearcher, Searcher, SearcherInner,
+    NAME_RANK_FIELD,
+};
+use crate::changelog::ChangelogEntry;
+use crate::error::{self, ErrorClass, FcSearchError};
+use crate::progress::Progress;
+
+impl Searcher for GenericSearcher<ChangelogEntry> {
+    type Item = ChangelogEntry;
+
+    fn parse_query(&self, query_string: &str) -> Box<dyn Query> {
+        let Some(ref inner) = self.inner else {
+            unreachable!("searcher not initialized, cannot parse");
+        };
+
+        let title = inner.schema.get_field("title").unwrap();
+        let text = inner.schema.get_field("text").unwrap();
+        let mut subqueries: Vec<(Occur, Box<dyn Query>)> = vec![];
+
+        for word in query_string.split(' ').filter(|w| !w.is_empty()) {
+            let word = word.to_lowercase();
+            let title_term = Term::from_field_text(title, &word);
+            let text_term = Term::from_field_text(text, &word);
+
+            // release names like `2024_001` are searched for exactly
+            subqueries.push((
+                Occur::Should,
+                Box::new(BoostQuery::new(
+                    Box::new(TermQuery::new(
+                        title_term,
+                        tantivy::schema::IndexRecordOption::Basic,
+                    )),
+                    2.,
+                )),
+            ));
+
+            subqueries.push((
+                Occur::Should,
+                Box::new(TermQuery::new(
+                    text_term.clone(),
+                    tantivy::schema::IndexRecordOption::WithFreqsAndPositions,
+                )),
+            ));
+
+            if word.len() > 3 {
+                let fq = FuzzyTermQuery::new_prefix(text_term, 1, true);
+                subqueries.push((Occur::Should, Box::new(BoostQuery::new(Box::new(fq), 0.5))));
+            }
+        }
+
+        Box::new(BooleanQuery::new(subqueries))
+    }
+
+    fn create_index(&mut self) -> error::Result<()> {
+        let mut schema_builder = Schema::builder();
+
+        let raw_stored = TextOptions::default()
+            .set_indexing_options(
+                TextFieldIndexing::default()
+                    .set_index_option(tantivy::schema::IndexRecordOption::Basic)
+                    .set_tokenizer("raw"),
+            )
+            .set_stored();
+
+        let key = schema_builder.add_text_field("key", raw_stored);
+        schema_builder.add_text_field("title", TEXT);
+        schema_builder.add_text_field("text", TEXT);
+        schema_builder.add_f64_field("position", tantivy::schema::FAST);
+        schema_builder.add_u64_field(NAME_RANK_FIELD, tantivy::schema::FAST);
+        let schema = schema_builder.build();
+
+        let index = open_or_create_index(&self.index_path, &schema)?;
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(tantivy::ReloadPolicy::OnCommit)
+            .try_into()
+            .unwrap();
+
+        self.map = HashMap::new();
+        self.inner = Some(SearcherInner {
+            schema,
+            index,
+            reader,
+            reference_field: key,
+        });
+
+        Ok(())
+    }
+
+    fn update_entries(&mut self, entries: HashMap<String, Self::Item>) -> error::Result<()> {
+        let Some(ref inner) = self.inner else {
+            return Err(FcSearchError::query(
+                "can not update the changelog before index creation",
+            ));
+        };
+
+        let schema = &inner.schema;
+        let mut index_writer = inner
+            .index
+            .writer(50_000_000)
+            .query("could not create the index writer")?;
+
+        let key = schema.get_field("key").expect("the field key should exist");
+        let title = schema
+            .get_field("title")
+            .expect("the field title should exist");
+        let text = schema
+            .get_field("text")
+            .expect("the field text should exist");
+        let position = schema
+            .get_field("position")
+            .expect("the field position should exist");
+        let name_rank = schema
+            .get_field(NAME_RANK_FIELD)
+            .expect("the field name_rank should exist");
+
+        index_writer
+            .delete_all_documents()
+            .expect("failed to delete all documents");
+        let total = entries.len();
+        for (i, (entry_key, entry)) in entries.iter().sorted_by_key(|(k, _)| *k).enumerate() {
+            if i % 1000 == 0 {
+                self.progress.report(Progress::Indexing {
+                    corpus: "changelog".into(),
+                    indexed: i,
+                    total,
+                });
+            }
+
+            let mut document = Document::default();
+            document.add_text(key, entry_key.clone());
+            document.add_text(title, entry.title.clone());
+            document.add_text(text, entry.text.clone());
+            document.add_f64(position, entry.position as f64);
+            document.add_u64(name_rank, i as u64);
+            index_writer
+                .add_document(document)
+                .query("could not add a document to the index")?;
+        }
+
+        index_writer.commit().query("could not commit the index")?;
+        self.map = entries;
+        Ok(())
+    }
+
+    fn collector(&self, n_items: u8, page: u8) -> impl Collector<Fruit = Vec<FCFruit>> {
+        TopDocs::with_limit(n_items.into())
+            .and_offset((page.max(1) - 1) as usize * n_items as usize)
+            .tweak_score(move |segment_reader: &SegmentReader| {
+                let positions = segment_reader
+                    .fast_fields()
+                    .f64("position")
+                    .expect("the field position is a fast field");
+                let name_rank = name_ranks(segment_reader);
+                // newer releases win ties
+                move |doc: DocId, score: Score| {
+                    let position = positions.first(doc).unwrap_or_default();
+                    let rank = name_rank.first(doc).unwrap_or_default();
+                    (score, -position as f32, Reverse(rank))
+                }
+            })
+    }
+}
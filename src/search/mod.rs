@@ -1,18 +1,93 @@
-use anyhow::Context;
+use arc_swap::{ArcSwap, ArcSwapOption};
 use itertools::Itertools;
-use std::collections::HashMap;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
-use tantivy::collector::Collector;
-use tantivy::query::Query;
-use tantivy::schema::{Field, Schema};
-use tantivy::{DocAddress, Index};
-use tracing::{debug, error, info};
+use std::sync::{Arc, LazyLock, Mutex, OnceLock, Weak};
+use std::time::{Duration, SystemTime};
+use tantivy::collector::{Collector, Count};
+use tantivy::columnar::Column;
+use tantivy::query::{BooleanQuery, ConstScoreQuery, Occur, Query, TermQuery};
+use tantivy::schema::{Field, IndexRecordOption, Schema};
+use tantivy::{DocAddress, Index, Score, SegmentReader, Term};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
 
-use crate::nix::{self, NixPackage};
-use crate::{Flake, FlakeRev, LogError, NaiveNixosOption};
+use crate::changelog::ChangelogEntry;
+use crate::changes::{ChannelChanges, ChannelUpdate};
+use crate::config::{EmbeddingsConfig, NixConfig, RankingConfig};
+use crate::embeddings::{self, Embeddings, EMBEDDINGS_CACHE};
+use crate::error::{self, ErrorClass, FcSearchError};
+use crate::history::{ChannelHistory, HistoryEntry};
+use crate::nix::{self, ChannelBuild, NixPackage, NixpkgsInput};
+use crate::progress::ProgressReporter;
+use crate::roles::{extract_roles, Role};
+use crate::state::{write_generations, Generations, UpdateLock};
+use crate::{Flake, FlakeRev, LogError, NaiveNixosOption, OptionSource};
+use options::SOURCE_FIELD;
 
-type FCFruit = ((f32, f32), DocAddress);
+/// score, tie breaker of the corpus and the [`NAME_RANK_FIELD`] of a result
+type FCFruit = ((f32, f32, Reverse<u64>), DocAddress);
 
+/// fast field with the position of the entry among all entry names sorted alphabetically.
+/// results with equal scores are ordered by it, so their order does not depend on the segments
+const NAME_RANK_FIELD: &str = "name_rank";
+
+/// the [`NAME_RANK_FIELD`] column of a segment
+fn name_ranks(segment_reader: &SegmentReader) -> Column<u64> {
+    segment_reader
+        .fast_fields()
+        .u64(NAME_RANK_FIELD)
+        .expect("the field name_rank is a fast field")
+}
+
+/// marker file written once a channel has been completely rebuilt.
+/// servers that do not index themselves watch it to pick up new index generations
+pub const GENERATION_FILE: &str = "generation";
+
+/// directory in the state dir holding the indices of every indexed revision.
+/// channels at the same revision share one entry instead of storing their own copy
+const STORE_DIR: &str = "revs";
+
+/// marker file written once the indices of a revision in the store are complete
+pub const COMPLETE_FILE: &str = "complete";
+
+/// revisions without a complete marker are only removed once they were not touched for this long,
+/// younger ones are still being indexed by this or another process
+const INCOMPLETE_GRACE_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// files and indices of a channel that were kept in its own directory before
+/// the indices moved into the store
+const LEGACY_ENTRIES: &[&str] = &[
+    "options.json",
+    "packages.json",
+    "home-manager-options.json",
+    "changelog.json",
+    "warnings.json",
+    "tantivy",
+    "tantivy_packages",
+    HOME_MANAGER_INDEX,
+    CHANGELOG_INDEX,
+];
+
+/// score multipliers of the options ranking, replaced when the config is reloaded
+static RANKING: LazyLock<ArcSwap<RankingConfig>> =
+    LazyLock::new(|| ArcSwap::from_pointee(RankingConfig::default()));
+
+/// use the given ranking weights for all following searches
+pub fn set_ranking(ranking: RankingConfig) {
+    RANKING.store(Arc::new(ranking));
+}
+
+/// indices that are currently open, so channels at the same revision also share them in memory
+static OPEN_INDICES: LazyLock<Mutex<HashMap<PathBuf, Weak<ChannelSearcherInner>>>> =
+    LazyLock::new(Default::default);
+
+pub mod changelog;
 pub mod options;
 pub mod packages;
 
@@ -28,76 +103,256 @@ pub struct SearcherInner {
 struct ChannelSearcherInner {
     options: GenericSearcher<NaiveNixosOption>,
     packages: GenericSearcher<NixPackage>,
+    home_manager: Option<GenericSearcher<NaiveNixosOption>>,
+    changelog: GenericSearcher<ChangelogEntry>,
+    /// fc-nixos roles found in the options
+    roles: BTreeMap<String, Role>,
+    /// computed after the indices, see [`ChannelSearcher::update_embeddings`]
+    embeddings: Arc<ArcSwapOption<Embeddings>>,
 }
 
+/// index of the optional home-manager options corpus
+const HOME_MANAGER_INDEX: &str = "tantivy_home_manager";
+
+const CHANGELOG_INDEX: &str = "tantivy_changelog";
+
 impl ChannelSearcherInner {
+    fn read_cache(
+        branch_path: &Path,
+    ) -> Option<(
+        HashMap<String, NaiveNixosOption>,
+        HashMap<String, NixPackage>,
+    )> {
+        let options = read_cache_file(branch_path, "options")?;
+        let packages = read_cache_file(branch_path, "packages")?;
+
+        Some((options, packages))
+    }
+
+    /// the cached home-manager options, if they were evaluated for this channel
+    fn read_home_manager_cache(branch_path: &Path) -> Option<HashMap<String, NaiveNixosOption>> {
+        read_cache_file(branch_path, "home-manager-options")
+    }
+
+    /// the cached changelog, caches written before changelogs were collected have none
+    fn read_changelog_cache(branch_path: &Path) -> HashMap<String, ChangelogEntry> {
+        std::fs::read_to_string(branch_path.join("changelog.json"))
+            .ok()
+            .and_then(|c| serde_json::from_str(&c).log_to_option("failed to deserialize changelog"))
+            .unwrap_or_default()
+    }
+
+    /// the cached embeddings of the options, if they were computed for this channel
+    fn read_embeddings_cache(branch_path: &Path) -> Arc<ArcSwapOption<Embeddings>> {
+        Arc::new(ArcSwapOption::from(
+            read_cache_file::<Embeddings>(branch_path, EMBEDDINGS_CACHE).map(Arc::new),
+        ))
+    }
+
+    /// open the complete indices in `data_path`, reusing them if another channel already has them open
+    fn open_shared(data_path: &Path) -> Option<Arc<Self>> {
+        let mut open_indices = OPEN_INDICES.lock().unwrap();
+        if let Some(inner) = open_indices.get(data_path).and_then(Weak::upgrade) {
+            debug!("sharing the open index at {}", data_path.display());
+            return Some(inner);
+        }
+
+        let inner = Arc::new(Self::open(data_path)?);
+        open_indices.insert(data_path.to_path_buf(), Arc::downgrade(&inner));
+        Some(inner)
+    }
+
+    /// make freshly built indices available to other channels at the same revision
+    fn share(data_path: &Path, inner: Self) -> Arc<Self> {
+        let inner = Arc::new(inner);
+        let mut open_indices = OPEN_INDICES.lock().unwrap();
+        open_indices.retain(|_, open| open.strong_count() > 0);
+        open_indices.insert(data_path.to_path_buf(), Arc::downgrade(&inner));
+        inner
+    }
+
     /// attempt to load cached options
-    pub fn maybe_load(branch_path: &Path) -> Option<Self> {
-        let options = serde_json::from_str(
-            &std::fs::read_to_string(branch_path.join("options.json"))
-                .log_to_option("could not load options from disk")?,
+    pub fn maybe_load(branch_path: &Path, progress: &ProgressReporter) -> Option<Self> {
+        let (options, packages) = Self::read_cache(branch_path)?;
+        let home_manager = Self::read_home_manager_cache(branch_path);
+        let changelog = Self::read_changelog_cache(branch_path);
+        Self::new_with_values(
+            branch_path,
+            options,
+            packages,
+            home_manager,
+            changelog,
+            progress,
         )
-        .log_to_option("failed to deserialize options")?;
+    }
 
-        let packages = serde_json::from_str(
-            &std::fs::read_to_string(branch_path.join("packages.json"))
-                .log_to_option("could not load package from cache")?,
-        )
-        .log_to_option("failed to deserialize packages json")?;
+    /// open the cached options and indices of a channel that was built by another process
+    /// without rewriting the indices
+    pub fn open(branch_path: &Path) -> Option<Self> {
+        let (options, packages) = Self::read_cache(branch_path)?;
+        let roles = extract_roles(&options);
 
-        Self::new_with_values(branch_path, options, packages)
+        let o_inner = GenericSearcher::<NaiveNixosOption>::open_with_values(
+            &branch_path.join("tantivy"),
+            options,
+        )
+        .log_to_option("opening options searcher")?;
+        let p_inner = GenericSearcher::<NixPackage>::open_with_values(
+            &branch_path.join("tantivy_packages"),
+            packages,
+        )
+        .log_to_option("opening packages searcher")?;
+        let hm_inner = Self::read_home_manager_cache(branch_path).and_then(|home_manager| {
+            GenericSearcher::<NaiveNixosOption>::open_with_values(
+                &branch_path.join(HOME_MANAGER_INDEX),
+                home_manager,
+            )
+            .log_to_option("opening home-manager searcher")
+        });
+        let changelog_index_path = branch_path.join(CHANGELOG_INDEX);
+        std::fs::create_dir_all(&changelog_index_path)
+            .log_to_option("creating changelog index path")?;
+        let c_inner = GenericSearcher::<ChangelogEntry>::open_with_values(
+            &changelog_index_path,
+            Self::read_changelog_cache(branch_path),
+        )
+        .log_to_option("opening changelog searcher")?;
+        Some(Self {
+            options: o_inner,
+            packages: p_inner,
+            home_manager: hm_inner,
+            changelog: c_inner,
+            roles,
+            embeddings: Self::read_embeddings_cache(branch_path),
+        })
     }
 
     pub fn new_with_values(
         branch_path: &Path,
         options: HashMap<String, NaiveNixosOption>,
         packages: HashMap<String, NixPackage>,
+        home_manager: Option<HashMap<String, NaiveNixosOption>>,
+        changelog: HashMap<String, ChangelogEntry>,
+        progress: &ProgressReporter,
     ) -> Option<Self> {
+        // loading a cache at startup is not part of an update that resets the progress
+        let _idle = progress.idle_on_drop();
         let options_index_path = branch_path.join("tantivy");
         let package_index_path = branch_path.join("tantivy_packages");
+        let roles = extract_roles(&options);
+
+        let mut o_inner = GenericSearcher::<NaiveNixosOption>::new(&options_index_path);
+        o_inner.progress = progress.clone();
+        o_inner
+            .load(options)
+            .log_to_option("creating new options searcher")?;
 
-        let o_inner =
-            GenericSearcher::<NaiveNixosOption>::new_with_values(&options_index_path, options)
-                .log_to_option("creating new options searcher")?;
-        let p_inner = GenericSearcher::<NixPackage>::new_with_values(&package_index_path, packages)
+        let mut p_inner = GenericSearcher::<NixPackage>::new(&package_index_path);
+        p_inner.progress = progress.clone();
+        p_inner
+            .load(packages)
             .log_to_option("creating new packages searcher")?;
+
+        let hm_inner = home_manager.and_then(|home_manager| {
+            Self::new_home_manager(branch_path, home_manager, progress)
+                .log_to_option("creating new home-manager searcher")
+        });
+
+        let changelog_index_path = branch_path.join(CHANGELOG_INDEX);
+        std::fs::create_dir_all(&changelog_index_path)
+            .log_to_option("creating changelog index path")?;
+        let mut c_inner = GenericSearcher::<ChangelogEntry>::new(&changelog_index_path);
+        c_inner.progress = progress.clone();
+        c_inner
+            .load(changelog)
+            .log_to_option("creating new changelog searcher")?;
+
         Some(Self {
             options: o_inner,
             packages: p_inner,
+            home_manager: hm_inner,
+            changelog: c_inner,
+            roles,
+            embeddings: Self::read_embeddings_cache(branch_path),
         })
     }
+
+    fn new_home_manager(
+        branch_path: &Path,
+        home_manager: HashMap<String, NaiveNixosOption>,
+        progress: &ProgressReporter,
+    ) -> error::Result<GenericSearcher<NaiveNixosOption>> {
+        let index_path = branch_path.join(HOME_MANAGER_INDEX);
+        std::fs::create_dir_all(&index_path).cache("failed to create home-manager index path")?;
+
+        let mut hm_inner = GenericSearcher::<NaiveNixosOption>::new(&index_path);
+        hm_inner.progress = progress.clone();
+        hm_inner.load(home_manager)?;
+        Ok(hm_inner)
+    }
 }
 
 #[derive(Clone)]
 pub struct ChannelSearcher {
-    inner: Option<ChannelSearcherInner>,
+    inner: Option<Arc<ChannelSearcherInner>>,
 
     // members required for updating the options at runtime
+    state_dir: PathBuf,
     branch_path: PathBuf,
     pub flake: Flake,
+
+    pub progress: ProgressReporter,
+
+    /// warnings printed while evaluating the indexed revision
+    pub warnings: Vec<String>,
+
+    /// the nixpkgs the indexed revision was evaluated with, unknown for caches of older versions
+    pub nixpkgs: Option<NixpkgsInput>,
+
+    /// history of the options, read when it is first needed
+    history: Arc<OnceLock<ChannelHistory>>,
 }
 
 impl ChannelSearcher {
-    #[tracing::instrument(skip(state_dir, flake), fields(branch = flake.branch))]
+    #[tracing::instrument(skip(state_dir, flake), fields(branch = flake.channel()))]
     pub fn in_statedir(state_dir: &Path, flake: &Flake) -> Self {
         let mut flake = flake.clone();
-        let branchname = flake.branch.clone();
+        let branchname = flake.channel().to_string();
         let branch_path = state_dir.join(branchname.clone());
 
         debug!("starting searcher for branch {}", &branchname);
 
-        let flake_info_path = branch_path.join("flake_info.json");
-        if matches!(flake.rev, FlakeRev::FallbackToCached) && flake_info_path.exists() {
-            if let Ok(saved_flake) = serde_json::from_str::<Flake>(
-                &std::fs::read_to_string(flake_info_path)
-                    .expect("flake_info.json exists but could not be read"),
-            ) {
-                info!("loaded flake from file cache: {:#?}", saved_flake);
-                flake = saved_flake;
-            };
+        let progress = ProgressReporter::default();
+        let saved_flake = read_flake_info(&branch_path);
+        let data_path = saved_flake
+            .as_ref()
+            .map(|saved| store_path(state_dir, saved));
+        if let Some(ref data_path) = data_path {
+            migrate_legacy_cache(&branch_path, data_path);
         }
 
-        let inner = ChannelSearcherInner::maybe_load(&branch_path);
+        // a complete marker means the indices were completely written for the saved revision,
+        // so they can be reused as they are
+        let inner = saved_flake
+            .zip(data_path.as_ref())
+            .filter(|(_, data_path)| data_path.join(COMPLETE_FILE).exists())
+            .and_then(|(saved_flake, data_path)| {
+                let inner = ChannelSearcherInner::open_shared(data_path).or_else(|| {
+                    // the evaluated data is complete, e.g. after restoring a snapshot without indices
+                    info!("rebuilding the indices from the cached data");
+                    let inner = ChannelSearcherInner::maybe_load(data_path, &progress)?;
+                    Some(ChannelSearcherInner::share(data_path, inner))
+                })?;
+                info!("reusing the index of rev {}", saved_flake.rev);
+                flake.rev = saved_flake.rev;
+                Some(inner)
+            })
+            .or_else(|| {
+                let inner = ChannelSearcherInner::maybe_load(data_path.as_ref()?, &progress)?;
+                // the revision of a partially written cache is unknown, the next update rebuilds it
+                flake.rev = FlakeRev::FallbackToCached;
+                Some(Arc::new(inner))
+            });
         if inner.is_some() {
             debug!("loaded the channel from cache");
         } else {
@@ -107,82 +362,437 @@ impl ChannelSearcher {
         Self {
             inner,
             flake,
+            state_dir: state_dir.to_path_buf(),
             branch_path: branch_path.to_path_buf(),
+            progress,
+            warnings: data_path
+                .as_ref()
+                .map(|p| read_warnings(p))
+                .unwrap_or_default(),
+            nixpkgs: data_path.as_ref().and_then(|p| read_nixpkgs(p)),
+            history: Arc::default(),
         }
     }
 
+    /// load a channel that is indexed by another process, see [`GENERATION_FILE`]
+    #[tracing::instrument(skip(state_dir))]
+    pub fn open_from_statedir(state_dir: &Path, branch: &str) -> error::Result<Self> {
+        let branch_path = state_dir.join(branch);
+        let flake: Flake = serde_json::from_str(
+            &std::fs::read_to_string(branch_path.join("flake_info.json"))
+                .cache("could not read flake info")?,
+        )
+        .cache("could not deserialize flake info")?;
+
+        let data_path = store_path(state_dir, &flake);
+        let inner = ChannelSearcherInner::open_shared(&data_path);
+        if inner.is_none() {
+            return Err(FcSearchError::cache("could not open the cached channel"));
+        }
+
+        Ok(Self {
+            inner,
+            flake,
+            state_dir: state_dir.to_path_buf(),
+            branch_path,
+            progress: ProgressReporter::default(),
+            warnings: read_warnings(&data_path),
+            nixpkgs: read_nixpkgs(&data_path),
+            history: Arc::default(),
+        })
+    }
+
+    pub fn branch_path(&self) -> &Path {
+        &self.branch_path
+    }
+
     pub fn active(&self) -> bool {
         self.inner.is_some()
     }
 
-    pub fn search_options(&self, q: &str, n_items: u8, page: u8) -> Vec<NaiveNixosOption> {
+    /// identifies the indexed data, it changes whenever the channel is updated to another revision
+    pub fn generation(&self) -> String {
+        self.flake.rev.to_string()
+    }
+
+    /// all options of the channel, if it is indexed
+    pub fn options(&self) -> Option<&HashMap<String, NaiveNixosOption>> {
+        self.inner.as_ref().map(|i| &i.options.map)
+    }
+
+    /// the options below an option of a submodule type
+    pub fn sub_options(&self, name: &str) -> Vec<NaiveNixosOption> {
+        self.inner
+            .as_ref()
+            .map(|i| i.options.sub_options(name))
+            .unwrap_or_default()
+    }
+
+    pub fn home_manager_sub_options(&self, name: &str) -> Vec<NaiveNixosOption> {
+        self.inner
+            .as_ref()
+            .and_then(|i| i.home_manager.as_ref())
+            .map(|hm| hm.sub_options(name))
+            .unwrap_or_default()
+    }
+
+    /// all home-manager options of the channel, if they were evaluated
+    pub fn home_manager_options(&self) -> Option<&HashMap<String, NaiveNixosOption>> {
+        self.inner
+            .as_ref()
+            .and_then(|i| i.home_manager.as_ref())
+            .map(|hm| &hm.map)
+    }
+
+    /// all packages of the channel, if it is indexed
+    pub fn packages(&self) -> Option<&HashMap<String, NixPackage>> {
+        self.inner.as_ref().map(|i| &i.packages.map)
+    }
+
+    /// whether both channels are served from the same index
+    pub fn shares_index_with(&self, other: &ChannelSearcher) -> bool {
+        match (&self.inner, &other.inner) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// options matching the query, only the ones of `source` if it is given.
+    /// the `similar` options are added to the results, see [`Self::similar_options`]
+    pub fn search_options(
+        &self,
+        q: &str,
+        source: Option<OptionSource>,
+        similar: &[(String, Score)],
+        n_items: u8,
+        page: u8,
+    ) -> Vec<NaiveNixosOption> {
+        let filter = source.map(|s| (SOURCE_FIELD, s.as_str()));
         self.inner
             .as_ref()
-            .map(|i| i.options.search_entries(q, n_items, page))
+            .map(|i| i.options.search_entries(q, filter, similar, n_items, page))
             .unwrap_or_default()
     }
 
     pub fn search_packages(&self, q: &str, n_items: u8, page: u8) -> Vec<NixPackage> {
         self.inner
             .as_ref()
-            .map(|i| i.packages.search_entries(q, n_items, page))
+            .map(|i| i.packages.search_entries(q, None, &[], n_items, page))
+            .unwrap_or_default()
+    }
+
+    pub fn search_changelog(&self, q: &str, n_items: u8, page: u8) -> Vec<ChangelogEntry> {
+        self.inner
+            .as_ref()
+            .map(|i| i.changelog.search_entries(q, None, &[], n_items, page))
+            .unwrap_or_default()
+    }
+
+    pub fn count_options(
+        &self,
+        q: &str,
+        source: Option<OptionSource>,
+        similar: &[(String, Score)],
+    ) -> usize {
+        let filter = source.map(|s| (SOURCE_FIELD, s.as_str()));
+        self.inner
+            .as_ref()
+            .map(|i| i.options.count_entries(q, filter, similar))
+            .unwrap_or_default()
+    }
+
+    /// the embeddings of the options, if they were computed
+    pub fn embeddings(&self) -> Option<Arc<Embeddings>> {
+        self.inner.as_ref()?.embeddings.load_full()
+    }
+
+    /// the options nearest in meaning to the query vector with their score,
+    /// empty if the embeddings are missing or were computed by another model
+    pub fn similar_options(
+        &self,
+        query: &[f32],
+        config: &EmbeddingsConfig,
+    ) -> Vec<(String, Score)> {
+        match self.embeddings() {
+            Some(embeddings) if embeddings.model == config.model => {
+                embeddings.nearest(query, config)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn count_packages(&self, q: &str) -> usize {
+        self.inner
+            .as_ref()
+            .map(|i| i.packages.count_entries(q, None, &[]))
+            .unwrap_or_default()
+    }
+
+    /// number of matching changelog entries, all of them for an empty query
+    pub fn count_changelog(&self, q: &str) -> usize {
+        self.inner
+            .as_ref()
+            .map(|i| match q {
+                "" => i.changelog.map.len(),
+                q => i.changelog.count_entries(q, None, &[]),
+            })
+            .unwrap_or_default()
+    }
+
+    /// changelog entries in the order of the changelog, newest first
+    pub fn browse_changelog(&self, n_items: u8, page: u8) -> Vec<ChangelogEntry> {
+        self.inner
+            .as_ref()
+            .map(|i| {
+                i.changelog
+                    .map
+                    .values()
+                    .sorted_by_key(|entry| entry.position)
+                    .skip((page.max(1) - 1) as usize * n_items as usize)
+                    .take(n_items.into())
+                    .cloned()
+                    .collect_vec()
+            })
+            .unwrap_or_default()
+    }
+
+    /// when the served revision was indexed, the time the flake info was last written
+    pub fn indexed_at(&self) -> Option<SystemTime> {
+        std::fs::metadata(self.branch_path.join("flake_info.json"))
+            .and_then(|m| m.modified())
+            .ok()
+    }
+
+    /// the changes of the last update of this channel
+    pub fn last_update(&self) -> Option<ChannelUpdate> {
+        ChannelUpdate::read(&self.branch_path)
+    }
+
+    /// what happened to an option over all revisions the channel was indexed at, oldest first
+    pub fn option_history(&self, name: &str) -> Vec<HistoryEntry> {
+        self.history
+            .get_or_init(|| ChannelHistory::read(&self.branch_path))
+            .of(name)
+            .to_vec()
+    }
+
+    /// the fc-nixos roles of this channel, keyed by their name
+    pub fn roles(&self) -> Option<&BTreeMap<String, Role>> {
+        self.inner.as_ref().map(|i| &i.roles)
+    }
+
+    /// whether home-manager options were evaluated for this channel
+    pub fn has_home_manager(&self) -> bool {
+        self.inner
+            .as_ref()
+            .is_some_and(|i| i.home_manager.is_some())
+    }
+
+    pub fn search_home_manager(&self, q: &str, n_items: u8, page: u8) -> Vec<NaiveNixosOption> {
+        self.inner
+            .as_ref()
+            .and_then(|i| i.home_manager.as_ref())
+            .map(|hm| hm.search_entries(q, None, &[], n_items, page))
+            .unwrap_or_default()
+    }
+
+    pub fn count_home_manager(&self, q: &str) -> usize {
+        self.inner
+            .as_ref()
+            .and_then(|i| i.home_manager.as_ref())
+            .map(|hm| hm.count_entries(q, None, &[]))
             .unwrap_or_default()
     }
 
-    #[tracing::instrument(skip(self), fields(branch = self.flake.branch))]
-    pub async fn update(&mut self) -> anyhow::Result<()> {
-        //anyhow::bail!("test error for logging");
+    /// the newest revision of the branch of the channel
+    pub async fn latest_rev(&self) -> error::Result<FlakeRev> {
+        Flake::get_latest_rev(&self.flake.owner, &self.flake.name, &self.flake.branch)
+            .await
+            .network("error getting the newest commit")
+    }
+
+    /// rebuild the channel if its branch has a newer revision than the indexed one
+    #[tracing::instrument(skip(self, cancel), fields(branch = self.flake.channel()))]
+    pub async fn update(
+        &mut self,
+        nix_config: &NixConfig,
+        cancel: &CancellationToken,
+    ) -> error::Result<()> {
+        let latest_rev = self.latest_rev().await?;
+        self.update_to(latest_rev, nix_config, cancel)
+    }
+
+    /// rebuild the channel at the given revision unless it is already indexed at it.
+    /// blocks until the channel is evaluated and indexed, cancelling `cancel` stops the evaluation
+    #[tracing::instrument(skip(self, cancel), fields(branch = self.flake.channel()))]
+    pub fn update_to(
+        &mut self,
+        new_flake_rev: FlakeRev,
+        nix_config: &NixConfig,
+        cancel: &CancellationToken,
+    ) -> error::Result<()> {
+        let progress = self.progress.clone();
+        let _idle = progress.idle_on_drop();
         let active = self.active();
-        let latest_rev =
-            Flake::get_latest_rev(&self.flake.owner, &self.flake.name, &self.flake.branch).await;
-        match latest_rev {
-            Ok(new_flake_rev) if !active || new_flake_rev != self.flake.rev => {
-                if active {
-                    info!("current rev is {:?}", self.flake.rev);
-                    info!("found newer revision: {:?}", new_flake_rev);
-                } else {
-                    info!("generating options for rev {:?}", new_flake_rev);
+        if active && new_flake_rev == self.flake.rev {
+            info!("already up-to-date");
+            return Ok(());
+        }
+
+        if active {
+            info!("current rev is {:?}", self.flake.rev);
+            info!("found newer revision: {:?}", new_flake_rev);
+        } else {
+            info!("generating options for rev {:?}", new_flake_rev);
+        }
+
+        self.rebuild(new_flake_rev, nix_config, cancel)
+    }
+
+    /// compute the embeddings of the options unless they were computed with the configured model.
+    /// they are stored next to the indices, so channels at the same revision share them
+    #[tracing::instrument(skip_all, fields(branch = self.flake.channel()))]
+    pub async fn update_embeddings(&self, config: &EmbeddingsConfig) -> error::Result<()> {
+        let Some(ref inner) = self.inner else {
+            return Ok(());
+        };
+        if config.endpoint.is_none()
+            || self
+                .embeddings()
+                .is_some_and(|embeddings| embeddings.model == config.model)
+        {
+            return Ok(());
+        }
+
+        info!(
+            "computing the embeddings of {} options",
+            inner.options.map.len()
+        );
+        let embeddings = {
+            let _idle = self.progress.idle_on_drop();
+            embeddings::embed_options(config, &inner.options.map, &self.progress).await?
+        };
+
+        let data_path = store_path(&self.state_dir, &self.flake);
+        write_cache_file(&data_path, EMBEDDINGS_CACHE, &embeddings)?;
+        inner.embeddings.store(Some(Arc::new(embeddings)));
+        // servers following the state dir reopen the channel to load them
+        write_timestamp(&self.branch_path.join(GENERATION_FILE))
+            .cache("could not write the generation marker")?;
+        write_generations(&self.state_dir);
+        Ok(())
+    }
+
+    fn rebuild(
+        &mut self,
+        new_flake_rev: FlakeRev,
+        nix_config: &NixConfig,
+        cancel: &CancellationToken,
+    ) -> error::Result<()> {
+        // keep snapshots from archiving a half written channel
+        let _lock =
+            UpdateLock::acquire(&self.state_dir).cache("could not acquire the update lock")?;
+
+        // the channel is incomplete until the generation marker is written again
+        let generation_path = self.branch_path.join(GENERATION_FILE);
+        if generation_path.exists() {
+            std::fs::remove_file(&generation_path)
+                .cache("could not remove the generation marker")?;
+        }
+
+        let mut new_flake = self.flake.clone();
+        new_flake.rev = new_flake_rev;
+        let data_path = store_path(&self.state_dir, &new_flake);
+
+        // another channel may already have indexed this revision
+        let shared = data_path
+            .join(COMPLETE_FILE)
+            .exists()
+            .then(|| ChannelSearcherInner::open_shared(&data_path))
+            .flatten();
+        let (inner, warnings) = match shared {
+            Some(inner) => {
+                info!("sharing the existing index of rev {}", new_flake.rev);
+                (inner, read_warnings(&data_path))
+            }
+            None => {
+                let complete_path = data_path.join(COMPLETE_FILE);
+                if complete_path.exists() {
+                    std::fs::remove_file(&complete_path)
+                        .cache("could not remove the complete marker")?;
                 }
 
-                let mut new_flake = self.flake.clone();
-                new_flake.rev = new_flake_rev;
-                match update_file_cache(&self.branch_path, &new_flake) {
-                    Ok((options, packages)) => {
-                        info!("successfully updated file cache");
-
-                        if !active {
-                            let inner = ChannelSearcherInner::new_with_values(
-                                &self.branch_path,
-                                options,
-                                packages,
-                            );
-
-                            self.flake = new_flake;
-                            self.inner = inner;
-                        } else {
-                            // this is guaranteed to be true after the `active` check from above
-                            // but the type system insists on unpacking it
-                            // since this is not a critical path, unsafe unwrapping is not
-                            // warranted
-                            if let Some(ref mut i) = &mut self.inner {
-                                i.options
-                                    .update_entries(options)
-                                    .context("could not update options")?;
-                                i.packages
-                                    .update_entries(packages)
-                                    .context("could not update packages")?;
-                            } else {
-                                unreachable!("channel searcher is active but inner is not some");
-                            }
-                        }
-                    }
-                    Err(e) => error!("error updating branch: {}", e),
-                };
+                let ChannelBuild {
+                    options,
+                    packages,
+                    home_manager_options,
+                    changelog,
+                    warnings,
+                    ..
+                } = update_file_cache(&data_path, &new_flake, nix_config, &self.progress, cancel)?;
+                info!("successfully updated file cache");
+
+                let inner = ChannelSearcherInner::new_with_values(
+                    &data_path,
+                    options,
+                    packages,
+                    home_manager_options,
+                    changelog,
+                    &self.progress,
+                )
+                .query("could not build the indices")?;
+                write_timestamp(&complete_path).cache("could not write the complete marker")?;
+                (ChannelSearcherInner::share(&data_path, inner), warnings)
             }
-            Ok(_) => info!("already up-to-date"),
-            Err(e) => error!("error getting the newest commit: {}", e),
         };
 
+        write_flake_info(&self.branch_path, &new_flake)?;
+        let updated_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        // a freshly indexed channel has nothing to compare against
+        if let Some(ref previous) = self.inner {
+            let update = ChannelUpdate {
+                from: self.flake.rev.clone(),
+                to: new_flake.rev.clone(),
+                updated_at,
+                changes: ChannelChanges::between(
+                    &previous.options.map,
+                    &previous.packages.map,
+                    &inner.options.map,
+                    &inner.packages.map,
+                ),
+            };
+            update
+                .write(&self.branch_path)
+                .cache("could not save the changes of the update")?;
+        }
+
+        let mut history = self
+            .history
+            .get()
+            .cloned()
+            .unwrap_or_else(|| ChannelHistory::read(&self.branch_path));
+        history.record(
+            self.inner.as_ref().map(|previous| &previous.options.map),
+            &inner.options.map,
+            &new_flake.rev,
+            updated_at,
+        );
+        history
+            .write(&self.branch_path)
+            .cache("could not save the option history")?;
+        self.history = Arc::new(OnceLock::from(history));
+
+        self.inner = Some(inner);
+        self.flake = new_flake;
+        self.warnings = warnings;
+        self.nixpkgs = read_nixpkgs(&data_path);
+
+        write_timestamp(&generation_path).cache("could not write the generation marker")?;
+        write_generations(&self.state_dir);
+        collect_garbage(&self.state_dir);
         Ok(())
     }
 }
@@ -191,6 +801,7 @@ impl ChannelSearcher {
 pub struct GenericSearcher<Item> {
     pub index_path: PathBuf,
     pub map: HashMap<String, Item>,
+    pub progress: ProgressReporter,
     inner: Option<SearcherInner>,
 }
 
@@ -199,24 +810,50 @@ impl<Item> GenericSearcher<Item> {
         Self {
             index_path: index_path.to_path_buf(),
             map: HashMap::new(),
+            progress: ProgressReporter::default(),
             inner: None,
         }
     }
 
-    pub fn new_with_values(
+    pub fn new_with_values(index_path: &Path, entries: HashMap<String, Item>) -> error::Result<Self>
+    where
+        Self: Searcher<Item = Item>,
+    {
+        let mut ret = Self::new(index_path);
+        ret.create_index()?;
+        ret.update_entries(entries)?;
+        Ok(ret)
+    }
+
+    /// open an index that was already built from `entries` without rewriting it
+    pub fn open_with_values(
         index_path: &Path,
         entries: HashMap<String, Item>,
-    ) -> anyhow::Result<Self>
+    ) -> error::Result<Self>
     where
         Self: Searcher<Item = Item>,
     {
         let mut ret = Self::new(index_path);
         ret.create_index()?;
-        ret.update_entries(entries)?;
+
+        // the index is recreated empty if its schema changed
+        let indexed = ret
+            .inner
+            .as_ref()
+            .map(|i| i.reader.searcher().num_docs())
+            .unwrap_or_default();
+        if indexed as usize != entries.len() {
+            return Err(FcSearchError::cache(format!(
+                "the index at {} is out of date",
+                index_path.display()
+            )));
+        }
+
+        ret.map = entries;
         Ok(ret)
     }
 
-    pub fn load(&mut self, entries: HashMap<String, Item>) -> anyhow::Result<()>
+    pub fn load(&mut self, entries: HashMap<String, Item>) -> error::Result<()>
     where
         Self: Searcher<Item = Item>,
     {
@@ -225,7 +862,78 @@ impl<Item> GenericSearcher<Item> {
         Ok(())
     }
 
-    pub fn search_entries(&self, query: &str, n_items: u8, page: u8) -> Vec<Item>
+    /// the parsed query, limited to the entries with the given value in a field.
+    /// the `boosted` entries match with their score as well, found by their indexed reference field
+    fn filtered_query(
+        &self,
+        inner: &SearcherInner,
+        query: &str,
+        filter: Filter,
+        boosted: &[(String, Score)],
+    ) -> Box<dyn Query>
+    where
+        Self: Searcher,
+    {
+        let mut query = self.parse_query(query);
+        if !boosted.is_empty() {
+            let mut subqueries: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Should, query)];
+            subqueries.extend(boosted.iter().map(|(name, score)| {
+                let query: Box<dyn Query> = Box::new(ConstScoreQuery::new(
+                    Box::new(TermQuery::new(
+                        Term::from_field_text(inner.reference_field, name),
+                        IndexRecordOption::Basic,
+                    )),
+                    *score,
+                ));
+                (Occur::Should, query)
+            }));
+            query = Box::new(BooleanQuery::new(subqueries));
+        }
+        let Some((field, value)) = filter else {
+            return query;
+        };
+        let Ok(field) = inner.schema.get_field(field) else {
+            error!("can not filter on the unknown field {field}");
+            return query;
+        };
+        Box::new(BooleanQuery::new(vec![
+            (Occur::Must, query),
+            (
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(field, value),
+                    IndexRecordOption::Basic,
+                )),
+            ),
+        ]))
+    }
+
+    /// number of entries matching the query
+    pub fn count_entries(&self, query: &str, filter: Filter, boosted: &[(String, Score)]) -> usize
+    where
+        Self: Searcher,
+    {
+        let Some(ref inner) = self.inner else {
+            error!("searcher not initialized yet, please call create_index first");
+            return 0;
+        };
+
+        let query = self.filtered_query(inner, query, filter, boosted);
+        inner
+            .reader
+            .searcher()
+            .search(&query, &Count)
+            .unwrap_or_default()
+    }
+
+    pub fn search_entries(
+        &self,
+        query: &str,
+        filter: Filter,
+        boosted: &[(String, Score)],
+        n_items: u8,
+        page: u8,
+    ) -> Vec<Item>
     where
         Item: std::fmt::Debug + Clone,
         Self: Searcher,
@@ -236,7 +944,7 @@ impl<Item> GenericSearcher<Item> {
         };
 
         let searcher = inner.reader.searcher();
-        let query = self.parse_query(query);
+        let query = self.filtered_query(inner, query, filter, boosted);
         let results = searcher.search(&query, &self.collector(n_items, page));
 
         results
@@ -268,57 +976,278 @@ impl<Item> GenericSearcher<Item> {
     }
 }
 
+/// a field and the value the results must have in it
+pub type Filter<'a> = Option<(&'a str, &'a str)>;
+
 pub trait Searcher {
     type Item;
 
     // TODO these depend on the underlying generic type...
     // find a better way to implement this
     fn parse_query(&self, query_string: &str) -> Box<dyn Query>;
-    fn create_index(&mut self) -> anyhow::Result<()>;
-    fn update_entries(&mut self, entries: HashMap<String, Self::Item>) -> anyhow::Result<()>;
+    fn create_index(&mut self) -> error::Result<()>;
+    fn update_entries(&mut self, entries: HashMap<String, Self::Item>) -> error::Result<()>;
     fn collector(&self, n_packages: u8, page: u8) -> impl Collector<Fruit = Vec<FCFruit>>;
 }
 
 pub fn update_file_cache(
-    branch_path: &Path,
+    data_path: &Path,
     flake: &Flake,
-) -> anyhow::Result<(
-    HashMap<String, NaiveNixosOption>,
-    HashMap<String, NixPackage>,
-)> {
-    let options_index_path = branch_path.join("tantivy");
-    let pkgs_index_path = branch_path.join("tantivy_packages");
+    nix_config: &NixConfig,
+    progress: &ProgressReporter,
+    cancel: &CancellationToken,
+) -> error::Result<ChannelBuild> {
+    let options_index_path = data_path.join("tantivy");
+    let pkgs_index_path = data_path.join("tantivy_packages");
 
     std::fs::create_dir_all(options_index_path.clone())
-        .context("failed to create options index path")?;
+        .cache("failed to create options index path")?;
     std::fs::create_dir_all(pkgs_index_path.clone())
-        .context("failed to create packages index path")?;
+        .cache("failed to create packages index path")?;
 
-    let (options, packages) = nix::build_options_for_fcio_branch(flake)?;
-    std::fs::write(
-        branch_path.join("options.json"),
-        serde_json::to_string(&options).expect("failed to serialize naive options"),
-    )
-    .expect("failed to save naive options");
-    std::fs::write(
-        branch_path.join("packages.json"),
-        serde_json::to_string(&packages).expect("failed to serialize packages"),
-    )
-    .expect("failed to save packages");
+    let build = nix::build_options_for_fcio_branch(flake, nix_config, progress, cancel)?;
+    write_cache_file(data_path, "options", &build.options)?;
+    write_cache_file(data_path, "packages", &build.packages)?;
+    match build.home_manager_options {
+        Some(ref hm_options) => write_cache_file(data_path, "home-manager-options", hm_options)?,
+        None => remove_cache_file(data_path, "home-manager-options"),
+    }
+    write_json(
+        &data_path.join("changelog.json"),
+        "changelog",
+        &build.changelog,
+    )?;
+    write_json(
+        &data_path.join("warnings.json"),
+        "warnings",
+        &build.warnings,
+    )?;
+    write_json(
+        &data_path.join("nixpkgs.json"),
+        "nixpkgs input",
+        &build.nixpkgs,
+    )?;
+
+    info!("successfully rebuilt options, packages + index");
+    Ok(build)
+}
+
+/// deserialize a cached json file while reading it,
+/// so its text and the parsed data are not held in memory at the same time
+fn read_json<T: DeserializeOwned>(path: &Path, what: &str) -> Option<T> {
+    let file = File::open(path).log_to_option(&format!("could not load {what} from disk"))?;
+    serde_json::from_reader(BufReader::new(file))
+        .log_to_option(&format!("failed to deserialize {what}"))
+}
+
+/// write a json file next to the caches, like the warnings of the evaluation
+fn write_json<T: Serialize>(path: &Path, what: &str, value: &T) -> error::Result<()> {
+    let json = serde_json::to_vec(value).cache(format!("failed to serialize {what}"))?;
+    std::fs::write(path, json).cache(format!("failed to save {what}"))
+}
+
+/// extension of the zstd compressed messagepack caches, they replaced json caches
+const CACHE_EXTENSION: &str = "msgpack.zst";
+
+/// the cache `name` in `data_path`, read from the json written by older versions if needed.
+/// `None` if there is none or it could not be read
+fn read_cache_file<T: DeserializeOwned>(data_path: &Path, name: &str) -> Option<T> {
+    let path = data_path.join(format!("{name}.{CACHE_EXTENSION}"));
+    let json_path = data_path.join(format!("{name}.json"));
+    if !path.exists() {
+        if !json_path.exists() {
+            debug!("no cached {name} in {}", data_path.display());
+            return None;
+        }
+        return read_json(&json_path, name);
+    }
+
+    let file = File::open(&path).log_to_option(&format!("could not load {name} from disk"))?;
+    let decoder =
+        zstd::Decoder::new(file).log_to_option(&format!("could not decompress {name}"))?;
+    rmp_serde::from_read(decoder).log_to_option(&format!("failed to deserialize {name}"))
+}
+
+/// write the cache `name` into `data_path` and remove its json version
+fn write_cache_file<T: Serialize>(data_path: &Path, name: &str, value: &T) -> error::Result<()> {
+    let message = || format!("could not write the {name} cache");
+    let path = data_path.join(format!("{name}.{CACHE_EXTENSION}"));
+    let file = File::create(&path).cache(message())?;
+    let mut encoder = zstd::Encoder::new(BufWriter::new(file), 0).cache(message())?;
+    // field names are kept so fields can be added to the cached types later
+    rmp_serde::encode::write_named(&mut encoder, value).cache(message())?;
+    encoder
+        .finish()
+        .and_then(|mut writer| writer.flush())
+        .cache(message())?;
+
+    let json_path = data_path.join(format!("{name}.json"));
+    if json_path.exists() {
+        std::fs::remove_file(json_path).cache(format!("could not remove the old {name} cache"))?;
+    }
+    Ok(())
+}
+
+/// remove the cache `name` from `data_path` if there is one
+fn remove_cache_file(data_path: &Path, name: &str) {
+    for path in [
+        data_path.join(format!("{name}.{CACHE_EXTENSION}")),
+        data_path.join(format!("{name}.json")),
+    ] {
+        if path.exists() {
+            if let Err(e) = std::fs::remove_file(&path) {
+                warn!("could not remove the stale cache {}: {e}", path.display());
+            }
+        }
+    }
+}
 
-    // cache the current branch + revision
+/// the flake the cached channel data was built from
+pub fn read_flake_info(branch_path: &Path) -> Option<Flake> {
+    serde_json::from_str(&std::fs::read_to_string(branch_path.join("flake_info.json")).ok()?)
+        .log_to_option("could not deserialize flake info")
+}
+
+/// warnings of the last evaluation, missing warnings are not an error
+fn read_warnings(branch_path: &Path) -> Vec<String> {
+    std::fs::read_to_string(branch_path.join("warnings.json"))
+        .ok()
+        .and_then(|w| serde_json::from_str(&w).ok())
+        .unwrap_or_default()
+}
+
+/// the nixpkgs input of the evaluation, missing for caches written by older versions
+fn read_nixpkgs(data_path: &Path) -> Option<NixpkgsInput> {
+    std::fs::read_to_string(data_path.join("nixpkgs.json"))
+        .ok()
+        .and_then(|n| serde_json::from_str(&n).ok())
+}
+
+/// remember the branch + revision a channel is served at
+fn write_flake_info(branch_path: &Path, flake: &Flake) -> error::Result<()> {
+    std::fs::create_dir_all(branch_path).cache("failed to create branch path")?;
     std::fs::write(
         branch_path.join("flake_info.json"),
-        serde_json::to_string(&flake).expect("failed to serialize flake info"),
+        serde_json::to_string(flake).cache("failed to serialize flake info")?,
     )
-    .expect("failed to save flake info");
+    .cache("failed to save flake info")
+}
 
-    info!("successfully rebuilt options, packages + index");
-    Ok((options, packages))
+/// write a marker file containing the current time
+fn write_timestamp(path: &Path) -> std::io::Result<()> {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    std::fs::write(path, timestamp.to_string())
+}
+
+/// directory in the store holding the indices of the revision of `flake`
+pub fn store_path(state_dir: &Path, flake: &Flake) -> PathBuf {
+    let rev = match &flake.rev {
+        FlakeRev::Specific(rev) => rev.clone(),
+        // without a known revision the indices can not be shared with other branches
+        other => format!("{}-{other}", flake.branch),
+    };
+    let kind = format!("{:?}", flake.kind).to_lowercase();
+    state_dir
+        .join(STORE_DIR)
+        .join(format!("{kind}-{}-{}-{rev}", flake.owner, flake.name))
+}
+
+/// move the indices of a channel written before they were kept in the store
+fn migrate_legacy_cache(branch_path: &Path, data_path: &Path) {
+    if !branch_path.join("options.json").exists() {
+        return;
+    }
+
+    if data_path.exists() {
+        // another channel at the same revision was migrated already
+        debug!("removing the legacy cache of {}", branch_path.display());
+        for entry in LEGACY_ENTRIES {
+            let path = branch_path.join(entry);
+            let _ = if path.is_dir() {
+                std::fs::remove_dir_all(path)
+            } else {
+                std::fs::remove_file(path)
+            };
+        }
+        return;
+    }
+
+    info!(
+        "moving the legacy cache of {} into the store",
+        branch_path.display()
+    );
+    if std::fs::create_dir_all(data_path)
+        .log_to_option("could not create the store path")
+        .is_none()
+    {
+        return;
+    }
+    for entry in LEGACY_ENTRIES {
+        let path = branch_path.join(entry);
+        if path.exists() {
+            let _ = std::fs::rename(&path, data_path.join(entry))
+                .log_to_option("could not move the legacy cache");
+        }
+    }
+    if branch_path.join(GENERATION_FILE).exists() {
+        let _ = write_timestamp(&data_path.join(COMPLETE_FILE))
+            .log_to_option("could not write the complete marker");
+    }
+}
+
+/// delete the indices of all revisions no channel in the state dir is served at anymore,
+/// except for revisions that are still being indexed or kept in the [`Generations`]
+pub fn collect_garbage(state_dir: &Path) {
+    let Some(entries) = std::fs::read_dir(state_dir).log_to_option("could not read the state dir")
+    else {
+        return;
+    };
+    let mut referenced: HashSet<PathBuf> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| read_flake_info(&entry.path()))
+        .map(|flake| store_path(state_dir, &flake))
+        .collect();
+    // readers of a shared state dir may still serve the previous revisions of a channel
+    if let Some(generations) = Generations::read(state_dir) {
+        referenced.extend(
+            generations
+                .revisions()
+                .map(|revision| state_dir.join(STORE_DIR).join(revision)),
+        );
+    }
+
+    let Ok(stored) = std::fs::read_dir(state_dir.join(STORE_DIR)) else {
+        return;
+    };
+    for entry in stored.filter_map(Result::ok) {
+        let path = entry.path();
+        if referenced.contains(&path) || is_in_progress(&path) {
+            continue;
+        }
+        info!("removing the unused index at {}", path.display());
+        if let Err(e) = std::fs::remove_dir_all(&path) {
+            error!("could not remove {}: {e}", path.display());
+        }
+    }
+}
+
+/// whether the revision at `path` may still be indexed by a running update
+fn is_in_progress(path: &Path) -> bool {
+    if path.join(COMPLETE_FILE).exists() {
+        return false;
+    }
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_none_or(|age| age < INCOMPLETE_GRACE_PERIOD)
 }
 
 #[tracing::instrument(skip(schema))]
-fn open_or_create_index(index_path: &Path, schema: &Schema) -> anyhow::Result<Index> {
+fn open_or_create_index(index_path: &Path, schema: &Schema) -> error::Result<Index> {
     let index_tmp = Index::open_or_create(
         tantivy::directory::MmapDirectory::open(index_path).unwrap(),
         schema.clone(),
@@ -329,9 +1258,9 @@ fn open_or_create_index(index_path: &Path, schema: &Schema) -> anyhow::Result<In
         Err(tantivy::TantivyError::SchemaError(e)) => {
             error!("schema error: {e}");
             debug!("deleting + recreating the old index");
-            std::fs::remove_dir_all(index_path)?;
-            std::fs::create_dir_all(index_path)?;
-            Ok(Index::create_in_dir(index_path, schema.clone())?)
+            std::fs::remove_dir_all(index_path).cache("could not remove the old index")?;
+            std::fs::create_dir_all(index_path).cache("could not create the index path")?;
+            Index::create_in_dir(index_path, schema.clone()).query("could not create the index")
         }
         Err(e) => unreachable!("unexpected error: {e}"),
     }
@@ -1,16 +1,28 @@
 use itertools::Itertools;
+use std::cmp::Reverse;
 use std::collections::HashMap;
-use tantivy::collector::{Collector, TopDocs};
+use tantivy::collector::{Collector, DocSetCollector, TopDocs};
 use tantivy::query::{
     BooleanQuery, BoostQuery, ConstScoreQuery, FuzzyTermQuery, Occur, PhraseQuery, Query, TermQuery,
 };
-use tantivy::schema::{Facet, FacetOptions, Schema, TextFieldIndexing, TextOptions, TEXT};
+use tantivy::schema::{
+    Facet, FacetOptions, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, FAST, STRING,
+    TEXT,
+};
 use tantivy::tokenizer::{TextAnalyzer, WhitespaceTokenizer};
 use tantivy::{DocId, Document, Score, SegmentReader, Term};
 
-use super::{open_or_create_index, FCFruit, GenericSearcher, Searcher, SearcherInner};
+use super::{
+    name_ranks, open_or_create_index, FCFruit, GenericSearcher, Searcher, SearcherInner,
+    NAME_RANK_FIELD, RANKING,
+};
+use crate::error::{self, ErrorClass, FcSearchError};
+use crate::progress::Progress;
 use crate::NaiveNixosOption;
 
+/// field holding the [`crate::OptionSource`] of an option, for filtering
+pub const SOURCE_FIELD: &str = "source";
+
 impl Searcher for GenericSearcher<NaiveNixosOption> {
     type Item = NaiveNixosOption;
 
@@ -110,12 +122,28 @@ impl Searcher for GenericSearcher<NaiveNixosOption> {
             BoostQuery::new(Box::new(BooleanQuery::new(description_subqueries)), 0.2);
         subqueries.push((Occur::Should, Box::new(description_query)));
 
+        // allowed values of enum options, to find the options accepting a value
+        let choices_field = inner.schema.get_field("choices").unwrap();
+        let choices_subqueries: Vec<(Occur, Box<dyn Query>)> = query_string
+            .split(' ')
+            .filter(|word| !word.is_empty())
+            .map(|word| {
+                let query: Box<dyn Query> = Box::new(TermQuery::new(
+                    Term::from_field_text(choices_field, &word.to_lowercase()),
+                    tantivy::schema::IndexRecordOption::Basic,
+                ));
+                (Occur::Should, query)
+            })
+            .collect();
+        let choices_query = BoostQuery::new(Box::new(BooleanQuery::new(choices_subqueries)), 0.5);
+        subqueries.push((Occur::Should, Box::new(choices_query)));
+
         Box::new(BooleanQuery::new(subqueries))
     }
 
     /// creates the index and initializes the struct that holds
     /// fields that are important for searching
-    fn create_index(&mut self) -> anyhow::Result<()> {
+    fn create_index(&mut self) -> error::Result<()> {
         let mut schema_builder = Schema::builder();
 
         let name_field_options = TextOptions::default().set_indexing_options(
@@ -125,9 +153,17 @@ impl Searcher for GenericSearcher<NaiveNixosOption> {
         );
 
         // name of the option, stored to access it's data from the searcher's hashmap
+        // and indexed as a whole to add the options nearest in meaning to the results
         let attribute_name = schema_builder.add_text_field(
             "attribute_name",
-            TextOptions::default().set_fast(None).set_stored(),
+            TextOptions::default()
+                .set_indexing_options(
+                    TextFieldIndexing::default()
+                        .set_tokenizer("raw")
+                        .set_index_option(IndexRecordOption::Basic),
+                )
+                .set_fast(None)
+                .set_stored(),
         );
 
         // faceted name of the option for access to related fields
@@ -139,6 +175,19 @@ impl Searcher for GenericSearcher<NaiveNixosOption> {
         // description
         schema_builder.add_text_field("description", TEXT);
 
+        // where the option is declared
+        schema_builder.add_text_field(SOURCE_FIELD, STRING);
+
+        // allowed values of enum options
+        schema_builder.add_text_field("choices", TEXT);
+
+        // ranking signals, read by the scorer without loading the stored document
+        schema_builder.add_bool_field("is_fc", FAST);
+        schema_builder.add_bool_field("is_enable", FAST);
+        schema_builder.add_bool_field("is_role", FAST);
+        schema_builder.add_u64_field("name_length", FAST);
+        schema_builder.add_u64_field(NAME_RANK_FIELD, FAST);
+
         let schema = schema_builder.build();
 
         let index = open_or_create_index(&self.index_path, &schema)?;
@@ -164,15 +213,19 @@ impl Searcher for GenericSearcher<NaiveNixosOption> {
     }
 
     /// updates indexed + cached entries with new ones
-    fn update_entries(&mut self, entries: HashMap<String, Self::Item>) -> anyhow::Result<()> {
+    fn update_entries(&mut self, entries: HashMap<String, Self::Item>) -> error::Result<()> {
         let Some(ref inner) = self.inner else {
-            anyhow::bail!("can not update options before index creation");
+            return Err(FcSearchError::query(
+                "can not update options before index creation",
+            ));
         };
 
-        let index = &inner.index;
         let schema = &inner.schema;
 
-        let mut index_writer = index.writer(50_000_000)?;
+        let mut index_writer = inner
+            .index
+            .writer(50_000_000)
+            .query("could not create the index writer")?;
         let name = schema
             .get_field("name")
             .expect("the field name should exist");
@@ -185,51 +238,135 @@ impl Searcher for GenericSearcher<NaiveNixosOption> {
         let description = schema
             .get_field("description")
             .expect("the description field should exist");
+        let source = schema
+            .get_field(SOURCE_FIELD)
+            .expect("the source field should exist");
+        let choices = schema
+            .get_field("choices")
+            .expect("the choices field should exist");
+        let is_fc = schema
+            .get_field("is_fc")
+            .expect("the is_fc field should exist");
+        let is_enable = schema
+            .get_field("is_enable")
+            .expect("the is_enable field should exist");
+        let is_role = schema
+            .get_field("is_role")
+            .expect("the is_role field should exist");
+        let name_length = schema
+            .get_field("name_length")
+            .expect("the name_length field should exist");
+        let name_rank = schema
+            .get_field(NAME_RANK_FIELD)
+            .expect("the name_rank field should exist");
 
         index_writer
             .delete_all_documents()
             .expect("failed to delete all documents");
 
-        for (option_name, option) in &entries {
+        let total = entries.len();
+        for (i, (option_name, option)) in entries.iter().sorted_by_key(|(k, _)| *k).enumerate() {
+            if i % 1000 == 0 {
+                self.progress.report(Progress::Indexing {
+                    corpus: "options".into(),
+                    indexed: i,
+                    total,
+                });
+            }
+
             let mut document = Document::default();
             document.add_text(attribute_name, option_name.clone());
             document.add_text(name, option_name.replace('.', " "));
             document.add_facet(name_facet, Facet::from_path(option_name.clone().split('.')));
-            document.add_text(description, option.description.0.clone());
-            index_writer.add_document(document)?;
+            document.add_text(description, option.description.text());
+            document.add_text(source, option.source().as_str());
+            document.add_text(choices, option.choices().join(" "));
+            document.add_bool(is_fc, option_name.starts_with("flyingcircus"));
+            document.add_bool(is_enable, option_name.ends_with("enable"));
+            document.add_bool(is_role, option_name.contains("roles"));
+            document.add_u64(name_length, option_name.len() as u64);
+            document.add_u64(name_rank, i as u64);
+            index_writer
+                .add_document(document)
+                .query("could not add a document to the index")?;
         }
 
-        index_writer.commit()?;
+        index_writer.commit().query("could not commit the index")?;
         self.map = entries;
         Ok(())
     }
 
     fn collector(&self, n_items: u8, page: u8) -> impl Collector<Fruit = Vec<FCFruit>> {
+        let ranking = RANKING.load_full();
         TopDocs::with_limit(n_items.into())
-            .and_offset((page.max(1) - 1) as usize * page as usize)
+            .and_offset((page.max(1) - 1) as usize * n_items as usize)
             .tweak_score(move |segment_reader: &SegmentReader| {
-                let store_reader = segment_reader.get_store_reader(100).unwrap();
-
+                let fast_fields = segment_reader.fast_fields();
+                let bool_column = |field: &str| {
+                    fast_fields
+                        .bool(field)
+                        .unwrap_or_else(|_| panic!("the field {field} is a fast field"))
+                };
+                let is_fc = bool_column("is_fc");
+                let is_enable = bool_column("is_enable");
+                let is_role = bool_column("is_role");
+                let name_length = fast_fields
+                    .u64("name_length")
+                    .expect("the field name_length is a fast field");
+                let name_rank = name_ranks(segment_reader);
+                let ranking = ranking.clone();
+
+                // shorter names win ties, then the alphabetically first one
                 move |doc: DocId, mut score: Score| {
-                    let d = store_reader.get(doc).unwrap();
-                    let attribute_name = d.field_values().first().unwrap().value.as_text().unwrap();
-
-                    let fcio_option = attribute_name.starts_with("flyingcircus");
-                    let enable_option = attribute_name.ends_with("enable");
-                    let roles_option = attribute_name.contains("roles");
-
-                    if fcio_option {
-                        score *= 1.3;
+                    if is_fc.first(doc).unwrap_or_default() {
+                        score *= ranking.fcio_boost;
                     }
-                    if enable_option {
-                        score *= 1.05;
+                    if is_enable.first(doc).unwrap_or_default() {
+                        score *= ranking.enable_boost;
                     }
-                    if roles_option {
-                        score *= 0.8;
+                    if is_role.first(doc).unwrap_or_default() {
+                        score *= ranking.roles_boost;
                     }
 
-                    (score, 1.0)
+                    let length = name_length.first(doc).unwrap_or_default().max(1);
+                    let rank = name_rank.first(doc).unwrap_or_default();
+                    (score, 1. / length as f32, Reverse(rank))
+                }
+            })
+    }
+}
+
+impl GenericSearcher<NaiveNixosOption> {
+    /// the options below `name` sorted by name, e.g. the options of a submodule
+    pub fn sub_options(&self, name: &str) -> Vec<NaiveNixosOption> {
+        let Some(ref inner) = self.inner else {
+            return Vec::new();
+        };
+        let name_facet = inner
+            .schema
+            .get_field("name_facet")
+            .expect("the field name_facet should exist");
+
+        // facets are indexed with all of their parents
+        let query = TermQuery::new(
+            Term::from_facet(name_facet, &Facet::from_path(name.split('.'))),
+            IndexRecordOption::Basic,
+        );
+        let searcher = inner.reader.searcher();
+        let Ok(docs) = searcher.search(&query, &DocSetCollector) else {
+            return Vec::new();
+        };
+
+        docs.into_iter()
+            .filter_map(|address| {
+                let doc = searcher.doc(address).ok()?;
+                let sub_name = doc.get_first(inner.reference_field)?.as_text()?;
+                if sub_name == name {
+                    return None;
                 }
+                self.map.get(sub_name).cloned()
             })
+            .sorted_by(|a, b| a.name.cmp(&b.name))
+            .collect_vec()
     }
 }
@@ -1,14 +1,62 @@
+use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, LazyLock, Mutex};
 
+use itertools::Itertools;
+use lru::LruCache;
 use tantivy::collector::{Collector, TopDocs};
 use tantivy::query::{
     BooleanQuery, BoostQuery, FuzzyTermQuery, Occur, Query, RegexQuery, TermQuery,
 };
-use tantivy::schema::{Schema, TextFieldIndexing, TextOptions, TEXT};
+use tantivy::schema::{Schema, TextFieldIndexing, TextOptions, FAST, TEXT};
 use tantivy::{DocId, Document, Score, SegmentReader, Term};
+use tantivy_fst::Regex;
 
-use super::{open_or_create_index, FCFruit, GenericSearcher, Searcher, SearcherInner};
+use super::{
+    name_ranks, open_or_create_index, FCFruit, GenericSearcher, Searcher, SearcherInner,
+    NAME_RANK_FIELD,
+};
+use crate::error::{self, ErrorClass, FcSearchError};
 use crate::nix::NixPackage;
+use crate::progress::Progress;
+
+/// longer words are not searched as part of package names, their automaton gets too large
+const MAX_REGEX_WORD_LENGTH: usize = 64;
+
+/// only the first words of a query are searched as part of package names
+const MAX_REGEX_WORDS: usize = 4;
+
+/// compiled automata of recently searched words, they are rebuilt for every keystroke otherwise
+static REGEX_CACHE: LazyLock<Mutex<LruCache<String, Arc<Regex>>>> =
+    LazyLock::new(|| Mutex::new(LruCache::new(NonZeroUsize::new(256).unwrap())));
+
+/// a regex matching the names containing `word` literally, cached across queries
+fn contains_regex(word: &str) -> Option<Arc<Regex>> {
+    if word.is_empty() || word.len() > MAX_REGEX_WORD_LENGTH {
+        return None;
+    }
+
+    let mut cache = REGEX_CACHE.lock().unwrap();
+    if let Some(regex) = cache.get(word) {
+        return Some(regex.clone());
+    }
+    let regex = Arc::new(Regex::new(&format!(".*{}.*", escape_regex(word))).ok()?);
+    cache.put(word.to_string(), regex.clone());
+    Some(regex)
+}
+
+/// escape the characters that have a meaning in a regex
+fn escape_regex(word: &str) -> String {
+    let mut escaped = String::with_capacity(word.len());
+    for c in word.chars() {
+        if "\\.+*?()|[]{}^$#&-~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
 
 impl Searcher for GenericSearcher<NixPackage> {
     type Item = NixPackage;
@@ -20,9 +68,25 @@ impl Searcher for GenericSearcher<NixPackage> {
 
         let attribute_name = inner.schema.get_field("attribute_name").unwrap();
         let description = inner.schema.get_field("description").unwrap();
+        let maintainer = inner.schema.get_field("maintainer").unwrap();
         let mut subqueries: Vec<(Occur, Box<dyn Query>)> = vec![];
 
-        for (i, word) in query_string.split(' ').enumerate() {
+        // `maintainer:handle` restricts the results to packages of that maintainer
+        let (filters, words): (Vec<&str>, Vec<&str>) = query_string
+            .split(' ')
+            .partition(|word| word.starts_with("maintainer:"));
+        for filter in filters {
+            let handle = filter.trim_start_matches("maintainer:").to_lowercase();
+            subqueries.push((
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(maintainer, &handle),
+                    tantivy::schema::IndexRecordOption::Basic,
+                )),
+            ));
+        }
+
+        for (i, word) in words.iter().copied().enumerate() {
             // words further back in the query get assigned less importance
             let length_loss = 1. - i as f32 / 10.;
 
@@ -43,11 +107,19 @@ impl Searcher for GenericSearcher<NixPackage> {
                 )),
             ));
 
-            // search for possible regex matches on the name field
-            if let Ok(regex_query) = RegexQuery::from_pattern(query_string, attribute_name) {
+            // search for names containing the word
+            let regex = if i < MAX_REGEX_WORDS {
+                contains_regex(word)
+            } else {
+                None
+            };
+            if let Some(regex) = regex {
                 subqueries.push((
                     Occur::Should,
-                    Box::new(BoostQuery::new(Box::new(regex_query), 1.2 * length_loss)),
+                    Box::new(BoostQuery::new(
+                        Box::new(RegexQuery::from_regex(regex, attribute_name)),
+                        1.2 * length_loss,
+                    )),
                 ));
             }
 
@@ -93,7 +165,7 @@ impl Searcher for GenericSearcher<NixPackage> {
         Box::new(BooleanQuery::new(subqueries))
     }
 
-    fn create_index(&mut self) -> anyhow::Result<()> {
+    fn create_index(&mut self) -> error::Result<()> {
         let mut schema_builder = Schema::builder();
 
         let raw_stored = TextOptions::default()
@@ -106,6 +178,15 @@ impl Searcher for GenericSearcher<NixPackage> {
 
         let attribute_name = schema_builder.add_text_field("attribute_name", raw_stored);
         schema_builder.add_text_field("description", TEXT);
+        schema_builder.add_text_field(
+            "maintainer",
+            TextOptions::default().set_indexing_options(
+                TextFieldIndexing::default()
+                    .set_index_option(tantivy::schema::IndexRecordOption::Basic)
+                    .set_tokenizer("raw"),
+            ),
+        );
+        schema_builder.add_u64_field(NAME_RANK_FIELD, FAST);
         let schema = schema_builder.build();
 
         let index = open_or_create_index(&self.index_path, &schema)?;
@@ -127,14 +208,18 @@ impl Searcher for GenericSearcher<NixPackage> {
         Ok(())
     }
 
-    fn update_entries(&mut self, entries: HashMap<String, Self::Item>) -> anyhow::Result<()> {
+    fn update_entries(&mut self, entries: HashMap<String, Self::Item>) -> error::Result<()> {
         let Some(ref inner) = self.inner else {
-            anyhow::bail!("can not update options before index creation");
+            return Err(FcSearchError::query(
+                "can not update options before index creation",
+            ));
         };
 
-        let index = &inner.index;
         let schema = &inner.schema;
-        let mut index_writer = index.writer(50_000_000)?;
+        let mut index_writer = inner
+            .index
+            .writer(50_000_000)
+            .query("could not create the index writer")?;
 
         let attribute_name = schema
             .get_field("attribute_name")
@@ -142,18 +227,41 @@ impl Searcher for GenericSearcher<NixPackage> {
         let description = schema
             .get_field("description")
             .expect("the field description should exist");
+        let maintainer = schema
+            .get_field("maintainer")
+            .expect("the field maintainer should exist");
+        let name_rank = schema
+            .get_field(NAME_RANK_FIELD)
+            .expect("the field name_rank should exist");
 
         index_writer
             .delete_all_documents()
             .expect("failed to delete all documents");
-        for (aname, package) in &entries {
+        let total = entries.len();
+        for (i, (aname, package)) in entries.iter().sorted_by_key(|(k, _)| *k).enumerate() {
+            if i % 1000 == 0 {
+                self.progress.report(Progress::Indexing {
+                    corpus: "packages".into(),
+                    indexed: i,
+                    total,
+                });
+            }
+
             let mut document = Document::default();
             document.add_text(attribute_name, aname.clone());
             document.add_text(description, package.description.clone().unwrap_or_default());
-            index_writer.add_document(document)?;
+            for m in &package.maintainers {
+                if let Some(ref github) = m.github {
+                    document.add_text(maintainer, github.to_lowercase());
+                }
+            }
+            document.add_u64(name_rank, i as u64);
+            index_writer
+                .add_document(document)
+                .query("could not add a document to the index")?;
         }
 
-        index_writer.commit()?;
+        index_writer.commit().query("could not commit the index")?;
         self.map = entries;
         Ok(())
     }
@@ -163,11 +271,42 @@ impl Searcher for GenericSearcher<NixPackage> {
             .and_offset((page.max(1) - 1) as usize * n_items as usize)
             .tweak_score(move |segment_reader: &SegmentReader| {
                 let store_reader = segment_reader.get_store_reader(10).unwrap();
+                let name_rank = name_ranks(segment_reader);
+                // shorter names win ties, then the alphabetically first one
                 move |doc: DocId, score: Score| {
                     let d = store_reader.get(doc).unwrap();
                     let name = d.field_values().first().unwrap().value.as_text().unwrap();
-                    (score, 1. / name.len() as f32)
+                    let rank = name_rank.first(doc).unwrap_or_default();
+                    (score, 1. / name.len() as f32, Reverse(rank))
                 }
             })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regex_characters_are_escaped() {
+        assert_eq!(escape_regex("hello"), "hello");
+        assert_eq!(escape_regex("gtk+3"), "gtk\\+3");
+        assert_eq!(escape_regex("c++"), "c\\+\\+");
+        assert_eq!(escape_regex("a.b-c"), "a\\.b\\-c");
+        assert_eq!(escape_regex("(x|y)"), "\\(x\\|y\\)");
+        assert_eq!(escape_regex("\\"), "\\\\");
+    }
+
+    #[test]
+    fn every_word_of_a_query_compiles() {
+        for word in ["gtk+3", "c++", "[", "(", "a{2}", "^$", "#&~"] {
+            assert!(contains_regex(word).is_some(), "{word} did not compile");
+        }
+    }
+
+    #[test]
+    fn empty_and_long_words_are_not_searched() {
+        assert!(contains_regex("").is_none());
+        assert!(contains_regex(&"a".repeat(MAX_REGEX_WORD_LENGTH + 1)).is_none());
+    }
+}
@@ -0,0 +1,412 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::{File, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::{Component, Path};
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::search::{read_flake_info, store_path, GENERATION_FILE};
+
+/// lock file that serializes writes to the channel data in the state dir
+const UPDATE_LOCK_FILE: &str = ".update.lock";
+
+/// lock file held by the process that owns the state dir
+const STATE_LOCK_FILE: &str = ".lock";
+
+/// tantivy index directories of a channel start with this
+const INDEX_DIR_PREFIX: &str = "tantivy";
+
+/// the generations of all channels, rewritten by the writer of the state dir after every update.
+/// readers on other hosts of a shared filesystem poll it, notifications do not reach them
+pub const GENERATIONS_FILE: &str = "generations.json";
+
+/// revisions kept per channel in the generations file, readers on other hosts may still
+/// serve the previous one until they noticed the new generation
+const KEPT_REVISIONS: usize = 2;
+
+fn is_lock_file(name: &OsStr) -> bool {
+    name == UPDATE_LOCK_FILE || name == STATE_LOCK_FILE
+}
+
+/// advisory lock marking a process as the only writer of a state dir.
+/// the lock is released when this value is dropped
+pub struct StateLock {
+    _file: File,
+}
+
+impl StateLock {
+    /// acquire the state dir lock, failing immediately if another process holds it
+    pub fn try_acquire(state_dir: &Path) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(state_dir).context("failed to create state dir")?;
+        let lock_path = state_dir.join(STATE_LOCK_FILE);
+        let mut file = File::options()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(&lock_path)
+            .context("could not open state dir lock file")?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                let _ = file.read_to_string(&mut holder);
+                anyhow::bail!(
+                    "state dir {} is already in use by another fc-search process (pid {}), refusing to start",
+                    state_dir.display(),
+                    holder.trim()
+                );
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(e).context("could not acquire state dir lock");
+            }
+        }
+
+        // record our pid to make the error message above more helpful
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+
+        debug!("acquired state dir lock {}", lock_path.display());
+        Ok(Self { _file: file })
+    }
+}
+
+/// advisory lock that is held while channel data in the state dir is written.
+/// the lock is released when this value is dropped
+pub struct UpdateLock {
+    _file: File,
+}
+
+impl UpdateLock {
+    /// blocks until the update lock of the state dir could be acquired
+    pub fn acquire(state_dir: &Path) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(state_dir).context("failed to create state dir")?;
+        let file = File::options()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(state_dir.join(UPDATE_LOCK_FILE))
+            .context("could not open update lock file")?;
+        file.lock().context("could not acquire update lock")?;
+        Ok(Self { _file: file })
+    }
+}
+
+/// the generation marker and the revisions in use of a channel
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ChannelGeneration {
+    /// the [`GENERATION_FILE`] of the channel dir
+    pub generation: String,
+    /// the store entries of the current and the previous revisions, newest first
+    #[serde(default)]
+    pub revisions: Vec<String>,
+}
+
+/// the generation marker of every completely indexed channel, see [`GENERATION_FILE`]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Generations {
+    pub channels: BTreeMap<String, ChannelGeneration>,
+}
+
+impl Generations {
+    /// the generations recorded by the writer, `None` if it has not written them yet
+    pub fn read(state_dir: &Path) -> Option<Self> {
+        std::fs::read_to_string(state_dir.join(GENERATIONS_FILE))
+            .ok()
+            .and_then(|c| serde_json::from_str(&c).ok())
+    }
+
+    /// read the generation markers and current revisions of the channel dirs themselves
+    pub fn scan(state_dir: &Path) -> std::io::Result<Self> {
+        let mut channels = BTreeMap::new();
+        for entry in std::fs::read_dir(state_dir)? {
+            let entry = entry?;
+            let Ok(generation) = std::fs::read_to_string(entry.path().join(GENERATION_FILE)) else {
+                continue;
+            };
+            let revisions = read_flake_info(&entry.path())
+                .and_then(|flake| {
+                    let path = store_path(state_dir, &flake);
+                    path.file_name().map(|f| f.to_string_lossy().to_string())
+                })
+                .into_iter()
+                .collect();
+            channels.insert(
+                entry.file_name().to_string_lossy().to_string(),
+                ChannelGeneration {
+                    generation: generation.trim().to_string(),
+                    revisions,
+                },
+            );
+        }
+        Ok(Self { channels })
+    }
+
+    /// the recorded generations, falling back to scanning the channel dirs
+    pub fn read_or_scan(state_dir: &Path) -> std::io::Result<Self> {
+        match Self::read(state_dir) {
+            Some(generations) => Ok(generations),
+            None => Self::scan(state_dir),
+        }
+    }
+
+    /// keep the newest revisions of `previous` after the current ones, up to [`KEPT_REVISIONS`]
+    fn keep_previous(&mut self, previous: &Self) {
+        for (branch, channel) in &mut self.channels {
+            let Some(previous) = previous.channels.get(branch) else {
+                continue;
+            };
+            for revision in &previous.revisions {
+                if channel.revisions.len() >= KEPT_REVISIONS {
+                    break;
+                }
+                if !channel.revisions.contains(revision) {
+                    channel.revisions.push(revision.clone());
+                }
+            }
+        }
+    }
+
+    /// the store entries of all kept revisions
+    pub fn revisions(&self) -> impl Iterator<Item = &str> {
+        self.channels
+            .values()
+            .flat_map(|channel| channel.revisions.iter().map(String::as_str))
+    }
+
+    /// replace the recorded generations, readers never see a partially written file
+    pub fn write(&self, state_dir: &Path) -> std::io::Result<()> {
+        let path = state_dir.join(GENERATIONS_FILE);
+        let tmp = path.with_extension("partial");
+        std::fs::write(&tmp, serde_json::to_vec(self)?)?;
+        std::fs::rename(tmp, path)
+    }
+}
+
+/// record the current generations of all channels for the readers of the state dir.
+/// the indices are complete without it, so failing to write it is only a warning
+pub fn write_generations(state_dir: &Path) {
+    let result = Generations::scan(state_dir).and_then(|mut generations| {
+        if let Some(previous) = Generations::read(state_dir) {
+            generations.keep_previous(&previous);
+        }
+        generations.write(state_dir)
+    });
+    if let Err(e) = result {
+        warn!("could not record the generations of the state dir: {e}");
+    }
+}
+
+/// write a zstd compressed tarball of the state dir to `output`.
+/// the update lock is held while archiving so the snapshot contains no partially written channels.
+/// without `indices` only the evaluated data is archived, the indices are rebuilt from it on restore
+pub fn snapshot(state_dir: &Path, output: &Path, indices: bool) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        state_dir.is_dir(),
+        "state dir {} does not exist",
+        state_dir.display()
+    );
+    // compare the resolved paths, `..` and symlinks may lead into the state dir
+    let output_dir = match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let output_dir = output_dir
+        .canonicalize()
+        .with_context(|| format!("the directory of {} does not exist", output.display()))?;
+    anyhow::ensure!(
+        !output_dir.starts_with(state_dir.canonicalize()?),
+        "the snapshot can not be written into the state dir itself"
+    );
+
+    let _lock = UpdateLock::acquire(state_dir)?;
+    info!(
+        "writing snapshot of {} to {}",
+        state_dir.display(),
+        output.display()
+    );
+
+    let file = File::create(output)
+        .with_context(|| format!("could not create snapshot file {}", output.display()))?;
+    let mut archive = tar::Builder::new(zstd::Encoder::new(file, 0)?);
+
+    for entry in std::fs::read_dir(state_dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if is_lock_file(&name) {
+            continue;
+        }
+
+        debug!("adding {:?} to snapshot", name);
+        append_entry(&mut archive, &entry.path(), Path::new(&name), indices)?;
+    }
+
+    archive
+        .into_inner()
+        .context("failed to finish snapshot archive")?
+        .finish()
+        .context("failed to finish snapshot compression")?;
+
+    info!("snapshot written to {}", output.display());
+    Ok(())
+}
+
+fn append_entry<W: Write>(
+    archive: &mut tar::Builder<W>,
+    path: &Path,
+    name: &Path,
+    indices: bool,
+) -> anyhow::Result<()> {
+    if !path.is_dir() {
+        archive.append_path_with_name(path, name)?;
+        return Ok(());
+    }
+    if indices {
+        archive.append_dir_all(name, path)?;
+        return Ok(());
+    }
+
+    archive.append_dir(name, path)?;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        if file_name.to_string_lossy().starts_with(INDEX_DIR_PREFIX) {
+            continue;
+        }
+        append_entry(archive, &entry.path(), &name.join(&file_name), indices)?;
+    }
+    Ok(())
+}
+
+/// unpack a snapshot created by [`snapshot`] into an empty state dir
+pub fn restore(input: &Path, state_dir: &Path) -> anyhow::Result<()> {
+    let _state_lock = StateLock::try_acquire(state_dir)?;
+    let _lock = UpdateLock::acquire(state_dir)?;
+
+    let occupied = std::fs::read_dir(state_dir)?
+        .filter_map(Result::ok)
+        .any(|entry| !is_lock_file(&entry.file_name()));
+    anyhow::ensure!(
+        !occupied,
+        "refusing to restore into the non-empty state dir {}",
+        state_dir.display()
+    );
+
+    info!(
+        "restoring snapshot {} into {}",
+        input.display(),
+        state_dir.display()
+    );
+    let file = File::open(input)
+        .with_context(|| format!("could not open snapshot file {}", input.display()))?;
+    let mut archive = tar::Archive::new(zstd::Decoder::new(file)?);
+    for entry in archive.entries().context("failed to read snapshot")? {
+        let mut entry = entry.context("failed to read snapshot")?;
+        let path = entry.path()?.into_owned();
+        // snapshots only contain the plain files and directories of a state dir
+        anyhow::ensure!(
+            path.components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+                && (entry.header().entry_type().is_file() || entry.header().entry_type().is_dir()),
+            "refusing to restore the snapshot entry {}",
+            path.display()
+        );
+        entry
+            .unpack_in(state_dir)
+            .with_context(|| format!("failed to unpack {}", path.display()))?;
+    }
+
+    info!("snapshot restored");
+    Ok(())
+}
+
+/// restore the snapshot at `url` if the state dir contains no channels yet,
+/// so a fresh instance is searchable without evaluating every channel first
+pub async fn seed(url: &Url, state_dir: &Path) -> anyhow::Result<()> {
+    if state_dir.exists() {
+        let occupied = std::fs::read_dir(state_dir)?
+            .filter_map(Result::ok)
+            .any(|entry| !is_lock_file(&entry.file_name()));
+        if occupied {
+            debug!("state dir is not empty, not seeding it");
+            return Ok(());
+        }
+    }
+
+    info!("seeding the state dir from {url}");
+    let mut response = reqwest::get(url.clone())
+        .await
+        .context("could not download the seed snapshot")?;
+    anyhow::ensure!(
+        response.status().is_success(),
+        "downloading the seed snapshot failed: {}",
+        response.status()
+    );
+
+    let mut file = tempfile::NamedTempFile::new().context("could not create a temporary file")?;
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk)?;
+    }
+    file.flush()?;
+
+    // waiting for the update lock blocks
+    let state_dir = state_dir.to_path_buf();
+    tokio::task::spawn_blocking(move || restore(file.path(), &state_dir)).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generations(channels: &[(&str, &str, &[&str])]) -> Generations {
+        Generations {
+            channels: channels
+                .iter()
+                .map(|(branch, generation, revisions)| {
+                    (
+                        branch.to_string(),
+                        ChannelGeneration {
+                            generation: generation.to_string(),
+                            revisions: revisions.iter().map(|r| r.to_string()).collect(),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn keeps_the_previous_revision() {
+        let previous = generations(&[("fc-24.11-production", "1", &["b", "a"])]);
+        let mut current = generations(&[("fc-24.11-production", "2", &["c"])]);
+        current.keep_previous(&previous);
+        assert_eq!(
+            current,
+            generations(&[("fc-24.11-production", "2", &["c", "b"])])
+        );
+    }
+
+    #[test]
+    fn unchanged_revisions_are_not_duplicated() {
+        let previous = generations(&[("fc-24.11-production", "1", &["b", "a"])]);
+        let mut current = generations(&[("fc-24.11-production", "2", &["b"])]);
+        current.keep_previous(&previous);
+        assert_eq!(
+            current,
+            generations(&[("fc-24.11-production", "2", &["b", "a"])])
+        );
+    }
+
+    #[test]
+    fn removed_channels_are_not_kept() {
+        let previous = generations(&[("fc-24.11-production", "1", &["a"])]);
+        let mut current = generations(&[]);
+        current.keep_previous(&previous);
+        assert!(current.channels.is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+use crate::error::FcSearchError;
+use crate::hydra::HydraStatus;
+use crate::nix::NixpkgsInput;
+use crate::progress::Progress;
+use crate::Flake;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::time::{Duration, SystemTime};
+
+/// bookkeeping about the updates of a channel, shared between the updater and the handlers
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ChannelStatus {
+    #[serde(with = "humantime_serde")]
+    pub last_attempt: Option<SystemTime>,
+    #[serde(with = "humantime_serde")]
+    pub last_success: Option<SystemTime>,
+    /// how long the last update attempt took
+    #[serde(with = "humantime_serde")]
+    pub last_duration: Option<Duration>,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    /// see [`FcSearchError::kind`]
+    pub last_error_kind: Option<Cow<'static, str>>,
+    /// set once the branch is no longer built on hydra
+    #[serde(with = "humantime_serde")]
+    pub retired_since: Option<SystemTime>,
+    /// the hydra evaluation of the indexed revision, refreshed on every update
+    pub hydra: Option<HydraStatus>,
+}
+
+impl ChannelStatus {
+    pub fn record_success(&mut self, started: SystemTime, duration: Duration) {
+        self.last_attempt = Some(started);
+        self.last_success = Some(started);
+        self.last_duration = Some(duration);
+        self.consecutive_failures = 0;
+        self.last_error = None;
+        self.last_error_kind = None;
+    }
+
+    pub fn record_failure(
+        &mut self,
+        started: SystemTime,
+        duration: Duration,
+        error: &FcSearchError,
+    ) {
+        self.last_attempt = Some(started);
+        self.last_duration = Some(duration);
+        self.consecutive_failures += 1;
+        self.last_error = Some(error.report());
+        self.last_error_kind = Some(error.kind().into());
+    }
+}
+
+/// what is indexed of a channel, shown in the footer of the search pages
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelInfo {
+    pub channel: String,
+    pub flake: Flake,
+    #[serde(with = "humantime_serde")]
+    pub indexed_at: Option<SystemTime>,
+    pub nixpkgs: Option<NixpkgsInput>,
+}
+
+/// a channel and its update status as shown on the status page
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusEntry {
+    pub flake: Flake,
+    pub active: bool,
+    pub retired: bool,
+    pub progress: Progress,
+    /// warnings of the evaluation of the indexed revision
+    pub warnings: Vec<String>,
+    /// other channels served from the same index because they are at the same revision
+    pub shared_with: Vec<String>,
+    #[serde(flatten)]
+    pub status: ChannelStatus,
+}
+
+impl StatusEntry {
+    pub fn updating(&self) -> bool {
+        self.progress != Progress::Idle
+    }
+
+    pub fn last_attempt_ago(&self) -> String {
+        format_ago(self.status.last_attempt)
+    }
+
+    pub fn last_success_ago(&self) -> String {
+        format_ago(self.status.last_success)
+    }
+
+    pub fn last_duration(&self) -> String {
+        self.status
+            .last_duration
+            .map(|d| humantime::format_duration(Duration::from_secs(d.as_secs())).to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// render how long ago `time` was, rounded to seconds
+pub fn format_ago(time: Option<SystemTime>) -> String {
+    match time.and_then(|t| t.elapsed().ok()) {
+        Some(d) => format!(
+            "{} ago",
+            humantime::format_duration(Duration::from_secs(d.as_secs()))
+        ),
+        None => "never".to_string(),
+    }
+}
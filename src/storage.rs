@@ -0,0 +1,391 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use s3::creds::Credentials;
+use s3::error::S3Error;
+use s3::{Bucket, Region};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::config::{S3Config, StorageBackend};
+use crate::search::{read_flake_info, store_path, COMPLETE_FILE, GENERATION_FILE};
+use crate::state::write_generations;
+use crate::Flake;
+
+/// prefix of the archives of the data and indices of every published revision
+const REVS_PREFIX: &str = "revs/";
+
+/// prefix of the manifests and archives of the channel dirs
+const CHANNELS_PREFIX: &str = "channels/";
+
+/// copy of the manifest a channel was pulled at, kept in its channel dir
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// a flat namespace of objects, like a bucket, selected in the config
+pub enum ObjectStorage {
+    /// objects as files below a directory, e.g. on a network share
+    Dir { root: PathBuf },
+    /// objects in an s3 compatible bucket
+    S3 {
+        bucket: Box<Bucket>,
+        /// prepended to every key, to share a bucket with other data
+        prefix: String,
+    },
+}
+
+impl ObjectStorage {
+    pub fn new(backend: &StorageBackend) -> anyhow::Result<Self> {
+        Ok(match backend {
+            StorageBackend::Dir { path } => Self::Dir { root: path.clone() },
+            StorageBackend::S3(config) => Self::S3 {
+                bucket: Box::new(s3_bucket(config)?),
+                prefix: config.prefix.clone(),
+            },
+        })
+    }
+
+    /// the content of the object, `None` if it does not exist
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match self {
+            Self::Dir { root } => match tokio::fs::read(root.join(key)).await {
+                Ok(content) => Ok(Some(content)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e).with_context(|| format!("could not read {key}")),
+            },
+            Self::S3 { bucket, prefix } => {
+                match bucket.get_object(format!("{prefix}{key}")).await {
+                    Ok(response) => Ok(Some(response.bytes().to_vec())),
+                    Err(e) if not_found(&e) => Ok(None),
+                    Err(e) => Err(e).with_context(|| format!("could not download {key}")),
+                }
+            }
+        }
+    }
+
+    async fn put(&self, key: &str, content: Vec<u8>) -> anyhow::Result<()> {
+        match self {
+            Self::Dir { root } => {
+                let (path, partial) = paths_for_write(root, key).await?;
+                tokio::fs::write(&partial, content)
+                    .await
+                    .with_context(|| format!("could not write {key}"))?;
+                tokio::fs::rename(&partial, &path)
+                    .await
+                    .with_context(|| format!("could not write {key}"))
+            }
+            Self::S3 { bucket, prefix } => {
+                bucket
+                    .put_object(format!("{prefix}{key}"), &content)
+                    .await
+                    .with_context(|| format!("could not upload {key}"))?;
+                Ok(())
+            }
+        }
+    }
+
+    /// download the object into `path`, `false` if it does not exist
+    async fn get_file(&self, key: &str, path: &Path) -> anyhow::Result<bool> {
+        match self {
+            Self::Dir { root } => match tokio::fs::copy(root.join(key), path).await {
+                Ok(_) => Ok(true),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+                Err(e) => Err(e).with_context(|| format!("could not read {key}")),
+            },
+            Self::S3 { bucket, prefix } => {
+                let mut file = tokio::fs::File::create(path)
+                    .await
+                    .with_context(|| format!("could not create {}", path.display()))?;
+                match bucket
+                    .get_object_to_writer(format!("{prefix}{key}"), &mut file)
+                    .await
+                {
+                    Ok(200..=299) => Ok(true),
+                    Ok(404) => Ok(false),
+                    Ok(status) => anyhow::bail!("could not download {key}: status {status}"),
+                    Err(e) if not_found(&e) => Ok(false),
+                    Err(e) => Err(e).with_context(|| format!("could not download {key}")),
+                }
+            }
+        }
+    }
+
+    /// upload the file at `path` without reading it into memory
+    async fn put_file(&self, key: &str, path: &Path) -> anyhow::Result<()> {
+        match self {
+            Self::Dir { root } => {
+                let (target, partial) = paths_for_write(root, key).await?;
+                tokio::fs::copy(path, &partial)
+                    .await
+                    .with_context(|| format!("could not write {key}"))?;
+                tokio::fs::rename(&partial, &target)
+                    .await
+                    .with_context(|| format!("could not write {key}"))
+            }
+            Self::S3 { bucket, prefix } => {
+                let mut file = tokio::fs::File::open(path)
+                    .await
+                    .with_context(|| format!("could not open {}", path.display()))?;
+                bucket
+                    .put_object_stream(&mut file, format!("{prefix}{key}"))
+                    .await
+                    .with_context(|| format!("could not upload {key}"))?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn exists(&self, key: &str) -> anyhow::Result<bool> {
+        match self {
+            Self::Dir { root } => Ok(tokio::fs::try_exists(root.join(key)).await?),
+            Self::S3 { bucket, prefix } => {
+                match bucket.head_object(format!("{prefix}{key}")).await {
+                    Ok(_) => Ok(true),
+                    Err(e) if not_found(&e) => Ok(false),
+                    Err(e) => Err(e).with_context(|| format!("could not look up {key}")),
+                }
+            }
+        }
+    }
+
+    /// keys of all objects starting with `prefix`
+    async fn list(&self, key_prefix: &str) -> anyhow::Result<Vec<String>> {
+        match self {
+            Self::Dir { root } => {
+                // keys only contain a single directory level, see the prefixes above
+                let (dir, name_prefix) = key_prefix.rsplit_once('/').unwrap_or(("", key_prefix));
+                let mut entries = match tokio::fs::read_dir(root.join(dir)).await {
+                    Ok(entries) => entries,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+                    Err(e) => {
+                        return Err(e).with_context(|| format!("could not list {key_prefix}"))
+                    }
+                };
+                let mut keys = Vec::new();
+                while let Some(entry) = entries.next_entry().await? {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    if name.starts_with(name_prefix) && !name.ends_with(PARTIAL_SUFFIX) {
+                        keys.push(if dir.is_empty() {
+                            name
+                        } else {
+                            format!("{dir}/{name}")
+                        });
+                    }
+                }
+                Ok(keys)
+            }
+            Self::S3 { bucket, prefix } => {
+                let pages = bucket
+                    .list(format!("{prefix}{key_prefix}"), None)
+                    .await
+                    .with_context(|| format!("could not list {key_prefix}"))?;
+                Ok(pages
+                    .into_iter()
+                    .flat_map(|page| page.contents)
+                    .filter_map(|object| object.key.strip_prefix(prefix).map(str::to_string))
+                    .collect())
+            }
+        }
+    }
+}
+
+/// appended to the file name of objects in a directory while they are written
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// the path of the object in the directory `root` and a temporary path next to it. objects are
+/// written to the temporary path first and then renamed, so readers never see a partial object
+async fn paths_for_write(root: &Path, key: &str) -> anyhow::Result<(PathBuf, PathBuf)> {
+    let path = root.join(key);
+    let parent = path.parent().context("invalid object key")?;
+    tokio::fs::create_dir_all(parent)
+        .await
+        .with_context(|| format!("could not create {}", parent.display()))?;
+    let mut partial = path.clone().into_os_string();
+    partial.push(PARTIAL_SUFFIX);
+    Ok((path, PathBuf::from(partial)))
+}
+
+fn s3_bucket(config: &S3Config) -> anyhow::Result<Bucket> {
+    let region = Region::Custom {
+        region: config.region.clone(),
+        endpoint: config
+            .endpoint
+            .to_string()
+            .trim_end_matches('/')
+            .to_string(),
+    };
+    // without keys in the config they are taken from the environment, like `AWS_ACCESS_KEY_ID`
+    let credentials = Credentials::new(
+        config.access_key.as_deref(),
+        config.secret_key.as_deref(),
+        None,
+        None,
+        None,
+    )
+    .context("could not find credentials for the bucket")?;
+    Ok(Bucket::new(&config.bucket, region, credentials)
+        .context("invalid bucket config")?
+        .with_path_style())
+}
+
+/// whether the request failed because the object does not exist
+fn not_found(error: &S3Error) -> bool {
+    matches!(error, S3Error::HttpFailWithBody(404, _))
+}
+
+/// what is published of a channel, written after its archives
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelManifest {
+    pub flake: Flake,
+    /// the generation marker of the published channel dir, see [`GENERATION_FILE`]
+    pub generation: String,
+}
+
+/// upload the indexed channel in the state dir unless this generation was already published.
+/// the data and indices of a revision are shared between channels and only uploaded once
+pub async fn publish(
+    storage: &ObjectStorage,
+    state_dir: &Path,
+    channel: &str,
+) -> anyhow::Result<()> {
+    let branch_path = state_dir.join(channel);
+    let flake = read_flake_info(&branch_path).context("the channel is not indexed")?;
+    let generation = tokio::fs::read_to_string(branch_path.join(GENERATION_FILE))
+        .await
+        .context("the channel is not completely indexed")?;
+    let manifest = ChannelManifest { flake, generation };
+    let manifest_content = serde_json::to_vec(&manifest)?;
+    let manifest_key = format!("{CHANNELS_PREFIX}{channel}.json");
+    if storage.get(&manifest_key).await?.as_ref() == Some(&manifest_content) {
+        debug!("{channel} is already published");
+        return Ok(());
+    }
+
+    let data_path = store_path(state_dir, &manifest.flake);
+    anyhow::ensure!(
+        data_path.join(COMPLETE_FILE).exists(),
+        "the indices of {channel} are incomplete"
+    );
+    let rev_key = rev_key(&data_path)?;
+    if !storage.exists(&rev_key).await? {
+        info!("publishing the data of {}", manifest.flake.rev);
+        let archive = archive(&data_path, &[]).await?;
+        storage.put_file(&rev_key, archive.path()).await?;
+    }
+
+    let archive = archive(&branch_path, &[GENERATION_FILE, MANIFEST_FILE]).await?;
+    storage
+        .put_file(
+            &format!("{CHANNELS_PREFIX}{channel}.tar.zst"),
+            archive.path(),
+        )
+        .await?;
+    storage.put(&manifest_key, manifest_content).await?;
+    info!("published {channel} at {}", manifest.flake.rev);
+    Ok(())
+}
+
+/// download the channels that were published since they were last pulled into the state dir.
+/// returns the channels that changed, they have to be reopened
+pub async fn pull(storage: &ObjectStorage, state_dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut pulled = Vec::new();
+    for key in storage.list(CHANNELS_PREFIX).await? {
+        let Some(channel) = key
+            .strip_prefix(CHANNELS_PREFIX)
+            .and_then(|name| name.strip_suffix(".json"))
+        else {
+            continue;
+        };
+        let Some(manifest) = storage.get(&key).await? else {
+            continue;
+        };
+        let branch_path = state_dir.join(channel);
+        let local = tokio::fs::read(branch_path.join(MANIFEST_FILE)).await.ok();
+        if local.as_deref() == Some(manifest.as_slice()) {
+            continue;
+        }
+        let parsed: ChannelManifest = serde_json::from_slice(&manifest)
+            .with_context(|| format!("invalid manifest of {channel}"))?;
+
+        let data_path = store_path(state_dir, &parsed.flake);
+        if !data_path.join(COMPLETE_FILE).exists() {
+            info!("pulling the data of {}", parsed.flake.rev);
+            let rev_key = rev_key(&data_path)?;
+            unpack_from(storage, &rev_key, &data_path).await?;
+        }
+        unpack_from(
+            storage,
+            &format!("{CHANNELS_PREFIX}{channel}.tar.zst"),
+            &branch_path,
+        )
+        .await?;
+
+        tokio::fs::write(branch_path.join(MANIFEST_FILE), &manifest).await?;
+        tokio::fs::write(branch_path.join(GENERATION_FILE), &parsed.generation).await?;
+        info!("pulled {channel} at {}", parsed.flake.rev);
+        pulled.push(channel.to_string());
+    }
+    if !pulled.is_empty() {
+        write_generations(state_dir);
+    }
+    Ok(pulled)
+}
+
+/// the key of the archive of a revision in the store
+fn rev_key(data_path: &Path) -> anyhow::Result<String> {
+    let name = data_path
+        .file_name()
+        .context("invalid store path")?
+        .to_string_lossy();
+    Ok(format!("{REVS_PREFIX}{name}.tar.zst"))
+}
+
+/// a zstd compressed tarball of the files in `dir`, except `excluded` ones
+async fn archive(dir: &Path, excluded: &[&str]) -> anyhow::Result<tempfile::NamedTempFile> {
+    let dir = dir.to_path_buf();
+    let excluded = excluded.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+    tokio::task::spawn_blocking(move || {
+        let file = tempfile::NamedTempFile::new().context("could not create a temporary file")?;
+        let mut archive = tar::Builder::new(zstd::Encoder::new(file.reopen()?, 0)?);
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if excluded.iter().any(|e| name == e.as_str()) {
+                continue;
+            }
+            if entry.path().is_dir() {
+                archive.append_dir_all(&name, entry.path())?;
+            } else {
+                archive.append_path_with_name(entry.path(), &name)?;
+            }
+        }
+        archive.into_inner()?.finish()?;
+        Ok(file)
+    })
+    .await?
+}
+
+/// download the archive at `key` and unpack it into `dir`. a new directory is only moved into
+/// place once it is completely unpacked, an existing one is updated in place
+async fn unpack_from(storage: &ObjectStorage, key: &str, dir: &Path) -> anyhow::Result<()> {
+    let file = tempfile::NamedTempFile::new().context("could not create a temporary file")?;
+    anyhow::ensure!(
+        storage.get_file(key, file.path()).await?,
+        "{key} was not published"
+    );
+
+    let dir = dir.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let parent = dir.parent().context("invalid target directory")?;
+        std::fs::create_dir_all(parent)?;
+        let mut archive = tar::Archive::new(zstd::Decoder::new(file.reopen()?)?);
+        if dir.exists() {
+            return archive.unpack(&dir).context("failed to unpack the archive");
+        }
+        let tmp = tempfile::tempdir_in(parent)?;
+        archive
+            .unpack(tmp.path())
+            .context("failed to unpack the archive")?;
+        std::fs::rename(tmp.into_path(), &dir).context("failed to move the unpacked archive")
+    })
+    .await?
+}
@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// a part of the option namespace, e.g. `services.nginx`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TreeNode {
+    /// last part of the path
+    pub name: String,
+    pub path: String,
+    /// number of options at and below the path
+    pub count: usize,
+    /// whether the path is an option itself
+    pub option: bool,
+    /// only filled up to the requested depth, nodes with a `count` above
+    /// the one of their own option have children to expand
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TreeNode>,
+}
+
+/// the namespace below `prefix` of the option `names`, `depth` levels deep
+pub fn option_tree<'a>(
+    names: impl Iterator<Item = &'a str>,
+    prefix: &str,
+    depth: usize,
+) -> TreeNode {
+    let rests = names
+        .filter_map(|name| {
+            if prefix.is_empty() {
+                Some(name)
+            } else if name == prefix {
+                Some("")
+            } else {
+                name.strip_prefix(prefix)?.strip_prefix('.')
+            }
+        })
+        .collect();
+    let name = prefix.rsplit('.').next().unwrap_or_default();
+    build(name, prefix.to_string(), rests, depth)
+}
+
+/// `rests` are the option names relative to `path`, empty for the option at `path` itself
+fn build(name: &str, path: String, rests: Vec<&str>, depth: usize) -> TreeNode {
+    let count = rests.len();
+    let option = rests.contains(&"");
+
+    let children = if depth == 0 {
+        Vec::new()
+    } else {
+        let mut groups: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for rest in rests.into_iter().filter(|r| !r.is_empty()) {
+            let (head, tail) = rest.split_once('.').unwrap_or((rest, ""));
+            groups.entry(head).or_default().push(tail);
+        }
+        groups
+            .into_iter()
+            .map(|(head, tails)| {
+                let child_path = if path.is_empty() {
+                    head.to_string()
+                } else {
+                    format!("{path}.{head}")
+                };
+                build(head, child_path, tails, depth - 1)
+            })
+            .collect()
+    };
+
+    TreeNode {
+        name: name.to_string(),
+        path,
+        count,
+        option,
+        children,
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+
+use reqwest::Client;
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::channel::Release;
+use crate::error::{self, ErrorClass};
+
+/// the nixos channels with the option and package lists search.nixos.org is built from
+pub const CHANNELS_BASE_URL: &str = "https://channels.nixos.org";
+
+pub const SEARCH_BASE_URL: &str = "https://search.nixos.org";
+
+/// buffer size of the brotli decompression
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// the options and packages of an upstream nixos release, only their names are kept
+#[derive(Debug)]
+pub struct UpstreamNames {
+    pub release: Release,
+    options: HashSet<String>,
+    packages: HashSet<String>,
+}
+
+/// layout of the `packages.json` of a channel
+#[derive(Deserialize)]
+struct Packages {
+    packages: HashMap<String, IgnoredAny>,
+}
+
+impl UpstreamNames {
+    pub fn has_option(&self, name: &str) -> bool {
+        self.options.contains(name)
+    }
+
+    pub fn has_package(&self, attribute_name: &str) -> bool {
+        self.packages.contains(attribute_name)
+    }
+
+    /// the option on search.nixos.org
+    pub fn option_url(&self, name: &str) -> String {
+        self.search_url("options", name)
+    }
+
+    /// the package on search.nixos.org
+    pub fn package_url(&self, attribute_name: &str) -> String {
+        self.search_url("packages", attribute_name)
+    }
+
+    fn search_url(&self, corpus: &str, name: &str) -> String {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("channel", &self.release.to_string())
+            .append_pair("show", name)
+            .append_pair("query", name)
+            .finish();
+        format!("{SEARCH_BASE_URL}/{corpus}?{query}")
+    }
+}
+
+/// fetch the option and package names of the nixos release `release`
+pub async fn fetch_upstream_names(release: Release) -> error::Result<UpstreamNames> {
+    let client = Client::builder()
+        .build()
+        .network("could not build request client")?;
+    let base_url = format!("{CHANNELS_BASE_URL}/nixos-{release}");
+
+    let options = fetch_brotli(&client, &format!("{base_url}/options.json.br")).await?;
+    let packages = fetch_brotli(&client, &format!("{base_url}/packages.json.br")).await?;
+
+    // the lists are large, so they are parsed off the async runtime
+    let names = tokio::task::spawn_blocking(move || -> error::Result<_> {
+        let options: HashMap<String, IgnoredAny> = parse_brotli(&options, "options")?;
+        let packages: Packages = parse_brotli(&packages, "packages")?;
+        Ok(UpstreamNames {
+            release,
+            options: options.into_keys().collect(),
+            packages: packages.packages.into_keys().collect(),
+        })
+    })
+    .await
+    .network("parsing the upstream lists failed")??;
+
+    info!(
+        "fetched {} options and {} packages of nixos {release}",
+        names.options.len(),
+        names.packages.len()
+    );
+    Ok(names)
+}
+
+async fn fetch_brotli(client: &Client, url: &str) -> error::Result<Vec<u8>> {
+    let response = client
+        .get(url)
+        .header("User-Agent", "fc-search")
+        .send()
+        .await
+        .network(format!("could not fetch {url}"))?;
+    let response = response
+        .error_for_status()
+        .network(format!("could not fetch {url}"))?;
+    let body = response
+        .bytes()
+        .await
+        .network(format!("could not read {url}"))?;
+    Ok(body.to_vec())
+}
+
+fn parse_brotli<T: DeserializeOwned>(compressed: &[u8], what: &str) -> error::Result<T> {
+    let mut json = Vec::new();
+    brotli::Decompressor::new(compressed, BUFFER_SIZE)
+        .read_to_end(&mut json)
+        .network(format!("could not decompress the upstream {what}"))?;
+    serde_json::from_slice(&json).network(format!("unexpected format of the upstream {what}"))
+}
@@ -0,0 +1,195 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use fc_search::channel::Channel;
+use fc_search::config::{Config, WebhookConfig, WebhookEvent, WebhookFormat};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{Client, Method};
+use serde::Serialize;
+use tracing::{debug, error, warn};
+
+/// timeout of a single webhook request
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// chat messages only contain the end of long errors, where nix prints the actual error
+const MESSAGE_ERROR_LENGTH: usize = 1500;
+
+/// makes the transaction ids of matrix messages sent in the same second unique
+static MATRIX_TRANSACTION: AtomicU64 = AtomicU64::new(0);
+
+/// the json body sent to the webhooks
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookPayload {
+    pub event: WebhookEvent,
+    pub channel: String,
+    /// the indexed revision after the event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_revision: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub consecutive_failures: u32,
+    /// unix time of the event
+    pub timestamp: u64,
+}
+
+impl WebhookPayload {
+    pub fn new(event: WebhookEvent, channel: &Channel) -> Self {
+        Self {
+            event,
+            channel: channel.to_string(),
+            revision: None,
+            previous_revision: None,
+            error: None,
+            consecutive_failures: 0,
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+
+    /// the event as chat message in markdown
+    fn message(&self) -> String {
+        let revision = self.revision.as_deref().unwrap_or("an unknown revision");
+        match self.event {
+            WebhookEvent::Indexed => format!("fc-search indexed `{}` at {revision}", self.channel),
+            WebhookEvent::RevisionChanged => format!(
+                "fc-search updated `{}` from {} to {revision}",
+                self.channel,
+                self.previous_revision
+                    .as_deref()
+                    .unwrap_or("an unknown revision")
+            ),
+            WebhookEvent::Failing => {
+                let mut message = format!(
+                    "fc-search failed to update `{}` {} times in a row, it is still served at {revision}",
+                    self.channel, self.consecutive_failures
+                );
+                if let Some(ref error) = self.error {
+                    message.push_str(&format!("\n```\n{}\n```", error_excerpt(error)));
+                }
+                message
+            }
+        }
+    }
+}
+
+/// the end of `error`, cut to [`MESSAGE_ERROR_LENGTH`] characters
+fn error_excerpt(error: &str) -> String {
+    let length = error.chars().count();
+    if length <= MESSAGE_ERROR_LENGTH {
+        return error.to_string();
+    }
+    let tail: String = error.chars().skip(length - MESSAGE_ERROR_LENGTH).collect();
+    format!("…{tail}")
+}
+
+/// a request to a single webhook
+struct Delivery {
+    method: Method,
+    url: String,
+    token: Option<String>,
+    body: serde_json::Value,
+}
+
+impl Delivery {
+    fn new(webhook: &WebhookConfig, payload: &WebhookPayload) -> serde_json::Result<Self> {
+        let (method, url, body) = match webhook.format {
+            WebhookFormat::Json => (
+                Method::POST,
+                webhook.url.to_string(),
+                serde_json::to_value(payload)?,
+            ),
+            WebhookFormat::Slack => (
+                Method::POST,
+                webhook.url.to_string(),
+                serde_json::json!({ "text": payload.message() }),
+            ),
+            WebhookFormat::Matrix => {
+                let room = webhook.room.as_deref().unwrap_or_default();
+                let transaction = format!(
+                    "fc-search-{}-{}",
+                    payload.timestamp,
+                    MATRIX_TRANSACTION.fetch_add(1, Ordering::Relaxed)
+                );
+                let url = format!(
+                    "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{transaction}",
+                    webhook.url.as_str().trim_end_matches('/'),
+                    url::form_urlencoded::byte_serialize(room.as_bytes()).collect::<String>()
+                );
+                let body = serde_json::json!({ "msgtype": "m.notice", "body": payload.message() });
+                (Method::PUT, url, body)
+            }
+        };
+        Ok(Self {
+            method,
+            url,
+            token: webhook.token.clone(),
+            body,
+        })
+    }
+}
+
+/// send the payload to all webhooks subscribed to its event and channel,
+/// in the background so a slow receiver does not hold up the updates
+pub fn notify(config: &Config, payload: WebhookPayload) {
+    let deliveries = config
+        .webhooks
+        .iter()
+        .filter(|webhook| webhook.wants(payload.event, &payload.channel))
+        .filter(|webhook| {
+            payload.event != WebhookEvent::Failing
+                || webhook.failing_after(payload.consecutive_failures, config.update.max_failures)
+        })
+        .filter_map(|webhook| match Delivery::new(webhook, &payload) {
+            Ok(delivery) => Some(delivery),
+            Err(e) => {
+                error!("could not serialize the webhook payload: {e}");
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    if deliveries.is_empty() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let client = match Client::builder().timeout(WEBHOOK_TIMEOUT).build() {
+            Ok(client) => client,
+            Err(e) => {
+                error!("could not build the webhook client: {e}");
+                return;
+            }
+        };
+        for delivery in deliveries {
+            let mut request = client
+                .request(delivery.method, &delivery.url)
+                .header(CONTENT_TYPE, "application/json")
+                .header("User-Agent", "fc-search")
+                .body(delivery.body.to_string());
+            if let Some(ref token) = delivery.token {
+                request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+            }
+            let result = request
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            // the url of matrix and slack webhooks contains secrets, so only the host is logged
+            let host = url::Url::parse(&delivery.url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_string))
+                .unwrap_or_default();
+            match result {
+                Ok(_) => debug!("sent {:?} of {} to {host}", payload.event, payload.channel),
+                Err(e) => warn!(
+                    "could not send {:?} of {} to {host}: {}",
+                    payload.event,
+                    payload.channel,
+                    e.without_url()
+                ),
+            }
+        }
+    });
+}